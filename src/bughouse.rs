@@ -0,0 +1,229 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use thiserror::Error;
+
+use crate::init::{Board, Move, Outcome, PieceType, SanError, Termination, BLACK, PAWN, WHITE};
+use crate::pgn::{Headers, TAG_REGEX};
+use crate::variant::BUGHOUSE;
+
+lazy_static! {
+    static ref BPGN_MOVETEXT_REGEX: Regex =
+        Regex::new(r"(\d+)([ABab])\.|\{([^}]*)\}|(1-0|0-1|1/2-1/2|\*)|([^\s{}]+)").unwrap();
+}
+
+/// Index of board A in `BughouseGame::boards`.
+pub const BOARD_A: usize = 0;
+/// Index of board B in `BughouseGame::boards`.
+pub const BOARD_B: usize = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BughouseMove {
+    /// `BOARD_A` or `BOARD_B`.
+    pub board: usize,
+    pub m: Move,
+    /// Seconds left on the clock of the player after the move, from a
+    /// `{118.4}` annotation.
+    pub clock: Option<f64>,
+}
+
+/// Two linked Crazyhouse boards. White on board A plays with black on
+/// board B and the other way round, and every captured piece goes to the
+/// partner's pocket. Outcomes name team A, white on board A, as `WHITE`.
+pub struct BughouseGame {
+    pub boards: [Board; 2],
+    pub headers: Headers,
+    pub moves: Vec<BughouseMove>,
+    /// Piece each move passed to the partner board, to take back on pop.
+    passed: Vec<Option<PieceType>>,
+}
+impl BughouseGame {
+    pub fn new() -> BughouseGame {
+        BughouseGame {
+            boards: [Board::from_variant(&BUGHOUSE), Board::from_variant(&BUGHOUSE)],
+            headers: Headers::new(None),
+            moves: Vec::new(),
+            passed: Vec::new(),
+        }
+    }
+    pub fn is_legal(&self, board: usize, m: Move) -> bool {
+        self.boards[board].is_legal(m)
+    }
+    /// Plays a pseudo-legal move on one board and passes a captured piece,
+    /// demoted if it was promoted, to the partner board.
+    pub fn push(&mut self, board: usize, m: Move) {
+        let color = self.boards[board].turn;
+        self.boards[board].push(m);
+        let state = self.boards[board].stack.last().unwrap();
+        let passed = state.captured().map(|piece_type| if state.captured_promoted() { PAWN } else { piece_type });
+        // The partner plays the color of the captured piece.
+        if let Some(piece_type) = passed {
+            self.boards[1 - board].add_to_pocket(!color, piece_type);
+        }
+        self.moves.push(BughouseMove { board, m, clock: None });
+        self.passed.push(passed);
+    }
+    pub fn push_san(&mut self, board: usize, san: &str) -> Result<Move, SanError> {
+        let m = self.boards[board].try_parse_san(san)?;
+        self.push(board, m);
+        Ok(m)
+    }
+    /// Takes back the last move of the game, whichever board it was on.
+    pub fn pop(&mut self) -> Option<BughouseMove> {
+        let bughouse_move = self.moves.pop()?;
+        let board = bughouse_move.board;
+        self.boards[board].pop();
+        if let Some(piece_type) = self.passed.pop().unwrap() {
+            let color = self.boards[board].turn;
+            self.boards[1 - board].remove_from_pocket(!color, piece_type);
+        }
+        Some(bughouse_move)
+    }
+    /// Whether the side to move on `board` is mated. A check that a drop
+    /// could block is not mate, since the partner may still pass a piece.
+    pub fn is_checkmate(&self, board: usize) -> bool {
+        let board = &self.boards[board];
+        board.is_checkmate() && board.legal_drop_squares_mask() == 0
+    }
+    /// The game ends with a mate on either board; a player without moves
+    /// just waits for a piece to drop. The winner is given as a team:
+    /// `WHITE` for white on board A and black on board B.
+    pub fn outcome(&self) -> Option<Outcome> {
+        [BOARD_A, BOARD_B].into_iter().find(|&board| self.is_checkmate(board)).map(|board| {
+            let team_a = if board == BOARD_A { WHITE } else { BLACK };
+            let winner = !self.boards[board].turn;
+            Outcome { termination: Termination::Checkmate, winner: Some(winner == team_a) }
+        })
+    }
+    /// Result from the boards, or from the `Result` header for games lost
+    /// on time or by resignation.
+    pub fn result(&self) -> String {
+        match self.outcome() {
+            Some(outcome) => outcome.result().to_string(),
+            None => self.headers.get("Result").unwrap_or("*").to_string(),
+        }
+    }
+    /// Parses a game in BPGN. Moves are numbered with their board, `1A.`
+    /// for white and `1a.` for black on board A, and may be followed by the
+    /// clock in seconds as `{118.4}`.
+    pub fn from_bpgn(bpgn: &str) -> Result<BughouseGame, BpgnError> {
+        let mut game = BughouseGame::new();
+        let mut movetext = String::new();
+        for line in bpgn.trim_start_matches('\u{feff}').lines() {
+            match TAG_REGEX.captures(line.trim()) {
+                Some(tag) if movetext.trim().is_empty() => game.headers.set(&tag[1], &tag[2]),
+                _ => {
+                    movetext.push_str(line);
+                    movetext.push('\n');
+                }
+            }
+        }
+
+        let mut board = None;
+        for captures in BPGN_MOVETEXT_REGEX.captures_iter(&movetext) {
+            if let Some(board_part) = captures.get(2) {
+                let board_char = board_part.as_str().chars().next().unwrap();
+                let index = if board_char.eq_ignore_ascii_case(&'A') { BOARD_A } else { BOARD_B };
+                if game.boards[index].turn != board_char.is_ascii_uppercase() {
+                    return Err(BpgnError::WrongTurn { token: captures[0].to_string() });
+                }
+                board = Some(index);
+            } else if let Some(comment) = captures.get(3) {
+                // Braces hold either a clock or a free comment.
+                if let (Ok(clock), Some(last)) = (comment.as_str().trim().parse::<f64>(), game.moves.last_mut()) {
+                    last.clock = Some(clock);
+                }
+            } else if let Some(result) = captures.get(4) {
+                if game.headers.get("Result").is_none() {
+                    game.headers.set("Result", result.as_str());
+                }
+            } else {
+                let token = &captures[5];
+                let index = board.take().ok_or_else(|| BpgnError::MissingBoard { token: token.to_string() })?;
+                game.push_san(index, token)
+                    .map_err(|source| BpgnError::InvalidMove { token: token.to_string(), source })?;
+            }
+        }
+        Ok(game)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum BpgnError {
+    #[error("move {token} is not preceded by a move number with a board")]
+    MissingBoard { token: String },
+    #[error("{token} does not match the side to move")]
+    WrongTurn { token: String },
+    #[error("invalid move {token}: {source}")]
+    InvalidMove { token: String, source: SanError },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game_with_board_a(fen: &str) -> BughouseGame {
+        let mut game = BughouseGame::new();
+        game.boards[BOARD_A] = Board::from_variant_fen(&BUGHOUSE, fen).unwrap();
+        game
+    }
+
+    #[test]
+    fn captures_go_to_the_partner_pocket() {
+        let mut game = BughouseGame::new();
+        for san in ["e4", "d5", "exd5"] {
+            game.push_san(BOARD_A, san).unwrap();
+        }
+        assert!(game.boards[BOARD_A].pockets[WHITE as usize].is_empty());
+        assert_eq!(game.boards[BOARD_B].pockets[BLACK as usize].count(PAWN), 1);
+
+        game.pop();
+        assert!(game.boards[BOARD_B].pockets[BLACK as usize].is_empty());
+        assert_eq!(game.moves.len(), 2);
+    }
+
+    #[test]
+    fn promoted_pieces_are_passed_as_pawns() {
+        let mut game = game_with_board_a("4k3/8/8/8/8/8/8/Rq~2K3[] w - - 0 1");
+        game.push_san(BOARD_A, "Rxb1").unwrap();
+        assert_eq!(game.boards[BOARD_B].pockets[BLACK as usize].fen(), "p");
+        game.pop();
+        assert!(game.boards[BOARD_B].pockets[BLACK as usize].is_empty());
+        assert_eq!(game.boards[BOARD_A].fen(false), "4k3/8/8/8/8/8/8/Rq~2K3[] w - - 0 1");
+    }
+
+    #[test]
+    fn checks_a_drop_could_block_are_not_mate() {
+        let game = game_with_board_a("k6R/8/1K6/8/8/8/8/8[] b - - 0 1");
+        assert!(game.boards[BOARD_A].is_check());
+        assert!(!game.is_checkmate(BOARD_A));
+        assert_eq!(game.outcome(), None);
+
+        let game = game_with_board_a("k7/1Q6/1K6/8/8/8/8/8[] b - - 0 1");
+        assert!(game.is_checkmate(BOARD_A));
+        assert_eq!(game.outcome(), Some(Outcome { termination: Termination::Checkmate, winner: Some(WHITE) }));
+        assert_eq!(game.result(), "1-0");
+    }
+
+    #[test]
+    fn from_bpgn() {
+        let bpgn = "[Event \"test\"]\n\n1A. e4 {119.2} 1B. d4 1a. d5 {118.0} 2A. exd5 *\n";
+        let game = BughouseGame::from_bpgn(bpgn).unwrap();
+        assert_eq!(game.headers.get("Event"), Some("test"));
+        assert_eq!(game.moves.len(), 4);
+        assert_eq!(game.moves[0].clock, Some(119.2));
+        assert_eq!(game.moves[1].clock, None);
+        assert_eq!(game.moves[3].board, BOARD_A);
+        assert_eq!(game.boards[BOARD_B].pockets[BLACK as usize].count(PAWN), 1);
+        assert_eq!(game.result(), "*");
+    }
+
+    #[test]
+    fn from_bpgn_errors() {
+        assert!(matches!(BughouseGame::from_bpgn("e4"), Err(BpgnError::MissingBoard { token }) if token == "e4"));
+        assert!(matches!(BughouseGame::from_bpgn("1a. e4"), Err(BpgnError::WrongTurn { token }) if token == "1a."));
+        assert!(matches!(
+            BughouseGame::from_bpgn("1A. e4 1B. e5"),
+            Err(BpgnError::InvalidMove { token, source: SanError::IllegalSan { .. } }) if token == "e5"
+        ));
+    }
+}
@@ -1,8 +1,6 @@
-use std::{io::{Write, Read}, process::{Child, ChildStdin, ChildStdout, Command, Stdio}, cell::RefCell};
+use std::{io::{Write, Read}, process::{Child, Command, Stdio}, cell::RefCell};
 
-use requests::Result;
-
-use crate::init::Move;
+use crate::init::{Board, Move, MoveError};
 
 pub struct Engine {
     process: RefCell<Child>,
@@ -26,7 +24,7 @@ impl Engine {
         let mut retval = String::new();
         let mut buffer: [u8;1] = [0];
 
-        while buffer[0] != '\n' as u8 {
+        while buffer[0] != b'\n' {
             let res = self.process.borrow_mut().stdout.as_mut().unwrap().read(&mut buffer);
             if res.is_err() {
                 return None;
@@ -63,10 +61,51 @@ impl Engine {
     pub fn set_option(&self, name: &str, value: &str) {
         self.write(&format!("setoption name {} value {}", name, value));
     }
-    pub fn get_best_move(&mut self, time_ms: usize) -> Move {
+    /// Best move of the engine in `board`, the position last sent with
+    /// `set_position`. A mated or stalemated engine answers
+    /// `bestmove (none)` or `bestmove 0000`, which gives `Ok(None)`.
+    pub fn get_best_move(&mut self, board: &Board, time_ms: usize) -> Result<Option<Move>, MoveError> {
         self.write(&format!("go movetime {}", time_ms));
         let line =  self.read_line_starts_with("bestmove");
-        let best_move = line.split(" ").nth(1).unwrap().to_owned();
-        return crate::init::Move::from_uci(&best_move);
-    } 
+        match line.split_whitespace().nth(1) {
+            None | Some("(none)") | Some("0000") => Ok(None),
+            Some(best_move) => board.parse_uci(best_move).map(Some),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use crate::init::{MoveError, STARTING_FEN};
+
+    /// An engine that answers every `go` with the given `bestmove` line.
+    fn fake_engine(name: &str, best_move: &str) -> Engine {
+        let path = std::env::temp_dir().join(format!("fake_engine_{}_{}", std::process::id(), name));
+        std::fs::write(&path, format!("#!/bin/sh\nwhile read line; do\n  case \"$line\" in go*) echo \"bestmove {}\";; esac\ndone\n", best_move)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        Engine::new(path.to_str().unwrap())
+    }
+
+    // One test, so that no other thread forks while a script is written.
+    #[test]
+    fn best_move_is_checked_against_the_board() {
+        let board = Board::new(Some(STARTING_FEN));
+        let mut engine = fake_engine("legal", "e2e4 ponder e7e5");
+        assert_eq!(engine.get_best_move(&board, 10), Ok(Some(Move::from_uci("e2e4"))));
+
+        let mut engine = fake_engine("illegal", "e2e5");
+        assert!(matches!(engine.get_best_move(&board, 10), Err(MoveError::IllegalMove { .. })));
+
+        let mut engine = fake_engine("invalid", "xyz");
+        assert!(matches!(engine.get_best_move(&board, 10), Err(MoveError::InvalidUci(_))));
+
+        // A mated engine has no move to give.
+        let board = Board::from_fen("7k/6Q1/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        for (name, reply) in [("none", "(none)"), ("null", "0000")] {
+            let mut engine = fake_engine(name, reply);
+            assert_eq!(engine.get_best_move(&board, 10), Ok(None));
+        }
+    }
 }
\ No newline at end of file
@@ -1,10 +1,11 @@
 
-use counter::Counter;
 use regex::Regex;
 use lazy_static::lazy_static;
-use core::panic;
-use std::{cmp::max, collections::{VecDeque}, fmt, fmt::Formatter, hash::Hash, intrinsics::{log2f64}, ops};
+use std::{cmp::{max, min}, collections::{VecDeque}, fmt, fmt::Formatter, hash::Hash, ops};
 use ahash::AHashMap;
+use thiserror::Error;
+
+use crate::variant::{CrazyhousePocket, Variant, STANDARD};
 
 pub const FILE_NAMES: [char; 8] = ['a', 'b', 'c', 'd', 'e', 'f', 'g', 'h'];
 pub const RANK_NAMES: [char; 8] = ['1', '2', '3', '4', '5', '6', '7', '8'];
@@ -17,7 +18,7 @@ pub const WHITE: bool = true;
 pub const BLACK: bool = false;
 pub const COLORS: [bool; 2] = [WHITE, BLACK];
 
-type PieceType = u8;
+pub type PieceType = u8;
 pub const PAWN: u8 = 1;
 pub const KNIGHT: u8 = 2;
 pub const BISHOP: u8 = 3;
@@ -25,6 +26,9 @@ pub const ROOK: u8 = 4;
 pub const QUEEN: u8 = 5;
 pub const KING: u8 = 6;
 pub const PIECE_TYPES: [u8; 6] = [PAWN, KNIGHT, BISHOP, ROOK, QUEEN, KING];
+/// Piece values in centipawns used by static exchange evaluation, indexed
+/// by piece type. The king is never captured in a legal exchange.
+pub const SEE_PIECE_VALUES: [i32; 7] = [0, 100, 300, 300, 500, 900, 0];
 pub const PIECE_SYMBOLS: [Option<char>; 7] = [
     None,
     Some('p'),
@@ -36,7 +40,7 @@ pub const PIECE_SYMBOLS: [Option<char>; 7] = [
 ];
 lazy_static! {
     pub static ref PIECE_NAMES: [Option<&'static str>; 7] = {
-        let names = [
+        [
             None,
             Some("pawn"),
             Some("knight"),
@@ -44,11 +48,10 @@ lazy_static! {
             Some("rook"),
             Some("queen"),
             Some("king"),
-        ];
-        names
+        ]
     };
 }
-fn piece_symbol(piece_type: PieceType) -> Option<char> {
+pub fn piece_symbol(piece_type: PieceType) -> Option<char> {
     PIECE_SYMBOLS[piece_type as usize]
 }
 fn piece_name(piece_name: PieceType) -> Option<&'static str> {
@@ -60,7 +63,7 @@ fn parse_file_name(c: char) -> u8 {
 fn parse_rank_name(c: char) -> u8 {
     c as u8 - 0x31u8
 }
-fn piece_type(piece_symbol: Option<char>) -> Option<u8> {
+pub fn piece_type(piece_symbol: Option<char>) -> Option<u8> {
     match piece_symbol {
         Some('p') => Some(PAWN),
         Some('n') => Some(KNIGHT),
@@ -75,6 +78,7 @@ pub trait Boolean {
     fn bool(&self) -> bool;
 }
 #[derive(PartialEq)]
+#[allow(clippy::upper_case_acronyms, clippy::enum_variant_names)]
 pub enum Status {
     VALID = 0,
     NoWhiteKing = 1 << 0,
@@ -205,7 +209,7 @@ pub const STATUS_RACE_MATERIAL: u32 = Status::RaceMaterial as u32;
 pub const STATUS_TOO_MANY_CHECKERS: u32 = Status::TooManyCheckers as u32;
 pub const STATUS_IMPOSSIBLE_CHECK: u32 = Status::ImpossibleCheck as u32;
 
-type Square = u8;
+pub type Square = u8;
 
 pub fn unicode_piece_symbols(p: char) -> char {
     match p {
@@ -237,8 +241,7 @@ lazy_static! {
         for i in (0..8).rev() {
             vec.extend_from_slice(&SQUARES[i * 8..i * 8 + 8])
         }
-        let squares = vec.try_into().expect("Wrong size");
-        squares
+        vec.try_into().expect("Wrong size")
     };
 }
 pub const SQUARE_NAMES: [&str; 64] = [
@@ -273,9 +276,9 @@ lazy_static! {
 }
 #[inline]
 pub fn parse_square(name: &str) -> Square {
-    let file: u8 = name.chars().nth(0).unwrap() as u8 - 'a' as u8;
-    let rank: u8 = name.chars().nth(1).unwrap() as u8 - '1' as u8;
-    return rank * 8 + file
+    let file: u8 = name.chars().next().unwrap() as u8 - b'a';
+    let rank: u8 = name.chars().nth(1).unwrap() as u8 - b'1';
+    rank * 8 + file
     // SQUARE_LOOKUP[(((name.as_bytes()[0] as u64) << 7) + name.as_bytes()[1] as u64) as usize]
     // SQUARE_HASH[name]
     // println!("file: {}, rank: {}, prev: {}, new: {}", file, rank, (name.as_bytes()[0] - 1) & 0xF, ((name.as_bytes()[1] - 1) & 0xF) * 8);
@@ -300,14 +303,15 @@ pub fn square_rank(square: Square) -> Square {
 }
 pub fn square_distance(a: Square, b: Square) -> u8 {
     max(
-        (square_file(a) as i8 - square_file(b) as i8).abs() as u8,
-        (square_rank(a) as i8 - square_rank(b) as i8).abs() as u8,
+        (square_file(a) as i8 - square_file(b) as i8).unsigned_abs(),
+        (square_rank(a) as i8 - square_rank(b) as i8).unsigned_abs(),
     )
 }
 pub fn square_mirror(square: Square) -> Square {
     square ^ 0x38
 }
-enum Termination {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Termination {
     Checkmate,
     Stalemate,
     InsufficientMaterial,
@@ -319,28 +323,31 @@ enum Termination {
     VariantLoss,
     VariantDraw,
 }
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Outcome {
-    termination: Termination,
-    winner: Option<Color>
+    pub termination: Termination,
+    pub winner: Option<Color>
 }
 impl Outcome {
-    fn result(&self) -> &str {
-        if self.winner == None {"1/2-1/2"} else {if self.winner.unwrap() == WHITE {"1-0"} else {"0-1"}}
+    pub fn result(&self) -> &str {
+        match self.winner {
+            None => "1/2-1/2",
+            Some(winner) => if winner == WHITE {"1-0"} else {"0-1"},
+        }
     }
 }
 
-type Bitboard = u64;
+pub type Bitboard = u64;
 pub const BB_EMPTY: u64 = 0;
 pub const BB_ALL: u64 = 0xffff_ffff_ffff_ffff;
 
 lazy_static! {
     pub static ref BB_SQUARES: [u64; 64] = {
-        let bb = (0..64)
+        (0..64)
             .map(|x| 1 << x)
             .collect::<Vec<u64>>()
             .try_into()
-            .expect("wrong sizef");
-        bb
+            .expect("wrong sizef")
     };
 }
 pub const BB_A1: u64 = 1 << 0;
@@ -416,23 +423,21 @@ pub const BB_DARK_SQUARES: u64 = 0xaa55_aa55_aa55_aa55;
 
 lazy_static! {
     pub static ref BB_FILES: [u64; 8] = {
-        let bb_files = (0..8)
+        (0..8)
             .map(|x| 0x0101_0101_0101_0101 << x)
             .collect::<Vec<u64>>()
             .try_into()
-            .expect("wrong sized");
-        bb_files
+            .expect("wrong sized")
     };
     pub static ref BB_RANKS: [u64; 8] = {
-        let bb_ranks = (0..8)
+        (0..8)
             .map(|x| 0xff << (8 * x))
             .collect::<Vec<u64>>()
             .try_into()
-            .expect("wrong sizee");
-        bb_ranks
+            .expect("wrong sizee")
     };
 }
-pub const BB_FILE_A: u64 = 0x0101_0101_0101_0101 << 0;
+pub const BB_FILE_A: u64 = 0x0101_0101_0101_0101;
 pub const BB_FILE_B: u64 = 0x0101_0101_0101_0101 << 1;
 pub const BB_FILE_C: u64 = 0x0101_0101_0101_0101 << 2;
 pub const BB_FILE_D: u64 = 0x0101_0101_0101_0101 << 3;
@@ -441,8 +446,8 @@ pub const BB_FILE_F: u64 = 0x0101_0101_0101_0101 << 5;
 pub const BB_FILE_G: u64 = 0x0101_0101_0101_0101 << 6;
 pub const BB_FILE_H: u64 = 0x0101_0101_0101_0101 << 7;
 
-pub const BB_RANK_1: u64 = 0xff << (8 * 0);
-pub const BB_RANK_2: u64 = 0xff << (8 * 1);
+pub const BB_RANK_1: u64 = 0xff;
+pub const BB_RANK_2: u64 = 0xff << 8;
 pub const BB_RANK_3: u64 = 0xff << (8 * 2);
 pub const BB_RANK_4: u64 = 0xff << (8 * 3);
 pub const BB_RANK_5: u64 = 0xff << (8 * 4);
@@ -452,10 +457,7 @@ pub const BB_RANK_8: u64 = 0xff << (8 * 7);
 
 pub const BB_BACKRANKS: u64 = BB_RANK_1 | BB_RANK_8;
 pub fn bit_length(x: u64) -> u64 {
-    if x == 0 {
-        return 0;
-    }
-    unsafe { 1 + log2f64(x as f64) as u64 }
+    (64 - x.leading_zeros()) as u64
 }
 pub fn lsb(bb: Bitboard) -> u8 {
     (bit_length(bb & bb.wrapping_neg()) - 1) as u8
@@ -487,75 +489,75 @@ pub fn scan_reversed(mut bb: Bitboard) -> impl Iterator<Item = Square> {
     })
 }
 
-fn popcount(bb: Bitboard) -> u32 {
+pub fn popcount(bb: Bitboard) -> u32 {
     bb.count_ones()
 }
 pub fn flip_vertical(mut bb: Bitboard) -> Bitboard {
     bb = ((bb >> 8) & 0x00ff_00ff_00ff_00ff) | ((bb & 0x00ff_00ff_00ff_00ff) << 8);
     bb = ((bb >> 16) & 0x0000_ffff_0000_ffff) | ((bb & 0x0000_ffff_0000_ffff) << 16);
     bb = (bb >> 32) | ((bb & 0x0000_0000_ffff_ffff) << 32);
-    return bb;
+    bb
 }
 pub fn flip_horizontal(mut bb: Bitboard) -> Bitboard {
     bb = ((bb >> 1) & 0x5555_5555_5555_5555) | ((bb & 0x5555_5555_5555_5555) << 1);
     bb = ((bb >> 2) & 0x3333_3333_3333_3333) | ((bb & 0x3333_3333_3333_3333) << 2);
     bb = ((bb >> 4) & 0x0f0f_0f0f_0f0f_0f0f) | ((bb & 0x0f0f_0f0f_0f0f_0f0f) << 4);
-    return bb;
+    bb
 }
 pub fn flip_diagonal(mut bb: Bitboard) -> Bitboard {
     let mut t = (bb ^ (bb << 28)) & 0x0f0f_0f0f_0000_0000;
-    bb = bb ^ (t ^ (t >> 28));
+    bb ^= t ^ (t >> 28);
     t = (bb ^ (bb << 14)) & 0x3333_0000_3333_0000;
-    bb = bb ^ (t ^ (t >> 14));
+    bb ^= t ^ (t >> 14);
     t = (bb ^ (bb << 7)) & 0x5500_5500_5500_5500;
-    bb = bb ^ (t ^ (t >> 7));
-    return bb;
+    bb ^= t ^ (t >> 7);
+    bb
 }
 pub fn flip_anti_diagonal(mut bb: Bitboard) -> Bitboard {
     let mut t = bb ^ (bb << 36);
     bb = bb ^ ((t ^ (bb >> 36)) & 0xf0f0_f0f0_0f0f_0f0f);
     t = (bb ^ (bb << 18)) & 0xcccc_0000_cccc_0000;
-    bb = bb ^ (t ^ (t >> 18));
+    bb ^= t ^ (t >> 18);
     t = (bb ^ (bb << 9)) & 0xaa00_aa00_aa00_aa00;
-    bb = bb ^ (t ^ (t >> 9));
-    return bb;
+    bb ^= t ^ (t >> 9);
+    bb
 }
 
 pub fn shift_down(b: Bitboard) -> Bitboard {
-    return b >> 8;
+    b >> 8
 }
 pub fn shift_2_down(b: Bitboard) -> Bitboard {
-    return b >> 16;
+    b >> 16
 }
 pub fn shift_up(b: Bitboard) -> Bitboard {
-    return (b << 8) & BB_ALL;
+    (b << 8) & BB_ALL
 }
 pub fn shift_2_up(b: Bitboard) -> Bitboard {
-    return (b << 16) & BB_ALL;
+    (b << 16) & BB_ALL
 }
 pub fn shift_right(b: Bitboard) -> Bitboard {
-    return (b << 1) & !BB_FILE_A & BB_ALL;
+    (b << 1) & !BB_FILE_A & BB_ALL
 }
 pub fn shift_2_right(b: Bitboard) -> Bitboard {
-    return (b << 2) & !BB_FILE_A & !BB_FILE_B & BB_ALL;
+    (b << 2) & !BB_FILE_A & !BB_FILE_B & BB_ALL
 }
 pub fn shift_left(b: Bitboard) -> Bitboard {
-    return (b >> 1) & !BB_FILE_H;
+    (b >> 1) & !BB_FILE_H
 }
 pub fn shift_2_left(b: Bitboard) -> Bitboard {
-    return (b >> 2) & !BB_FILE_G & !BB_FILE_H;
+    (b >> 2) & !BB_FILE_G & !BB_FILE_H
 }
 pub fn shift_up_left(b: Bitboard) -> Bitboard {
-    return (b << 7) & !BB_FILE_H & BB_ALL;
+    (b << 7) & !BB_FILE_H & BB_ALL
 }
 pub fn shift_up_right(b: Bitboard) -> Bitboard {
-    return (b << 9) & !BB_FILE_A & BB_ALL;
+    (b << 9) & !BB_FILE_A & BB_ALL
 }
 pub fn shift_down_left(b: Bitboard) -> Bitboard {
-    return (b >> 9) & !BB_FILE_H;
+    (b >> 9) & !BB_FILE_H
 }
 pub fn shift_down_right(b: Bitboard) -> Bitboard {
-    return (b >> 7) & !BB_FILE_A;
+    (b >> 7) & !BB_FILE_A
 }
 pub fn any<T, B>(iter: T) -> bool where T: IntoIterator<Item = B>, B: Boolean {
     for i in iter {
@@ -594,14 +596,14 @@ where
             }
         }
     }
-    return attacks;
+    attacks
 }
 
 fn step_attacks<'a, I>(square: Square, deltas: I) -> Bitboard
 where
     I: Iterator<Item = &'a i8>,
 {
-    return sliding_attacks(square, BB_ALL, deltas);
+    sliding_attacks(square, BB_ALL, deltas)
 }
 
 lazy_static! {
@@ -635,8 +637,8 @@ lazy_static! {
     };
 }
 fn edges(square: Square) -> Bitboard {
-    return (BB_RANK_1 | BB_RANK_8) & !BB_RANKS[square_rank(square) as usize]
-        | (BB_FILE_A | BB_FILE_H) & !BB_FILES[square_file(square) as usize];
+    (BB_RANK_1 | BB_RANK_8) & !BB_RANKS[square_rank(square) as usize]
+        | (BB_FILE_A | BB_FILE_H) & !BB_FILES[square_file(square) as usize]
 }
 fn carry_rippler(mask: Bitboard) -> impl Iterator<Item = u64> {
     gen_iter!({
@@ -675,65 +677,119 @@ lazy_static! {
             .expect("wrong size");
         table
     };
-    pub static ref BB_DIAG_ATTACKS: [AHashMap<Bitboard, Bitboard>; 64] = {
-        let mut table = Vec::new();
-        for sq in 0..64 {
-            let mut attacks = AHashMap::new();
-            let mask = BB_DIAG_MASKS[sq];
-            for subset in carry_rippler(mask) {
-                attacks.insert(
-                    subset,
-                    sliding_attacks(sq as Square, subset, [-9, -7, 7, 9].iter()),
-                );
-            }
-            table.push(attacks);
-        }
-        table.try_into().expect("wrong size")
-    };
-    pub static ref BB_FILE_ATTACKS: [AHashMap<Bitboard, Bitboard>; 64] = {
-        let mut table = Vec::new();
-
-        for sq in 0..64 {
-            let mut attacks = AHashMap::new();
-            let mask = BB_FILE_MASKS[sq];
-            for subset in carry_rippler(mask) {
-                attacks.insert(
-                    subset,
-                    sliding_attacks(sq as Square, subset, [-8, 8].iter()),
-                );
-            }
-            table.push(attacks);
-        }
-        table.try_into().expect("wrong size")
-    };
-    pub static ref BB_RANK_ATTACKS: [AHashMap<Bitboard, Bitboard>; 64] = {
-        let mut table = Vec::new();
-
-        for sq in 0..64 {
-            let mut attacks = AHashMap::new();
-            let mask = BB_RANK_MASKS[sq];
+    pub static ref BB_DIAG_ATTACKS: [MagicAttacks; 64] = magic_attacks(&BB_DIAG_MASKS, &[-9, -7, 7, 9], &DIAG_MAGICS);
+    pub static ref BB_FILE_ATTACKS: [MagicAttacks; 64] = magic_attacks(&BB_FILE_MASKS, &[-8, 8], &FILE_MAGICS);
+    pub static ref BB_RANK_ATTACKS: [MagicAttacks; 64] = magic_attacks(&BB_RANK_MASKS, &[-1, 1], &RANK_MAGICS);
+}
+// Magics found once by trying sparse xorshift candidates for each square
+// until every occupancy subset of the mask maps to a slot without
+// conflicting attacks, so building the tables is a single pass.
+const DIAG_MAGICS: [u64; 64] = [
+    0x0020_4284_0040_8200, 0x2008_0101_0421_0004, 0x0010_2902_0068_8800, 0x020c_0c02_8020_0004,
+    0x4024_0420_4000_1202, 0x0002_0802_8900_1020, 0x0000_8210_0222_0080, 0x0201_0041_1018_2224,
+    0x0080_4028_0549_0200, 0x3001_4882_3084_0100, 0x4024_1004_0581_2020, 0x0000_0404_0080_4088,
+    0x0540_0404_2110_0250, 0x0405_0510_0805_3010, 0x0180_0904_0120_1810, 0x0204_2122_8404_2000,
+    0x2405_0420_0890_0300, 0x0008_2202_0808_0080, 0x0482_0050_0820_4100, 0x8008_0500_8200_4042,
+    0x0801_0088_2008_4082, 0x0003_0004_8041_4000, 0x0080_4201_0848_0400, 0x2820_2906_0202_0210,
+    0x0020_0800_3408_0848, 0xa0e1_1000_2404_2803, 0x2804_0206_4040_8108, 0x48c8_0800_0082_0500,
+    0x0080_8480_0400_2000, 0x0801_0040_1200_5044, 0x0000_8090_2c04_0400, 0x0004_0090_0500_4100,
+    0x0081_2808_3041_1010, 0x0001_0420_0403_0801, 0x0800_1402_0010_0080, 0x8401_0108_0091_0040,
+    0x0840_0100_1129_0040, 0x4010_0214_202e_1000, 0x0842_0400_4001_0840, 0x0208_0182_8010_2200,
+    0x8218_2442_2800_2008, 0x0034_1084_0c10_1002, 0x0021_1201_1000_0d02, 0x48c1_0042_0800_0084,
+    0x0010_0881_0041_4400, 0x0021_1010_0042_0580, 0x0010_0405_5840_1410, 0x0010_0502_0080_0060,
+    0x0011_1088_2008_8000, 0x4801_0100_9024_0008, 0x8820_0ca3_0808_0080, 0x0000_1100_8404_2802,
+    0x0200_8110_0602_0240, 0x0102_6084_0112_0200, 0x2408_0244_0c04_0221, 0x0810_1022_0086_2120,
+    0x0004_1406_0304_2008, 0x4000_4049_0818_0288, 0x2001_0002_8048_0812, 0x0004_2001_0020_880c,
+    0x0000_0000_2014_2400, 0x0020_4008_0308_0a00, 0x0844_5122_0801_1400, 0x0048_2201_8802_0080,
+];
+const FILE_MAGICS: [u64; 64] = [
+    0x0020_0412_8041_0800, 0x0018_0c02_4020_10a0, 0x0024_08b0_2002_0100, 0x1250_8801_2450_0200,
+    0x8a08_4084_0108_0a11, 0x8109_0080_4024_0200, 0x8028_4082_0211_0020, 0x4000_4500_1108_8020,
+    0x8100_0880_1024_4010, 0x2000_c430_22a8_4000, 0x4000_1807_0410_2011, 0x0400_8104_0802_5000,
+    0x0208_0802_0904_20a0, 0x0000_0208_8102_4400, 0x2011_5902_0080_4024, 0x2000_0300_9220_4080,
+    0x1020_0410_0840_8002, 0x0084_0418_4070_2108, 0x0048_0030_040b_2001, 0x9150_0508_0104_0280,
+    0x0020_8048_340a_0100, 0x0002_0208_4104_0080, 0x0046_1400_4221_0880, 0x0000_a401_0032_4484,
+    0x0828_1110_8060_4500, 0x12c0_1448_9019_2102, 0x0823_1008_0820_0400, 0x2048_4200_010c_1000,
+    0x1021_280c_8400_8240, 0x0120_4408_5114_8202, 0x0192_0020_6091_4500, 0x0880_a040_0819_0080,
+    0xc881_2028_4310_4080, 0x4440_0810_0020_8500, 0x0050_2014_2488_1617, 0x6024_0110_4022_0808,
+    0x0804_00a8_0002_0501, 0x0040_810c_2901_4200, 0x7800_9020_4001_0200, 0x5540_8011_0800_2040,
+    0x1120_4028_b000_8010, 0x1009_4020_1140_1400, 0x0820_0208_9000_4428, 0x4002_5094_1900_0800,
+    0x0026_0400_8102_0800, 0x0204_0201_4080_0108, 0x0804_4181_060a_d0a0, 0x8000_2080_1300_0040,
+    0x0810_200a_4480_0004, 0x0240_0418_1020_0240, 0x2250_1820_0a24_0085, 0x0210_2201_0408_6002,
+    0x0001_4428_0200_8100, 0x8048_5084_0502_1001, 0x4001_2082_4a01_0900, 0x2001_2049_8100_9000,
+    0x1010_c022_0480_0800, 0x8006_6040_0810_0400, 0x0002_1008_8120_0409, 0x2004_0190_080a_00f0,
+    0xa001_0808_4202_a402, 0x4020_8401_004a_0028, 0x0000_4201_0080_1020, 0x4080_2011_4180_0900,
+];
+const RANK_MAGICS: [u64; 64] = [
+    0x0200_0002_0000_0010, 0x4840_04a2_8200_4000, 0x4300_0022_c001_8111, 0x4a00_0200_0202_0080,
+    0x3208_0000_0500_2021, 0x4824_0000_0000_0120, 0x4400_0c90_0004_0050, 0x0201_4044_0013_0504,
+    0x0092_0018_9000_6440, 0x2282_0000_0d20_0000, 0x0406_0800_1002_0000, 0x8120_8020_0200_0480,
+    0xc010_4000_4602_0040, 0x0018_280c_0102_0000, 0x0014_1400_0084_0001, 0x010a_0002_20a4_8000,
+    0xa000_0a00_2004_4086, 0x0002_0248_0909_4401, 0x0000_8604_01c2_024a, 0x1104_4a01_6100_4910,
+    0x2040_5200_d102_081c, 0xa204_2200_0618_0000, 0x1042_0400_1140_0405, 0x0208_b208_0800_0400,
+    0x4004_0884_1020_0000, 0x1101_8146_0002_0000, 0x1008_8a41_010a_0801, 0x1918_0120_8018_0000,
+    0x0400_a052_0000_5001, 0x8016_0208_2400_8000, 0x0000_0342_0008_080a, 0x0000_0302_0000_1100,
+    0x0001_0280_0202_0480, 0x0101_21c4_0200_0404, 0x4000_0040_8600_8000, 0x0240_0002_0a00_0008,
+    0x0200_8080_5040_4021, 0x0008_0088_0822_4418, 0x8100_0800_1840_8322, 0xc820_a000_0410_0300,
+    0x2404_082a_8082_0010, 0x2800_0440_0022_4510, 0x0000_0000_9043_0001, 0x0000_2089_0820_8008,
+    0x1008_0018_2430_4021, 0x011d_0484_0008_2001, 0x0048_2800_010c_0042, 0x5580_9000_0c0a_0020,
+    0x2108_2000_2094_0200, 0x3000_8000_2011_0200, 0x2100_81a2_1070_0601, 0x0080_0006_2804_0a00,
+    0x0200_0022_1200_1200, 0x0a05_0008_3002_0820, 0xa100_0400_0001_0400, 0x8210_0012_0010_1410,
+    0x0400_0020_0008_0102, 0x0880_1002_0106_2002, 0x0000_0000_0000_440e, 0x0100_1200_4108_008a,
+    0x0080_9408_8080_0112, 0x5206_8000_0310_8022, 0x0000_2004_0080_0042, 0x0002_0101_0011_8222,
+];
+/// Slider attacks from a single square, looked up by multiplying the
+/// relevant occupancy with a precomputed magic number.
+pub struct MagicAttacks {
+    mask: Bitboard,
+    magic: u64,
+    shift: u32,
+    attacks: Vec<Bitboard>,
+}
+impl MagicAttacks {
+    #[inline]
+    pub fn get(&self, occupied: Bitboard) -> Bitboard {
+        self.attacks[((occupied & self.mask).wrapping_mul(self.magic) >> self.shift) as usize]
+    }
+    /// The attacks on an otherwise empty board.
+    #[inline]
+    pub fn empty(&self) -> Bitboard {
+        self.attacks[0]
+    }
+}
+fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state >> 12;
+    *state ^= *state << 25;
+    *state ^= *state >> 27;
+    state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+}
+fn magic_attacks(masks: &[Bitboard; 64], deltas: &[i8], magics: &[u64; 64]) -> [MagicAttacks; 64] {
+    (0..64)
+        .map(|sq| {
+            let (mask, magic) = (masks[sq], magics[sq]);
+            let shift = 64 - popcount(mask);
+            let mut attacks = vec![BB_EMPTY; 1 << popcount(mask)];
             for subset in carry_rippler(mask) {
-                attacks.insert(
-                    subset,
-                    sliding_attacks(sq as Square, subset, [-1, 1].iter()),
-                );
+                attacks[(subset.wrapping_mul(magic) >> shift) as usize] =
+                    sliding_attacks(sq as Square, subset, deltas.iter());
             }
-            table.push(attacks);
-        }
-        table.try_into().expect("wrong size")
-    };
+            MagicAttacks { mask, magic, shift, attacks }
+        })
+        .collect::<Vec<MagicAttacks>>()
+        .try_into()
+        .unwrap_or_else(|_| panic!("wrong size"))
 }
 fn rays() -> [[Bitboard; 64]; 64] {
     let mut rays = [[0; 64]; 64];
     for (a, bb_a) in BB_SQUARES.iter().enumerate() {
         let mut rays_row = [0; 64];
         for (b, bb_b) in BB_SQUARES.iter().enumerate() {
-            if (BB_DIAG_ATTACKS[a][&0] & bb_b) != 0 {
-                rays_row[b] = (BB_DIAG_ATTACKS[a][&0] & BB_DIAG_ATTACKS[b][&0]) | bb_a | bb_b;
-            } else if (BB_RANK_ATTACKS[a][&0] & bb_b) != 0 {
-                rays_row[b] = BB_RANK_ATTACKS[a][&0] | bb_a;
-            } else if (BB_FILE_ATTACKS[a][&0] & bb_b) != 0 {
-                rays_row[b] = BB_FILE_ATTACKS[a][&0] | bb_a;
+            if (BB_DIAG_ATTACKS[a].empty() & bb_b) != 0 {
+                rays_row[b] = (BB_DIAG_ATTACKS[a].empty() & BB_DIAG_ATTACKS[b].empty()) | bb_a | bb_b;
+            } else if (BB_RANK_ATTACKS[a].empty() & bb_b) != 0 {
+                rays_row[b] = BB_RANK_ATTACKS[a].empty() | bb_a;
+            } else if (BB_FILE_ATTACKS[a].empty() & bb_b) != 0 {
+                rays_row[b] = BB_FILE_ATTACKS[a].empty() | bb_a;
             } else {
                 rays_row[b] = BB_EMPTY;
             }
@@ -744,26 +800,262 @@ fn rays() -> [[Bitboard; 64]; 64] {
 }
 lazy_static! {
     pub static ref BB_RAYS: [[Bitboard; 64]; 64] = {
-        let rays = rays();
-        rays
+        rays()
     };
     pub static ref SAN_REGEX: Regex = {
         let regex =
-            Regex::new(r"^([NBKRQ])?([a-h])?([1-8])?[\-x]?([a-h][1-8])(=?[nbrqkNBRQK])?[\+#]?");
+            Regex::new(r"^([NBKRQ])?([a-h])?([1-8])?[\-x]?([a-h][1-8])(=?[nbrqkNBRQK])?[\+#]?$");
         regex.unwrap()
     };
     pub static ref FEN_CASTLING_REGEX: Regex = {
-        let regex = Regex::new(r"^(?:-|[KQABCDEFGH]{0,2}[kqabcdefgh]{0,2})\Z");
+        let regex = Regex::new(r"^(?:-|[KQABCDEFGH]{0,2}[kqabcdefgh]{0,2})$");
         regex.unwrap()
     };
 }
-fn ray(a: Square, b: Square) -> Bitboard {
+pub fn ray(a: Square, b: Square) -> Bitboard {
     BB_RAYS[a as usize][b as usize]
 }
-fn between(a: Square, b: Square) -> Bitboard {
+pub fn between(a: Square, b: Square) -> Bitboard {
     let bb = BB_RAYS[a as usize][b as usize] & ((BB_ALL << a) ^ (BB_ALL << b));
     bb & (bb.wrapping_sub(1u64) )
 }
+pub const ZOBRIST_CASTLING_OFFSET: usize = 768;
+pub const ZOBRIST_EP_OFFSET: usize = 772;
+pub const ZOBRIST_TURN_OFFSET: usize = 780;
+/// The Polyglot Random64 array: 12 * 64 piece keys (black pawn, white pawn,
+/// black knight, ...), 4 castling keys, 8 en passant file keys and the white
+/// to move key. Standard positions hash exactly like Polyglot opening books.
+pub const ZOBRIST_KEYS: [u64; 781] = [
+    0x9D39247E33776D41, 0x2AF7398005AAA5C7, 0x44DB015024623547, 0x9C15F73E62A76AE2,
+    0x75834465489C0C89, 0x3290AC3A203001BF, 0x0FBBAD1F61042279, 0xE83A908FF2FB60CA,
+    0x0D7E765D58755C10, 0x1A083822CEAFE02D, 0x9605D5F0E25EC3B0, 0xD021FF5CD13A2ED5,
+    0x40BDF15D4A672E32, 0x011355146FD56395, 0x5DB4832046F3D9E5, 0x239F8B2D7FF719CC,
+    0x05D1A1AE85B49AA1, 0x679F848F6E8FC971, 0x7449BBFF801FED0B, 0x7D11CDB1C3B7ADF0,
+    0x82C7709E781EB7CC, 0xF3218F1C9510786C, 0x331478F3AF51BBE6, 0x4BB38DE5E7219443,
+    0xAA649C6EBCFD50FC, 0x8DBD98A352AFD40B, 0x87D2074B81D79217, 0x19F3C751D3E92AE1,
+    0xB4AB30F062B19ABF, 0x7B0500AC42047AC4, 0xC9452CA81A09D85D, 0x24AA6C514DA27500,
+    0x4C9F34427501B447, 0x14A68FD73C910841, 0xA71B9B83461CBD93, 0x03488B95B0F1850F,
+    0x637B2B34FF93C040, 0x09D1BC9A3DD90A94, 0x3575668334A1DD3B, 0x735E2B97A4C45A23,
+    0x18727070F1BD400B, 0x1FCBACD259BF02E7, 0xD310A7C2CE9B6555, 0xBF983FE0FE5D8244,
+    0x9F74D14F7454A824, 0x51EBDC4AB9BA3035, 0x5C82C505DB9AB0FA, 0xFCF7FE8A3430B241,
+    0x3253A729B9BA3DDE, 0x8C74C368081B3075, 0xB9BC6C87167C33E7, 0x7EF48F2B83024E20,
+    0x11D505D4C351BD7F, 0x6568FCA92C76A243, 0x4DE0B0F40F32A7B8, 0x96D693460CC37E5D,
+    0x42E240CB63689F2F, 0x6D2BDCDAE2919661, 0x42880B0236E4D951, 0x5F0F4A5898171BB6,
+    0x39F890F579F92F88, 0x93C5B5F47356388B, 0x63DC359D8D231B78, 0xEC16CA8AEA98AD76,
+    0x5355F900C2A82DC7, 0x07FB9F855A997142, 0x5093417AA8A7ED5E, 0x7BCBC38DA25A7F3C,
+    0x19FC8A768CF4B6D4, 0x637A7780DECFC0D9, 0x8249A47AEE0E41F7, 0x79AD695501E7D1E8,
+    0x14ACBAF4777D5776, 0xF145B6BECCDEA195, 0xDABF2AC8201752FC, 0x24C3C94DF9C8D3F6,
+    0xBB6E2924F03912EA, 0x0CE26C0B95C980D9, 0xA49CD132BFBF7CC4, 0xE99D662AF4243939,
+    0x27E6AD7891165C3F, 0x8535F040B9744FF1, 0x54B3F4FA5F40D873, 0x72B12C32127FED2B,
+    0xEE954D3C7B411F47, 0x9A85AC909A24EAA1, 0x70AC4CD9F04F21F5, 0xF9B89D3E99A075C2,
+    0x87B3E2B2B5C907B1, 0xA366E5B8C54F48B8, 0xAE4A9346CC3F7CF2, 0x1920C04D47267BBD,
+    0x87BF02C6B49E2AE9, 0x092237AC237F3859, 0xFF07F64EF8ED14D0, 0x8DE8DCA9F03CC54E,
+    0x9C1633264DB49C89, 0xB3F22C3D0B0B38ED, 0x390E5FB44D01144B, 0x5BFEA5B4712768E9,
+    0x1E1032911FA78984, 0x9A74ACB964E78CB3, 0x4F80F7A035DAFB04, 0x6304D09A0B3738C4,
+    0x2171E64683023A08, 0x5B9B63EB9CEFF80C, 0x506AACF489889342, 0x1881AFC9A3A701D6,
+    0x6503080440750644, 0xDFD395339CDBF4A7, 0xEF927DBCF00C20F2, 0x7B32F7D1E03680EC,
+    0xB9FD7620E7316243, 0x05A7E8A57DB91B77, 0xB5889C6E15630A75, 0x4A750A09CE9573F7,
+    0xCF464CEC899A2F8A, 0xF538639CE705B824, 0x3C79A0FF5580EF7F, 0xEDE6C87F8477609D,
+    0x799E81F05BC93F31, 0x86536B8CF3428A8C, 0x97D7374C60087B73, 0xA246637CFF328532,
+    0x043FCAE60CC0EBA0, 0x920E449535DD359E, 0x70EB093B15B290CC, 0x73A1921916591CBD,
+    0x56436C9FE1A1AA8D, 0xEFAC4B70633B8F81, 0xBB215798D45DF7AF, 0x45F20042F24F1768,
+    0x930F80F4E8EB7462, 0xFF6712FFCFD75EA1, 0xAE623FD67468AA70, 0xDD2C5BC84BC8D8FC,
+    0x7EED120D54CF2DD9, 0x22FE545401165F1C, 0xC91800E98FB99929, 0x808BD68E6AC10365,
+    0xDEC468145B7605F6, 0x1BEDE3A3AEF53302, 0x43539603D6C55602, 0xAA969B5C691CCB7A,
+    0xA87832D392EFEE56, 0x65942C7B3C7E11AE, 0xDED2D633CAD004F6, 0x21F08570F420E565,
+    0xB415938D7DA94E3C, 0x91B859E59ECB6350, 0x10CFF333E0ED804A, 0x28AED140BE0BB7DD,
+    0xC5CC1D89724FA456, 0x5648F680F11A2741, 0x2D255069F0B7DAB3, 0x9BC5A38EF729ABD4,
+    0xEF2F054308F6A2BC, 0xAF2042F5CC5C2858, 0x480412BAB7F5BE2A, 0xAEF3AF4A563DFE43,
+    0x19AFE59AE451497F, 0x52593803DFF1E840, 0xF4F076E65F2CE6F0, 0x11379625747D5AF3,
+    0xBCE5D2248682C115, 0x9DA4243DE836994F, 0x066F70B33FE09017, 0x4DC4DE189B671A1C,
+    0x51039AB7712457C3, 0xC07A3F80C31FB4B4, 0xB46EE9C5E64A6E7C, 0xB3819A42ABE61C87,
+    0x21A007933A522A20, 0x2DF16F761598AA4F, 0x763C4A1371B368FD, 0xF793C46702E086A0,
+    0xD7288E012AEB8D31, 0xDE336A2A4BC1C44B, 0x0BF692B38D079F23, 0x2C604A7A177326B3,
+    0x4850E73E03EB6064, 0xCFC447F1E53C8E1B, 0xB05CA3F564268D99, 0x9AE182C8BC9474E8,
+    0xA4FC4BD4FC5558CA, 0xE755178D58FC4E76, 0x69B97DB1A4C03DFE, 0xF9B5B7C4ACC67C96,
+    0xFC6A82D64B8655FB, 0x9C684CB6C4D24417, 0x8EC97D2917456ED0, 0x6703DF9D2924E97E,
+    0xC547F57E42A7444E, 0x78E37644E7CAD29E, 0xFE9A44E9362F05FA, 0x08BD35CC38336615,
+    0x9315E5EB3A129ACE, 0x94061B871E04DF75, 0xDF1D9F9D784BA010, 0x3BBA57B68871B59D,
+    0xD2B7ADEEDED1F73F, 0xF7A255D83BC373F8, 0xD7F4F2448C0CEB81, 0xD95BE88CD210FFA7,
+    0x336F52F8FF4728E7, 0xA74049DAC312AC71, 0xA2F61BB6E437FDB5, 0x4F2A5CB07F6A35B3,
+    0x87D380BDA5BF7859, 0x16B9F7E06C453A21, 0x7BA2484C8A0FD54E, 0xF3A678CAD9A2E38C,
+    0x39B0BF7DDE437BA2, 0xFCAF55C1BF8A4424, 0x18FCF680573FA594, 0x4C0563B89F495AC3,
+    0x40E087931A00930D, 0x8CFFA9412EB642C1, 0x68CA39053261169F, 0x7A1EE967D27579E2,
+    0x9D1D60E5076F5B6F, 0x3810E399B6F65BA2, 0x32095B6D4AB5F9B1, 0x35CAB62109DD038A,
+    0xA90B24499FCFAFB1, 0x77A225A07CC2C6BD, 0x513E5E634C70E331, 0x4361C0CA3F692F12,
+    0xD941ACA44B20A45B, 0x528F7C8602C5807B, 0x52AB92BEB9613989, 0x9D1DFA2EFC557F73,
+    0x722FF175F572C348, 0x1D1260A51107FE97, 0x7A249A57EC0C9BA2, 0x04208FE9E8F7F2D6,
+    0x5A110C6058B920A0, 0x0CD9A497658A5698, 0x56FD23C8F9715A4C, 0x284C847B9D887AAE,
+    0x04FEABFBBDB619CB, 0x742E1E651C60BA83, 0x9A9632E65904AD3C, 0x881B82A13B51B9E2,
+    0x506E6744CD974924, 0xB0183DB56FFC6A79, 0x0ED9B915C66ED37E, 0x5E11E86D5873D484,
+    0xF678647E3519AC6E, 0x1B85D488D0F20CC5, 0xDAB9FE6525D89021, 0x0D151D86ADB73615,
+    0xA865A54EDCC0F019, 0x93C42566AEF98FFB, 0x99E7AFEABE000731, 0x48CBFF086DDF285A,
+    0x7F9B6AF1EBF78BAF, 0x58627E1A149BBA21, 0x2CD16E2ABD791E33, 0xD363EFF5F0977996,
+    0x0CE2A38C344A6EED, 0x1A804AADB9CFA741, 0x907F30421D78C5DE, 0x501F65EDB3034D07,
+    0x37624AE5A48FA6E9, 0x957BAF61700CFF4E, 0x3A6C27934E31188A, 0xD49503536ABCA345,
+    0x088E049589C432E0, 0xF943AEE7FEBF21B8, 0x6C3B8E3E336139D3, 0x364F6FFA464EE52E,
+    0xD60F6DCEDC314222, 0x56963B0DCA418FC0, 0x16F50EDF91E513AF, 0xEF1955914B609F93,
+    0x565601C0364E3228, 0xECB53939887E8175, 0xBAC7A9A18531294B, 0xB344C470397BBA52,
+    0x65D34954DAF3CEBD, 0xB4B81B3FA97511E2, 0xB422061193D6F6A7, 0x071582401C38434D,
+    0x7A13F18BBEDC4FF5, 0xBC4097B116C524D2, 0x59B97885E2F2EA28, 0x99170A5DC3115544,
+    0x6F423357E7C6A9F9, 0x325928EE6E6F8794, 0xD0E4366228B03343, 0x565C31F7DE89EA27,
+    0x30F5611484119414, 0xD873DB391292ED4F, 0x7BD94E1D8E17DEBC, 0xC7D9F16864A76E94,
+    0x947AE053EE56E63C, 0xC8C93882F9475F5F, 0x3A9BF55BA91F81CA, 0xD9A11FBB3D9808E4,
+    0x0FD22063EDC29FCA, 0xB3F256D8ACA0B0B9, 0xB03031A8B4516E84, 0x35DD37D5871448AF,
+    0xE9F6082B05542E4E, 0xEBFAFA33D7254B59, 0x9255ABB50D532280, 0xB9AB4CE57F2D34F3,
+    0x693501D628297551, 0xC62C58F97DD949BF, 0xCD454F8F19C5126A, 0xBBE83F4ECC2BDECB,
+    0xDC842B7E2819E230, 0xBA89142E007503B8, 0xA3BC941D0A5061CB, 0xE9F6760E32CD8021,
+    0x09C7E552BC76492F, 0x852F54934DA55CC9, 0x8107FCCF064FCF56, 0x098954D51FFF6580,
+    0x23B70EDB1955C4BF, 0xC330DE426430F69D, 0x4715ED43E8A45C0A, 0xA8D7E4DAB780A08D,
+    0x0572B974F03CE0BB, 0xB57D2E985E1419C7, 0xE8D9ECBE2CF3D73F, 0x2FE4B17170E59750,
+    0x11317BA87905E790, 0x7FBF21EC8A1F45EC, 0x1725CABFCB045B00, 0x964E915CD5E2B207,
+    0x3E2B8BCBF016D66D, 0xBE7444E39328A0AC, 0xF85B2B4FBCDE44B7, 0x49353FEA39BA63B1,
+    0x1DD01AAFCD53486A, 0x1FCA8A92FD719F85, 0xFC7C95D827357AFA, 0x18A6A990C8B35EBD,
+    0xCCCB7005C6B9C28D, 0x3BDBB92C43B17F26, 0xAA70B5B4F89695A2, 0xE94C39A54A98307F,
+    0xB7A0B174CFF6F36E, 0xD4DBA84729AF48AD, 0x2E18BC1AD9704A68, 0x2DE0966DAF2F8B1C,
+    0xB9C11D5B1E43A07E, 0x64972D68DEE33360, 0x94628D38D0C20584, 0xDBC0D2B6AB90A559,
+    0xD2733C4335C6A72F, 0x7E75D99D94A70F4D, 0x6CED1983376FA72B, 0x97FCAACBF030BC24,
+    0x7B77497B32503B12, 0x8547EDDFB81CCB94, 0x79999CDFF70902CB, 0xCFFE1939438E9B24,
+    0x829626E3892D95D7, 0x92FAE24291F2B3F1, 0x63E22C147B9C3403, 0xC678B6D860284A1C,
+    0x5873888850659AE7, 0x0981DCD296A8736D, 0x9F65789A6509A440, 0x9FF38FED72E9052F,
+    0xE479EE5B9930578C, 0xE7F28ECD2D49EECD, 0x56C074A581EA17FE, 0x5544F7D774B14AEF,
+    0x7B3F0195FC6F290F, 0x12153635B2C0CF57, 0x7F5126DBBA5E0CA7, 0x7A76956C3EAFB413,
+    0x3D5774A11D31AB39, 0x8A1B083821F40CB4, 0x7B4A38E32537DF62, 0x950113646D1D6E03,
+    0x4DA8979A0041E8A9, 0x3BC36E078F7515D7, 0x5D0A12F27AD310D1, 0x7F9D1A2E1EBE1327,
+    0xDA3A361B1C5157B1, 0xDCDD7D20903D0C25, 0x36833336D068F707, 0xCE68341F79893389,
+    0xAB9090168DD05F34, 0x43954B3252DC25E5, 0xB438C2B67F98E5E9, 0x10DCD78E3851A492,
+    0xDBC27AB5447822BF, 0x9B3CDB65F82CA382, 0xB67B7896167B4C84, 0xBFCED1B0048EAC50,
+    0xA9119B60369FFEBD, 0x1FFF7AC80904BF45, 0xAC12FB171817EEE7, 0xAF08DA9177DDA93D,
+    0x1B0CAB936E65C744, 0xB559EB1D04E5E932, 0xC37B45B3F8D6F2BA, 0xC3A9DC228CAAC9E9,
+    0xF3B8B6675A6507FF, 0x9FC477DE4ED681DA, 0x67378D8ECCEF96CB, 0x6DD856D94D259236,
+    0xA319CE15B0B4DB31, 0x073973751F12DD5E, 0x8A8E849EB32781A5, 0xE1925C71285279F5,
+    0x74C04BF1790C0EFE, 0x4DDA48153C94938A, 0x9D266D6A1CC0542C, 0x7440FB816508C4FE,
+    0x13328503DF48229F, 0xD6BF7BAEE43CAC40, 0x4838D65F6EF6748F, 0x1E152328F3318DEA,
+    0x8F8419A348F296BF, 0x72C8834A5957B511, 0xD7A023A73260B45C, 0x94EBC8ABCFB56DAE,
+    0x9FC10D0F989993E0, 0xDE68A2355B93CAE6, 0xA44CFE79AE538BBE, 0x9D1D84FCCE371425,
+    0x51D2B1AB2DDFB636, 0x2FD7E4B9E72CD38C, 0x65CA5B96B7552210, 0xDD69A0D8AB3B546D,
+    0x604D51B25FBF70E2, 0x73AA8A564FB7AC9E, 0x1A8C1E992B941148, 0xAAC40A2703D9BEA0,
+    0x764DBEAE7FA4F3A6, 0x1E99B96E70A9BE8B, 0x2C5E9DEB57EF4743, 0x3A938FEE32D29981,
+    0x26E6DB8FFDF5ADFE, 0x469356C504EC9F9D, 0xC8763C5B08D1908C, 0x3F6C6AF859D80055,
+    0x7F7CC39420A3A545, 0x9BFB227EBDF4C5CE, 0x89039D79D6FC5C5C, 0x8FE88B57305E2AB6,
+    0xA09E8C8C35AB96DE, 0xFA7E393983325753, 0xD6B6D0ECC617C699, 0xDFEA21EA9E7557E3,
+    0xB67C1FA481680AF8, 0xCA1E3785A9E724E5, 0x1CFC8BED0D681639, 0xD18D8549D140CAEA,
+    0x4ED0FE7E9DC91335, 0xE4DBF0634473F5D2, 0x1761F93A44D5AEFE, 0x53898E4C3910DA55,
+    0x734DE8181F6EC39A, 0x2680B122BAA28D97, 0x298AF231C85BAFAB, 0x7983EED3740847D5,
+    0x66C1A2A1A60CD889, 0x9E17E49642A3E4C1, 0xEDB454E7BADC0805, 0x50B704CAB602C329,
+    0x4CC317FB9CDDD023, 0x66B4835D9EAFEA22, 0x219B97E26FFC81BD, 0x261E4E4C0A333A9D,
+    0x1FE2CCA76517DB90, 0xD7504DFA8816EDBB, 0xB9571FA04DC089C8, 0x1DDC0325259B27DE,
+    0xCF3F4688801EB9AA, 0xF4F5D05C10CAB243, 0x38B6525C21A42B0E, 0x36F60E2BA4FA6800,
+    0xEB3593803173E0CE, 0x9C4CD6257C5A3603, 0xAF0C317D32ADAA8A, 0x258E5A80C7204C4B,
+    0x8B889D624D44885D, 0xF4D14597E660F855, 0xD4347F66EC8941C3, 0xE699ED85B0DFB40D,
+    0x2472F6207C2D0484, 0xC2A1E7B5B459AEB5, 0xAB4F6451CC1D45EC, 0x63767572AE3D6174,
+    0xA59E0BD101731A28, 0x116D0016CB948F09, 0x2CF9C8CA052F6E9F, 0x0B090A7560A968E3,
+    0xABEEDDB2DDE06FF1, 0x58EFC10B06A2068D, 0xC6E57A78FBD986E0, 0x2EAB8CA63CE802D7,
+    0x14A195640116F336, 0x7C0828DD624EC390, 0xD74BBE77E6116AC7, 0x804456AF10F5FB53,
+    0xEBE9EA2ADF4321C7, 0x03219A39EE587A30, 0x49787FEF17AF9924, 0xA1E9300CD8520548,
+    0x5B45E522E4B1B4EF, 0xB49C3B3995091A36, 0xD4490AD526F14431, 0x12A8F216AF9418C2,
+    0x001F837CC7350524, 0x1877B51E57A764D5, 0xA2853B80F17F58EE, 0x993E1DE72D36D310,
+    0xB3598080CE64A656, 0x252F59CF0D9F04BB, 0xD23C8E176D113600, 0x1BDA0492E7E4586E,
+    0x21E0BD5026C619BF, 0x3B097ADAF088F94E, 0x8D14DEDB30BE846E, 0xF95CFFA23AF5F6F4,
+    0x3871700761B3F743, 0xCA672B91E9E4FA16, 0x64C8E531BFF53B55, 0x241260ED4AD1E87D,
+    0x106C09B972D2E822, 0x7FBA195410E5CA30, 0x7884D9BC6CB569D8, 0x0647DFEDCD894A29,
+    0x63573FF03E224774, 0x4FC8E9560F91B123, 0x1DB956E450275779, 0xB8D91274B9E9D4FB,
+    0xA2EBEE47E2FBFCE1, 0xD9F1F30CCD97FB09, 0xEFED53D75FD64E6B, 0x2E6D02C36017F67F,
+    0xA9AA4D20DB084E9B, 0xB64BE8D8B25396C1, 0x70CB6AF7C2D5BCF0, 0x98F076A4F7A2322E,
+    0xBF84470805E69B5F, 0x94C3251F06F90CF3, 0x3E003E616A6591E9, 0xB925A6CD0421AFF3,
+    0x61BDD1307C66E300, 0xBF8D5108E27E0D48, 0x240AB57A8B888B20, 0xFC87614BAF287E07,
+    0xEF02CDD06FFDB432, 0xA1082C0466DF6C0A, 0x8215E577001332C8, 0xD39BB9C3A48DB6CF,
+    0x2738259634305C14, 0x61CF4F94C97DF93D, 0x1B6BACA2AE4E125B, 0x758F450C88572E0B,
+    0x959F587D507A8359, 0xB063E962E045F54D, 0x60E8ED72C0DFF5D1, 0x7B64978555326F9F,
+    0xFD080D236DA814BA, 0x8C90FD9B083F4558, 0x106F72FE81E2C590, 0x7976033A39F7D952,
+    0xA4EC0132764CA04B, 0x733EA705FAE4FA77, 0xB4D8F77BC3E56167, 0x9E21F4F903B33FD9,
+    0x9D765E419FB69F6D, 0xD30C088BA61EA5EF, 0x5D94337FBFAF7F5B, 0x1A4E4822EB4D7A59,
+    0x6FFE73E81B637FB3, 0xDDF957BC36D8B9CA, 0x64D0E29EEA8838B3, 0x08DD9BDFD96B9F63,
+    0x087E79E5A57D1D13, 0xE328E230E3E2B3FB, 0x1C2559E30F0946BE, 0x720BF5F26F4D2EAA,
+    0xB0774D261CC609DB, 0x443F64EC5A371195, 0x4112CF68649A260E, 0xD813F2FAB7F5C5CA,
+    0x660D3257380841EE, 0x59AC2C7873F910A3, 0xE846963877671A17, 0x93B633ABFA3469F8,
+    0xC0C0F5A60EF4CDCF, 0xCAF21ECD4377B28C, 0x57277707199B8175, 0x506C11B9D90E8B1D,
+    0xD83CC2687A19255F, 0x4A29C6465A314CD1, 0xED2DF21216235097, 0xB5635C95FF7296E2,
+    0x22AF003AB672E811, 0x52E762596BF68235, 0x9AEBA33AC6ECC6B0, 0x944F6DE09134DFB6,
+    0x6C47BEC883A7DE39, 0x6AD047C430A12104, 0xA5B1CFDBA0AB4067, 0x7C45D833AFF07862,
+    0x5092EF950A16DA0B, 0x9338E69C052B8E7B, 0x455A4B4CFE30E3F5, 0x6B02E63195AD0CF8,
+    0x6B17B224BAD6BF27, 0xD1E0CCD25BB9C169, 0xDE0C89A556B9AE70, 0x50065E535A213CF6,
+    0x9C1169FA2777B874, 0x78EDEFD694AF1EED, 0x6DC93D9526A50E68, 0xEE97F453F06791ED,
+    0x32AB0EDB696703D3, 0x3A6853C7E70757A7, 0x31865CED6120F37D, 0x67FEF95D92607890,
+    0x1F2B1D1F15F6DC9C, 0xB69E38A8965C6B65, 0xAA9119FF184CCCF4, 0xF43C732873F24C13,
+    0xFB4A3D794A9A80D2, 0x3550C2321FD6109C, 0x371F77E76BB8417E, 0x6BFA9AAE5EC05779,
+    0xCD04F3FF001A4778, 0xE3273522064480CA, 0x9F91508BFFCFC14A, 0x049A7F41061A9E60,
+    0xFCB6BE43A9F2FE9B, 0x08DE8A1C7797DA9B, 0x8F9887E6078735A1, 0xB5B4071DBFC73A66,
+    0x230E343DFBA08D33, 0x43ED7F5A0FAE657D, 0x3A88A0FBBCB05C63, 0x21874B8B4D2DBC4F,
+    0x1BDEA12E35F6A8C9, 0x53C065C6C8E63528, 0xE34A1D250E7A8D6B, 0xD6B04D3B7651DD7E,
+    0x5E90277E7CB39E2D, 0x2C046F22062DC67D, 0xB10BB459132D0A26, 0x3FA9DDFB67E2F199,
+    0x0E09B88E1914F7AF, 0x10E8B35AF3EEAB37, 0x9EEDECA8E272B933, 0xD4C718BC4AE8AE5F,
+    0x81536D601170FC20, 0x91B534F885818A06, 0xEC8177F83F900978, 0x190E714FADA5156E,
+    0xB592BF39B0364963, 0x89C350C893AE7DC1, 0xAC042E70F8B383F2, 0xB49B52E587A1EE60,
+    0xFB152FE3FF26DA89, 0x3E666E6F69AE2C15, 0x3B544EBE544C19F9, 0xE805A1E290CF2456,
+    0x24B33C9D7ED25117, 0xE74733427B72F0C1, 0x0A804D18B7097475, 0x57E3306D881EDB4F,
+    0x4AE7D6A36EB5DBCB, 0x2D8D5432157064C8, 0xD1E649DE1E7F268B, 0x8A328A1CEDFE552C,
+    0x07A3AEC79624C7DA, 0x84547DDC3E203C94, 0x990A98FD5071D263, 0x1A4FF12616EEFC89,
+    0xF6F7FD1431714200, 0x30C05B1BA332F41C, 0x8D2636B81555A786, 0x46C9FEB55D120902,
+    0xCCEC0A73B49C9921, 0x4E9D2827355FC492, 0x19EBB029435DCB0F, 0x4659D2B743848A2C,
+    0x963EF2C96B33BE31, 0x74F85198B05A2E7D, 0x5A0F544DD2B1FB18, 0x03727073C2E134B1,
+    0xC7F6AA2DE59AEA61, 0x352787BAA0D7C22F, 0x9853EAB63B5E0B35, 0xABBDCDD7ED5C0860,
+    0xCF05DAF5AC8D77B0, 0x49CAD48CEBF4A71E, 0x7A4C10EC2158C4A6, 0xD9E92AA246BF719E,
+    0x13AE978D09FE5557, 0x730499AF921549FF, 0x4E4B705B92903BA4, 0xFF577222C14F0A3A,
+    0x55B6344CF97AAFAE, 0xB862225B055B6960, 0xCAC09AFBDDD2CDB4, 0xDAF8E9829FE96B5F,
+    0xB5FDFC5D3132C498, 0x310CB380DB6F7503, 0xE87FBB46217A360E, 0x2102AE466EBB1148,
+    0xF8549E1A3AA5E00D, 0x07A69AFDCC42261A, 0xC4C118BFE78FEAAE, 0xF9F4892ED96BD438,
+    0x1AF3DBE25D8F45DA, 0xF5B4B0B0D2DEEEB4, 0x962ACEEFA82E1C84, 0x046E3ECAAF453CE9,
+    0xF05D129681949A4C, 0x964781CE734B3C84, 0x9C2ED44081CE5FBD, 0x522E23F3925E319E,
+    0x177E00F9FC32F791, 0x2BC60A63A6F3B3F2, 0x222BBFAE61725606, 0x486289DDCC3D6780,
+    0x7DC7785B8EFDFC80, 0x8AF38731C02BA980, 0x1FAB64EA29A2DDF7, 0xE4D9429322CD065A,
+    0x9DA058C67844F20C, 0x24C0E332B70019B0, 0x233003B5A6CFE6AD, 0xD586BD01C5C217F6,
+    0x5E5637885F29BC2B, 0x7EBA726D8C94094B, 0x0A56A5F0BFE39272, 0xD79476A84EE20D06,
+    0x9E4C1269BAA4BF37, 0x17EFEE45B0DEE640, 0x1D95B0A5FCF90BC6, 0x93CBE0B699C2585D,
+    0x65FA4F227A2B6D79, 0xD5F9E858292504D5, 0xC2B5A03F71471A6F, 0x59300222B4561E00,
+    0xCE2F8642CA0712DC, 0x7CA9723FBB2E8988, 0x2785338347F2BA08, 0xC61BB3A141E50E8C,
+    0x150F361DAB9DEC26, 0x9F6A419D382595F4, 0x64A53DC924FE7AC9, 0x142DE49FFF7A7C3D,
+    0x0C335248857FA9E7, 0x0A9C32D5EAE45305, 0xE6C42178C4BBB92E, 0x71F1CE2490D20B07,
+    0xF1BCC3D275AFE51A, 0xE728E8C83C334074, 0x96FBF83A12884624, 0x81A1549FD6573DA5,
+    0x5FA7867CAF35E149, 0x56986E2EF3ED091B, 0x917F1DD5F8886C61, 0xD20D8C88C8FFE65F,
+    0x31D71DCE64B2C310, 0xF165B587DF898190, 0xA57E6339DD2CF3A0, 0x1EF6E6DBB1961EC9,
+    0x70CC73D90BC26E24, 0xE21A6B35DF0C3AD7, 0x003A93D8B2806962, 0x1C99DED33CB890A1,
+    0xCF3145DE0ADD4289, 0xD0E4427A5514FB72, 0x77C621CC9FB3A483, 0x67A34DAC4356550B,
+    0xF8D626AAAF278509,
+];
+/// Seed of the `xorshift` stream for the keys the Polyglot array has no
+/// entries for: first the Crazyhouse pocket keys, then the Three-check keys.
+const ZOBRIST_EXTRA_SEED: u64 = 0x3bd5_a0e2_58f0_1c7b;
+/// Keys for the number of pieces of each type in a Crazyhouse pocket,
+/// indexed by color, piece type and count, and for the number of checks a
+/// side has left in Three-check, indexed by color and count. Three checks
+/// left has no key, so other variants hash the same.
+type ZobristExtraKeys = ([[[u64; 32]; 7]; 2], [[u64; 3]; 2]);
+lazy_static! {
+    static ref ZOBRIST_EXTRA_KEYS: ZobristExtraKeys = {
+        let mut pocket_keys = [[[0; 32]; 7]; 2];
+        let mut check_keys = [[0; 3]; 2];
+        let mut state = ZOBRIST_EXTRA_SEED;
+        for key in pocket_keys.iter_mut().flatten().flatten().chain(check_keys.iter_mut().flatten()) {
+            *key = xorshift(&mut state);
+        }
+        (pocket_keys, check_keys)
+    };
+}
+fn zobrist_pocket_key(pocket: &CrazyhousePocket, color: Color) -> u64 {
+    let mut key = 0;
+    for piece_type in PIECE_TYPES {
+        let count = pocket.count(piece_type);
+        if count > 0 {
+            key ^= ZOBRIST_EXTRA_KEYS.0[color as usize][piece_type as usize][min(count, 31) as usize];
+        }
+    }
+    key
+}
+fn zobrist_piece_key(piece_type: PieceType, color: Color, square: Square) -> u64 {
+    let kind = 2 * (piece_type as usize - 1) + color as usize;
+    ZOBRIST_KEYS[64 * kind + square as usize]
+}
 #[derive(Hash, Debug)]
 pub struct Piece {
     piece_type: PieceType,
@@ -776,16 +1068,16 @@ impl Piece {
         } else {
             piece_symbol(self.piece_type).unwrap()
         };
-        return symbol;
+        symbol
     }
     fn unicode_symbol(&self) -> char {
-        return unicode_piece_symbols(self.symbol());
+        unicode_piece_symbols(self.symbol())
     }
     fn unicode_symbol_inverted(&self) -> char {
         if self.symbol().is_ascii_uppercase() {
             return unicode_piece_symbols(self.symbol().to_ascii_lowercase());
         }
-        return unicode_piece_symbols(self.symbol().to_ascii_uppercase());
+        unicode_piece_symbols(self.symbol().to_ascii_uppercase())
     }
     fn from_symbol(symbol: char) -> Piece {
         Piece {
@@ -799,10 +1091,13 @@ pub struct Move {
     pub from_square: Square,
     pub to_square: Square,
     pub promotion: Option<PieceType>,
+    /// Piece type dropped from the pocket (Crazyhouse). Drops have the same
+    /// from and to square.
+    pub drop: Option<PieceType>,
 }
 impl Boolean for Option<Square>{
     fn bool(&self) -> bool {
-        *self != None
+        self.is_some()
     }
 }
 impl Boolean for Square {
@@ -817,7 +1112,7 @@ impl Boolean for Bitboard {
 }
 impl Boolean for Move {
     fn bool(&self) -> bool {
-        self.from_square.bool() || self.to_square.bool() || self.promotion.bool()
+        self.from_square.bool() || self.to_square.bool() || self.promotion.bool() || self.drop.bool()
     }
 }
 impl Boolean for bool {
@@ -828,7 +1123,11 @@ impl Boolean for bool {
 impl Move {
     pub fn uci(&self) -> String {
         let mut result = String::new();
-        if let Some(promotion) = self.promotion {
+        if let Some(drop) = self.drop {
+            result.push(piece_symbol(drop).unwrap().to_ascii_uppercase());
+            result.push('@');
+            result.push_str(SQUARE_NAMES[self.to_square as usize]);
+        } else if let Some(promotion) = self.promotion {
             result.push_str(SQUARE_NAMES[self.from_square as usize]);
             result.push_str(SQUARE_NAMES[self.to_square as usize]);
             result.push(piece_symbol(promotion).unwrap());
@@ -844,33 +1143,63 @@ impl Move {
         if self.bool() {
             return self.uci();
         }
-        return String::from("@@@@");
+        String::from("@@@@")
     }
     pub fn from_uci(uci: &str) -> Move {
+        match Move::try_from_uci(uci) {
+            Ok(m) => m,
+            Err(e) => panic!("{}", e),
+        }
+    }
+    /// Parses a move in UCI notation without looking at any position.
+    pub fn try_from_uci(uci: &str) -> Result<Move, MoveError> {
+        let invalid = || MoveError::InvalidUci(uci.to_string());
         if uci == "0000" {
-            Move::null()
-        } else if uci.len() == 4 || uci.len() == 5 {
-            let from_square = parse_square(&uci[..2]);
-            let to_square = parse_square(&uci[2..4]);
-            let promotion = if uci.len() == 5 {
-                Some(piece_type(uci.chars().nth(4)).unwrap())
-            } else {
-                None
+            return Ok(Move::null());
+        }
+        if !uci.is_ascii() {
+            return Err(invalid());
+        }
+        if uci.len() == 4 && &uci[1..2] == "@" {
+            let drop = match piece_type(uci.chars().next().map(|c| c.to_ascii_lowercase())) {
+                Some(drop) if SQUARE_NAMES.contains(&&uci[2..]) => drop,
+                _ => return Err(invalid()),
             };
-            Move {
-                from_square,
-                to_square,
-                promotion,
+            let square = parse_square(&uci[2..]);
+            return Ok(Move { from_square: square, to_square: square, promotion: None, drop: Some(drop) });
+        }
+        if uci.len() != 4 && uci.len() != 5 {
+            return Err(invalid());
+        }
+        if !SQUARE_NAMES.contains(&&uci[..2]) || !SQUARE_NAMES.contains(&&uci[2..4]) {
+            return Err(invalid());
+        }
+        let from_square = parse_square(&uci[..2]);
+        let to_square = parse_square(&uci[2..4]);
+        let promotion = if uci.len() == 5 {
+            match piece_type(uci.chars().nth(4)) {
+                Some(PAWN) | None => return Err(invalid()),
+                promotion => promotion,
             }
         } else {
-            panic!("expected uci string to be of lenght 4 or 5: {}", uci);
-        }
+            None
+        };
+        if from_square == to_square {
+            return Err(invalid());
+        }
+        Ok(Move {
+            from_square,
+            to_square,
+            promotion,
+            drop: None,
+        })
     }
     pub fn null() -> Move {
         Move {
             from_square: 0,
             to_square: 0,
             promotion: None,
+            drop: None,
         }
     }
 }
@@ -884,6 +1213,48 @@ impl fmt::Debug for Move {
         write!(f, "Move(\"{}\")", self.uci())
     }
 }
+/// Error returned by the fallible FEN parsers. `position` is the index of
+/// the offending character in the string that was passed in.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum FenError {
+    #[error("empty fen")]
+    Empty,
+    #[error("invalid board part at character {position}: {reason}")]
+    Board { position: usize, reason: &'static str },
+    #[error("expected 'w' or 'b' for turn part at character {position}")]
+    Turn { position: usize },
+    #[error("invalid pocket at character {position}")]
+    Pocket { position: usize },
+    #[error("invalid castling part at character {position}")]
+    Castling { position: usize },
+    #[error("invalid en passant square at character {position}")]
+    EnPassant { position: usize },
+    #[error("invalid remaining checks at character {position}")]
+    Checks { position: usize },
+    #[error("invalid halfmove clock at character {position}")]
+    HalfmoveClock { position: usize },
+    #[error("invalid fullmove number at character {position}")]
+    FullmoveNumber { position: usize },
+    #[error("unexpected part at character {position}")]
+    TooManyParts { position: usize },
+}
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum MoveError {
+    #[error("invalid uci: {0}")]
+    InvalidUci(String),
+    #[error("illegal move: {uci} in {fen}")]
+    IllegalMove { uci: String, fen: String },
+}
+#[derive(Error, Debug, Clone, PartialEq)]
+#[allow(clippy::enum_variant_names)]
+pub enum SanError {
+    #[error("invalid san: {0}")]
+    InvalidSan(String),
+    #[error("illegal san: {san} in {fen}")]
+    IllegalSan { san: String, fen: String },
+    #[error("ambiguous san: {san} in {fen}, candidates: {candidates:?}")]
+    AmbiguousSan { san: String, fen: String, candidates: Vec<Move> },
+}
 #[derive(Clone, Copy)]
 pub struct BaseBoard {
     pub pawns: u64,
@@ -944,6 +1315,60 @@ impl BaseBoard {
         self.occupied_co[BLACK as usize] = BB_EMPTY;
         self.occupied = BB_EMPTY;
     }
+    /// Sets up a Chess960 starting position given its index between 0 and
+    /// 959 (Scharnagl numbering, 518 is the standard starting position).
+    pub fn set_chess960_pos(&mut self, scharnagl: u32) {
+        assert!(scharnagl <= 959, "chess960 position index not 0 <= {} <= 959", scharnagl);
+
+        let (n, bw) = (scharnagl / 4, scharnagl % 4);
+        let (n, bb) = (n / 4, n % 4);
+        let (n, q) = (n / 6, n % 6);
+
+        // Knight placements are enumerated as pairs of the 5 remaining files.
+        let (mut n1, mut n2) = (0i32, 0i32);
+        for i in 0..4 {
+            n1 = i;
+            n2 = n as i32 + (3 - n1) * (4 - n1) / 2 - 5;
+            if n1 < n2 && (1..=4).contains(&n2) {
+                break;
+            }
+        }
+
+        let bw_file = (bw * 2 + 1) as usize;
+        let bb_file = (bb * 2) as usize;
+        self.bishops = (BB_FILES[bw_file] | BB_FILES[bb_file]) & BB_BACKRANKS;
+
+        let mut q_file = q as usize;
+        q_file += (min(bw_file, bb_file) <= q_file) as usize;
+        q_file += (max(bw_file, bb_file) <= q_file) as usize;
+        self.queens = BB_FILES[q_file] & BB_BACKRANKS;
+
+        let mut used = vec![bw_file, bb_file, q_file];
+
+        self.knights = BB_EMPTY;
+        for i in 0..8 {
+            if !used.contains(&i) {
+                if n1 == 0 || n2 == 0 {
+                    self.knights |= BB_FILES[i] & BB_BACKRANKS;
+                    used.push(i);
+                }
+                n1 -= 1;
+                n2 -= 1;
+            }
+        }
+
+        // The remaining three files are rook, king, rook.
+        let mut rkr = (0..8).filter(|i| !used.contains(i));
+        let (a_rook, king, h_rook) = (rkr.next().unwrap(), rkr.next().unwrap(), rkr.next().unwrap());
+        self.rooks = (BB_FILES[a_rook] | BB_FILES[h_rook]) & BB_BACKRANKS;
+        self.kings = BB_FILES[king] & BB_BACKRANKS;
+
+        self.pawns = BB_RANK_2 | BB_RANK_7;
+        self.promoted = BB_EMPTY;
+        self.occupied_co[WHITE as usize] = BB_RANK_1 | BB_RANK_2;
+        self.occupied_co[BLACK as usize] = BB_RANK_7 | BB_RANK_8;
+        self.occupied = BB_RANK_1 | BB_RANK_2 | BB_RANK_7 | BB_RANK_8;
+    }
     pub fn pieces_mask(&self, piece_type: PieceType, color: Color) -> Bitboard {
         let bb = match piece_type {
             PAWN => self.pawns,
@@ -956,7 +1381,7 @@ impl BaseBoard {
                 panic!("expected PieceType got: {}", piece_type)
             }
         };
-        return bb & self.occupied_co[color as usize];
+        bb & self.occupied_co[color as usize]
     }
     pub fn pieces(&self, piece_type: PieceType, color: Color) -> SquareSet {
         SquareSet {
@@ -966,19 +1391,19 @@ impl BaseBoard {
     pub fn piece_type_at(&self, square: Square) -> Option<PieceType> {
         let mask = BB_SQUARES[square as usize];
         if !self.occupied & mask != 0 {
-            return None;
+            None
         } else if self.pawns & mask != 0 {
-            return Some(PAWN);
+            Some(PAWN)
         } else if self.knights & mask != 0 {
-            return Some(KNIGHT);
+            Some(KNIGHT)
         } else if self.bishops & mask != 0 {
-            return Some(BISHOP);
+            Some(BISHOP)
         } else if self.rooks & mask != 0 {
-            return Some(ROOK);
+            Some(ROOK)
         } else if self.queens & mask != 0 {
-            return Some(QUEEN);
+            Some(QUEEN)
         } else {
-            return Some(KING);
+            Some(KING)
         }
     }
     pub fn piece_at(&self, square: Square) -> Option<Piece> {
@@ -988,8 +1413,8 @@ impl BaseBoard {
                 let mask = BB_SQUARES[square as usize];
                 let color = self.occupied_co[WHITE as usize] & mask != 0;
                 Some(Piece {
-                    piece_type: piece_type,
-                    color: color,
+                    piece_type,
+                    color,
                 })
             }
             None => None,
@@ -1015,22 +1440,19 @@ impl BaseBoard {
         let bb_square = BB_SQUARES[square as usize];
         if bb_square & self.pawns != 0 {
             let color = bb_square & self.occupied_co[WHITE as usize] != 0;
-            return BB_PAWN_ATTACKS[color as usize][square as usize];
+            BB_PAWN_ATTACKS[color as usize][square as usize]
         } else if bb_square & self.knights != 0 {
-            return BB_KNIGHT_ATTACKS[square as usize];
+            BB_KNIGHT_ATTACKS[square as usize]
         } else if bb_square & self.kings != 0 {
-            return BB_KING_ATTACKS[square as usize];
+            BB_KING_ATTACKS[square as usize]
         } else {
             let mut attacks = 0;
             if bb_square & self.bishops != 0 || bb_square & self.queens != 0 {
-                attacks = BB_DIAG_ATTACKS[square as usize]
-                    [&(BB_DIAG_MASKS[square as usize] & self.occupied)];
+                attacks = BB_DIAG_ATTACKS[square as usize].get(self.occupied);
             }
             if bb_square & self.rooks != 0 || bb_square & self.queens != 0 {
-                attacks |= BB_RANK_ATTACKS[square as usize]
-                    [&(BB_RANK_MASKS[square as usize] & self.occupied)]
-                    | BB_FILE_ATTACKS[square as usize]
-                        [&(BB_FILE_MASKS[square as usize] & self.occupied)];
+                attacks |= BB_RANK_ATTACKS[square as usize].get(self.occupied)
+                    | BB_FILE_ATTACKS[square as usize].get(self.occupied);
             }
 
             attacks
@@ -1051,9 +1473,9 @@ impl BaseBoard {
 
         let attackers = (BB_KING_ATTACKS[square as usize] & self.kings)
             | (BB_KNIGHT_ATTACKS[square as usize] & self.knights)
-            | (BB_RANK_ATTACKS[square as usize][&rank_pieces] & queens_and_rooks)
-            | (BB_FILE_ATTACKS[square as usize][&file_pieces] & queens_and_rooks)
-            | (BB_DIAG_ATTACKS[square as usize][&diag_pieces] & queens_and_bishops)
+            | (BB_RANK_ATTACKS[square as usize].get(rank_pieces) & queens_and_rooks)
+            | (BB_FILE_ATTACKS[square as usize].get(file_pieces) & queens_and_rooks)
+            | (BB_DIAG_ATTACKS[square as usize].get(diag_pieces) & queens_and_bishops)
             | (BB_PAWN_ATTACKS[(!color) as usize][square as usize] & self.pawns);
 
         attackers & self.occupied_co[color as usize]
@@ -1070,23 +1492,23 @@ impl BaseBoard {
         }
     }
     pub fn pin_mask(&self, color: Color, square: Square) -> Bitboard {
-        if self.king(color) == None {
+        if self.king(color).is_none() {
             return BB_ALL;
         }
         let king = self.king(color).unwrap();
 
         let square_mask = BB_SQUARES[square as usize];
 
-        let a: [(&[AHashMap<Bitboard, Bitboard>; 64], u64); 3] = [
+        let a: [(&[MagicAttacks; 64], u64); 3] = [
             (&BB_FILE_ATTACKS, self.rooks | self.queens),
             (&BB_RANK_ATTACKS, self.rooks | self.queens),
             (&BB_DIAG_ATTACKS, self.bishops | self.queens),
         ];
 
         for (attacks, sliders) in a {
-            let rays = attacks[king as usize][&0];
+            let rays = attacks[king as usize].empty();
             if rays & square_mask != 0 {
-                let snipers = rays & sliders & self.occupied_co[(!color as usize)];
+                let snipers = rays & sliders & self.occupied_co[!color as usize];
                 for sniper in scan_reversed(snipers) {
                     if between(sniper as Square, king) & (self.occupied | square_mask)
                         == square_mask
@@ -1126,18 +1548,15 @@ impl BaseBoard {
 
         self.promoted &= !mask;
 
-        return piece_type;
+        piece_type
     }
     pub fn remove_piece_at(&mut self, square: Square) -> Option<Piece> {
         let color = self.occupied_co[WHITE as usize] & BB_SQUARES[square as usize] != 0;
         let piece_type = self._remove_piece_at(square);
-        match piece_type {
-            Some(piece) => Some(Piece {
+        piece_type.map(|piece| Piece {
                 piece_type: piece,
-                color: color,
-            }),
-            None => None,
-        }
+                color,
+            })
     }
     pub fn _set_piece_at(
         &mut self,
@@ -1222,64 +1641,83 @@ impl BaseBoard {
         builder
     }
     pub fn set_board_fen(&mut self, fen: &str) {
-        let fen_trimmed = fen.trim();
-        if fen_trimmed.contains(" ") {
-            panic!("expected position part of fen, got multiple parts {}", fen);
+        if let Err(e) = self.try_set_board_fen(fen) {
+            panic!("{} in fen {}", e, fen);
         }
-        let rows: Vec<&str> = fen.split("/").collect();
-        if rows.len() != 8 {
-            panic!("expected 8 rows in position part of fen {}", fen);
-        }
-
-        for row in rows {
-            let mut field_sum = 0;
-            let mut previuos_was_digit = false;
-            let mut previous_was_piece = false;
-
-            for c in row.chars() {
-                if ['1', '2', '3', '4', '5', '6', '7', '8'].contains(&c) {
-                    if previuos_was_digit {
-                        panic!("two subseqeunt digits in position part of fen {}", fen);
-                    }
-                    field_sum += (c as u8 - 0x30) as u64;
-                    previuos_was_digit = true;
-                    previous_was_piece = false;
-                } else if c == '~' {
-                    if !previous_was_piece {
-                        panic!("'~' not after piece in position part of fen {}", fen);
-                    }
-                    previuos_was_digit = false;
-                    previous_was_piece = false;
-                } else if PIECE_SYMBOLS.contains(&Some(c.to_ascii_lowercase())) {
-                    field_sum += 1;
-                    previuos_was_digit = false;
-                    previous_was_piece = true
-                } else {
-                    panic!("invlaid character in position part of fen {}", fen);
+    }
+    /// Sets the position from the board part of a FEN. On error the board
+    /// is left untouched and the error points at the offending character.
+    pub fn try_set_board_fen(&mut self, fen: &str) -> Result<(), FenError> {
+        let board_error = |position: usize, reason: &'static str| FenError::Board { position, reason };
+        let fen_trimmed = fen.trim();
+        let offset = fen.len() - fen.trim_start().len();
+        if fen_trimmed.is_empty() {
+            return Err(FenError::Empty);
+        }
+        if let Some(i) = fen_trimmed.find(char::is_whitespace) {
+            return Err(board_error(offset + i, "expected position part of fen, got multiple parts"));
+        }
+
+        let mut board = BaseBoard::new(None);
+        let mut rows = 0;
+        let mut field_sum = 0;
+        let mut previous_was_digit = false;
+        let mut previous_was_piece = false;
+        let mut square_index: usize = 0;
+
+        for (i, c) in fen_trimmed.char_indices() {
+            let position = offset + i;
+            if c == '/' {
+                if field_sum != 8 {
+                    return Err(board_error(position, "expected 8 columns per row"));
+                }
+                rows += 1;
+                if rows == 8 {
+                    return Err(board_error(position, "expected 8 rows"));
+                }
+                field_sum = 0;
+                previous_was_digit = false;
+                previous_was_piece = false;
+            } else if ['1', '2', '3', '4', '5', '6', '7', '8'].contains(&c) {
+                if previous_was_digit {
+                    return Err(board_error(position, "two subsequent digits"));
+                }
+                field_sum += (c as u8 - 0x30) as usize;
+                if field_sum > 8 {
+                    return Err(board_error(position, "expected 8 columns per row"));
+                }
+                square_index += (c as u8 - 0x30) as usize;
+                previous_was_digit = true;
+                previous_was_piece = false;
+            } else if c == '~' {
+                if !previous_was_piece {
+                    return Err(board_error(position, "'~' not after piece"));
+                }
+                board.promoted |= BB_SQUARES[SQUARES_180[square_index - 1] as usize];
+                previous_was_digit = false;
+                previous_was_piece = false;
+            } else if c.is_ascii() && PIECE_SYMBOLS.contains(&Some(c.to_ascii_lowercase())) {
+                field_sum += 1;
+                if field_sum > 8 {
+                    return Err(board_error(position, "expected 8 columns per row"));
                 }
-            }
-            if field_sum != 8 {
-                panic!("expected 8 columns per row in position part of fen {}", fen);
-            }
-        }
-        self.clear_board();
-        let mut square_index = 0;
-        for c in fen_trimmed.chars() {
-            if ['1', '2', '3', '4', '5', '6', '7', '8'].contains(&c) {
-                square_index += (c as u8 - 0x30) as Square;
-            } else if PIECE_SYMBOLS.contains(&Some(c.to_ascii_lowercase())) {
                 let piece = Piece::from_symbol(c);
-                self._set_piece_at(
-                    SQUARES_180[square_index as usize] as Square,
-                    piece.piece_type,
-                    piece.color,
-                    false,
-                );
+                board._set_piece_at(SQUARES_180[square_index], piece.piece_type, piece.color, false);
                 square_index += 1;
-            } else if c == '~' {
-                self.promoted |= BB_SQUARES[SQUARES_180[(square_index - 1) as usize] as usize];
+                previous_was_digit = false;
+                previous_was_piece = true;
+            } else {
+                return Err(board_error(position, "invalid character"));
             }
         }
+        if field_sum != 8 {
+            return Err(board_error(offset + fen_trimmed.len(), "expected 8 columns per row"));
+        }
+        if rows != 7 {
+            return Err(board_error(offset + fen_trimmed.len(), "expected 8 rows"));
+        }
+        *self = board;
+        Ok(())
     }
     pub fn piece_map(&self, mask: Bitboard) -> AHashMap<Square, Piece> {
         let mut result = AHashMap::new();
@@ -1352,17 +1790,16 @@ impl BaseBoard {
         self.promoted = f(self.promoted);
     }
     pub fn transform(&self, f: fn(Bitboard) -> Bitboard) -> BaseBoard {
-        let mut board = self.clone();
+        let mut board = *self;
         board.apply_transform(f);
         board
     }
     pub fn apply_mirror(&mut self) {
         self.apply_transform(flip_vertical);
-        self.occupied_co[WHITE as usize] = self.occupied_co[BLACK as usize];
-        self.occupied_co[BLACK as usize] = self.occupied_co[WHITE as usize];
+        self.occupied_co.swap(WHITE as usize, BLACK as usize);
     }
     pub fn mirror(&self) -> BaseBoard {
-        let mut board = self.clone();
+        let mut board = *self;
         board.apply_mirror();
         board
     }
@@ -1379,63 +1816,67 @@ impl PartialEq for BaseBoard {
             && self.kings == board.kings
     }
 }
-#[derive(Clone, Copy)]
+/// Undo record of one move on the stack: whatever `pop` can not derive
+/// from the move itself.
+#[derive(Clone)]
 pub struct BoardState {
-    pawns: u64,
-    knights: u64,
-    bishops: u64,
-    rooks: u64,
-    queens: u64,
-    kings: u64,
-    promoted: u64,
-    occupied_w: u64,
-    occupied_b: u64,
-    occupied: u64,
-    turn: Color,
+    captured: Option<PieceType>,
+    capture_square: Square,
+    captured_promoted: bool,
+    promoted: bool,
+    castling: bool,
     castling_rights: Bitboard,
     ep_square: Option<Square>,
     halfmove_clock: u64,
     fullmove_number: u64,
+    remaining_checks: [u8; 2],
+    zobrist_key: u64,
+    /// Board before the move, only kept when the variant changes more
+    /// squares than the move itself.
+    baseboard: Option<Box<BaseBoard>>,
 }
 impl BoardState {
-    fn new(board: Board) -> BoardState {
+    fn new(board: &Board) -> BoardState {
         BoardState {
-            pawns: board.baseboard.pawns,
-            knights: board.baseboard.knights,
-            bishops: board.baseboard.bishops,
-            rooks: board.baseboard.rooks,
-            queens: board.baseboard.queens,
-            kings: board.baseboard.kings,
-            promoted: board.baseboard.promoted,
-            occupied_w: board.baseboard.occupied_co[WHITE as usize],
-            occupied_b: board.baseboard.occupied_co[BLACK as usize],
-            occupied: board.baseboard.occupied,
-            turn: board.turn,
+            captured: None,
+            capture_square: 0,
+            captured_promoted: false,
+            promoted: false,
+            castling: false,
             castling_rights: board.castling_rights,
             ep_square: board.ep_square,
             halfmove_clock: board.halfmove_clock,
             fullmove_number: board.fullmove_number,
+            remaining_checks: board.remaining_checks,
+            zobrist_key: board.zobrist_key,
+            baseboard: None,
         }
     }
-    fn restore(&self, board: &mut Board) {
-        board.baseboard.pawns = self.pawns;
-        board.baseboard.knights = self.knights;
-        board.baseboard.bishops = self.bishops;
-        board.baseboard.rooks = self.rooks;
-        board.baseboard.queens = self.queens;
-        board.baseboard.kings = self.kings;
-
-        board.baseboard.occupied_co[WHITE as usize] = self.occupied_w;
-        board.baseboard.occupied_co[BLACK as usize] = self.occupied_b;
-        board.baseboard.occupied = self.occupied;
-
-        board.baseboard.promoted = self.promoted;
-
-        board.turn = self.turn;
-        board.castling_rights = self.castling_rights;
-        board.ep_square = self.ep_square;
-        board.halfmove_clock = self.halfmove_clock;
-        board.fullmove_number = self.fullmove_number;
+    /// Type of the piece captured by the move, if any.
+    pub fn captured(&self) -> Option<PieceType> {
+        if self.castling { None } else { self.captured }
+    }
+    /// Whether the captured piece was a promoted pawn.
+    pub fn captured_promoted(&self) -> bool {
+        !self.castling && self.captured_promoted
+    }
+    pub fn castling_rights(&self) -> Bitboard {
+        self.castling_rights
+    }
+    pub fn ep_square(&self) -> Option<Square> {
+        self.ep_square
+    }
+    pub fn halfmove_clock(&self) -> u64 {
+        self.halfmove_clock
+    }
+    pub fn fullmove_number(&self) -> u64 {
+        self.fullmove_number
+    }
+    pub fn remaining_checks(&self) -> [u8; 2] {
+        self.remaining_checks
+    }
+    pub fn zobrist_hash(&self) -> u64 {
+        self.zobrist_key
     }
 }
 pub struct Board {
@@ -1447,22 +1888,26 @@ pub struct Board {
     pub castling_rights: Bitboard,
     pub halfmove_clock: u64,
     pub fullmove_number: u64,
+    /// Chess960 mode: castling rights may name any rook of the back rank
+    /// and castling moves are written king takes rook in UCI.
+    pub chess960: bool,
+    pub variant: &'static dyn Variant,
+    /// Pieces in hand of white and black, only used by Crazyhouse.
+    pub pockets: [CrazyhousePocket; 2],
+    /// Checks black and white may still give before winning, only used by
+    /// Three-check.
+    pub remaining_checks: [u8; 2],
+    zobrist_key: u64,
 }
 impl Board {
     pub fn new(fen: Option<&str>) -> Board {
-        let baseboard;
-        match fen {
-            None => {
-                baseboard = BaseBoard::new(None);
-            },
-            Some(STARTING_FEN) => {
-                baseboard = BaseBoard::new(Some(STARTING_BOARD_FEN));
-            },
-            _ => {baseboard = BaseBoard::new(Some(fen.unwrap()))},
-        }
+        let baseboard = match fen {
+            Some(STARTING_FEN) => BaseBoard::new(Some(STARTING_BOARD_FEN)),
+            _ => BaseBoard::new(None),
+        };
         // let baseboard = BaseBoard::new(fen);
         let mut board = Board {
-            baseboard: baseboard,
+            baseboard,
             ep_square: None,
             move_stack: Vec::new(),
             stack: Vec::new(),
@@ -1470,6 +1915,11 @@ impl Board {
             castling_rights: BB_EMPTY,
             halfmove_clock: 0,
             fullmove_number: 1,
+            chess960: false,
+            variant: &STANDARD,
+            pockets: [CrazyhousePocket::new(); 2],
+            remaining_checks: [3, 3],
+            zobrist_key: 0,
         };
 
         match fen {
@@ -1484,6 +1934,10 @@ impl Board {
         board
     }
     pub fn reset(&mut self) {
+        if self.variant.starting_fen() != STARTING_FEN {
+            self.set_fen(self.variant.starting_fen());
+            return;
+        }
         self.turn = WHITE;
         self.castling_rights = BB_CORNERS;
         self.ep_square = None;
@@ -1494,6 +1948,25 @@ impl Board {
     }
     pub fn reset_board(&mut self) {
         self.baseboard.reset_board();
+        self.pockets = [CrazyhousePocket::new(); 2];
+        self.remaining_checks = [3, 3];
+        self.clear_stack();
+    }
+    /// Creates a Chess960 board set up in the starting position with the
+    /// given index between 0 and 959.
+    pub fn from_chess960_pos(scharnagl: u32) -> Board {
+        let mut board = Board::new(None);
+        board.set_chess960_pos(scharnagl);
+        board
+    }
+    pub fn set_chess960_pos(&mut self, scharnagl: u32) {
+        self.baseboard.set_chess960_pos(scharnagl);
+        self.chess960 = true;
+        self.turn = WHITE;
+        self.castling_rights = self.baseboard.rooks;
+        self.ep_square = None;
+        self.halfmove_clock = 0;
+        self.fullmove_number = 1;
         self.clear_stack();
     }
     pub fn clear(&mut self) {
@@ -1505,7 +1978,7 @@ impl Board {
 
         self.clear_board();
     }
-    pub fn copy(&mut self, copy_stack: bool) -> Board {
+    pub fn copy(&self, copy_stack: bool) -> Board {
         let mut board = Board::new(None);
         board.baseboard = self.baseboard;
         board.ep_square = self.ep_square;
@@ -1513,6 +1986,11 @@ impl Board {
         board.turn = self.turn;
         board.fullmove_number = self.fullmove_number;
         board.halfmove_clock = self.halfmove_clock;
+        board.chess960 = self.chess960;
+        board.variant = self.variant;
+        board.pockets = self.pockets;
+        board.remaining_checks = self.remaining_checks;
+        board.zobrist_key = self.zobrist_key;
         if copy_stack {
             board.move_stack = self.move_stack.to_owned();
             board.stack = self.stack.to_owned(); 
@@ -1521,70 +1999,149 @@ impl Board {
     }
     pub fn clear_board(&mut self) {
         self.baseboard.clear_board();
+        self.pockets = [CrazyhousePocket::new(); 2];
+        self.remaining_checks = [3, 3];
         self.clear_stack();
     }
     pub fn clear_stack(&mut self) {
         self.move_stack.clear();
         self.stack.clear();
+        // Every way of setting up a position ends here, so this is where
+        // the incrementally updated key gets computed from scratch.
+        self.zobrist_key = self.compute_zobrist_hash();
     }
     pub fn ply(&self) -> u64 {
         2 * (self.fullmove_number - 1) + (self.turn == BLACK) as u64
     }
-    pub fn find_move(&self, from_square: Square, to_square: Square, mut promotion: Option<PieceType>) -> Move {
-        if promotion.is_none() && self.baseboard.pawns != 0 
-            && BB_SQUARES[from_square as usize] != 0 && BB_SQUARES[to_square as usize] != 0 && BB_BACKRANKS != 0 {
+    /// Applies `f` to the pieces, castling rights and en passant square.
+    /// Only flips that keep the ranks preserve the meaning of castling
+    /// rights and pawns. Clears the move stack.
+    pub fn apply_transform(&mut self, f: fn(Bitboard) -> Bitboard) {
+        self.baseboard.apply_transform(f);
+        self.ep_square = self.ep_square.map(|square| msb(f(BB_SQUARES[square as usize])));
+        self.castling_rights = f(self.castling_rights);
+        self.clear_stack();
+    }
+    pub fn transform(&self, f: fn(Bitboard) -> Bitboard) -> Board {
+        let mut board = self.copy(false);
+        board.apply_transform(f);
+        board
+    }
+    /// Flips the board vertically and swaps the colors of the pieces, the
+    /// side to move, pockets and remaining checks, which gives the same
+    /// position from the other side. Clears the move stack.
+    pub fn apply_mirror(&mut self) {
+        self.baseboard.apply_mirror();
+        self.ep_square = self.ep_square.map(|square| square ^ 0x38);
+        self.castling_rights = flip_vertical(self.castling_rights);
+        self.turn = !self.turn;
+        self.pockets.swap(WHITE as usize, BLACK as usize);
+        self.remaining_checks.swap(WHITE as usize, BLACK as usize);
+        self.clear_stack();
+    }
+    pub fn mirror(&self) -> Board {
+        let mut board = self.copy(false);
+        board.apply_mirror();
+        board
+    }
+    /// One representative of all positions equivalent to this one by
+    /// symmetry: the one with the smallest FEN. Files may be flipped
+    /// without castling rights, the board may be rotated and flipped
+    /// freely without pawns either, and colors may be mirrored if the
+    /// variant allows it.
+    pub fn canonical(&self) -> Board {
+        let mut transforms: Vec<fn(Bitboard) -> Bitboard> = vec![|bb| bb];
+        if self.clean_castling_rights() == 0 {
+            transforms.push(flip_horizontal);
+            let no_pawns = self.baseboard.pawns == 0
+                && self.pockets.iter().all(|pocket| pocket.count(PAWN) == 0);
+            if no_pawns && self.variant.has_symmetric_rules() {
+                let rotations: [fn(Bitboard) -> Bitboard; 6] = [
+                    flip_vertical,
+                    flip_diagonal,
+                    flip_anti_diagonal,
+                    |bb| flip_vertical(flip_horizontal(bb)),
+                    |bb| flip_vertical(flip_diagonal(bb)),
+                    |bb| flip_horizontal(flip_diagonal(bb)),
+                ];
+                transforms.extend(rotations);
+            }
+        }
+        let mut candidates: Vec<Board> = transforms.iter().map(|&f| self.transform(f)).collect();
+        if self.variant.has_symmetric_rules() {
+            let mirrored: Vec<Board> = candidates.iter().map(|board| board.mirror()).collect();
+            candidates.extend(mirrored);
+        }
+        candidates.into_iter().min_by_key(|board| board.fen(false)).unwrap()
+    }
+    pub fn find_move(&self, from_square: Square, to_square: Square, promotion: Option<PieceType>) -> Move {
+        match self.try_find_move(from_square, to_square, promotion) {
+            Some(m) => m,
+            None => panic!("No matching legal move for {}{} in {}", square_name(from_square), square_name(to_square), self.fen(false)),
+        }
+    }
+    /// Finds a legal move between the given squares, promoting pawns on
+    /// the backrank to a queen unless another piece is given.
+    pub fn try_find_move(&self, from_square: Square, to_square: Square, mut promotion: Option<PieceType>) -> Option<Move> {
+        if promotion.is_none() && self.baseboard.pawns & BB_SQUARES[from_square as usize] != 0
+            && BB_SQUARES[to_square as usize] & BB_BACKRANKS != 0 {
                 promotion = Some(QUEEN);
         }
-        
-        let m = Move{from_square: from_square, to_square: to_square, promotion: promotion};
+
+        let m = Move{from_square, to_square, promotion, drop: None};
 
         if !self.is_legal(m) {
-            panic!("No matching legal move for {}", m.uci())
+            return None;
         }
-        m
-
+        Some(m)
     }
+    /// Parses a move in standard algebraic notation. Panics with the
+    /// `SanError` if the move is malformed, illegal or ambiguous, see
+    /// `try_parse_san`.
     pub fn parse_san(&self, san: &str) -> Move {
+        self.try_parse_san(san).unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Parses a move in standard algebraic notation in the context of the
+    /// current position, telling apart malformed, illegal and ambiguous input.
+    pub fn try_parse_san(&self, san: &str) -> Result<Move, SanError> {
+        let illegal = || SanError::IllegalSan { san: san.to_string(), fen: self.fen(false) };
         match san {
             "O-O"| "O-O+"| "O-O#"| "0-0"| "0-0+"| "0-0#" => {
-                let mut m_opt_iter = self.generate_castling_moves(BB_ALL, BB_ALL)
-                    .filter(|m| self.is_kingside_castling(*m));
-                if let Some(m) = m_opt_iter.next() {
-                    return m;
-                }
-                else {
-                    println!("illegal san: {}", san);
-                    return Move::null();
-                }
-                
+                return self.generate_castling_moves(BB_ALL, BB_ALL)
+                    .find(|m| self.is_kingside_castling(*m))
+                    .ok_or_else(illegal);
             },
             "O-O-O"| "O-O-O+"| "O-O-O#"| "0-0-0"| "0-0-0+"| "0-0-0#" => {
-                let mut m_opt_iter = self.generate_castling_moves(BB_ALL, BB_ALL)
-                    .filter(|m| self.is_queenside_castling(*m));
-                if let Some(m) = m_opt_iter.next() {
-                    return m;
-                }
-                else {
-                    println!("illegal san: {}", san);
-                    return Move::null();
-                }
+                return self.generate_castling_moves(BB_ALL, BB_ALL)
+                    .find(|m| self.is_queenside_castling(*m))
+                    .ok_or_else(illegal);
+            }
+            "--" | "Z0" | "0000" | "@@@@" => {
+                return Ok(Move::null());
             }
             _ => {}
         }
-
-        let re_match_opt = SAN_REGEX.captures(san);
-
-        if re_match_opt.is_none() {
-            match san {
-                "--" | "Z0" | "0000" | "@@@@" => { return Move::null();},
-                _ => { panic!("invlaid san: {}", san);}
+        if san.contains('@') {
+            let uci = san.trim_end_matches(['+', '#']);
+            let uci = if uci.starts_with('@') { format!("P{}", uci) } else { uci.to_string() };
+            let m = Move::try_from_uci(&uci).map_err(|_| SanError::InvalidSan(san.to_string()))?;
+            if m.drop.is_none() {
+                return Err(SanError::InvalidSan(san.to_string()));
             }
+            return if self.is_legal(m) { Ok(m) } else { Err(illegal()) };
         }
-        let re_match = re_match_opt.unwrap();
+
+        let re_match = match SAN_REGEX.captures(san) {
+            Some(re_match) => re_match,
+            None => return Err(SanError::InvalidSan(san.to_string())),
+        };
         let to_square = parse_square(&re_match[4]);
         let to_mask = BB_SQUARES[to_square as usize] & !self.baseboard.occupied_co[self.turn as usize];
         let p = re_match.get(5).map_or("", |x|x.as_str());
         let promotion = if p.is_empty() { None } else {piece_type(p.to_lowercase().chars().last())};
+        if promotion == Some(PAWN) {
+            return Err(illegal());
+        }
         let mut from_file = 0;
         let mut from_rank = 0;
         let mut from_mask = BB_ALL;
@@ -1602,47 +2159,118 @@ impl Board {
             from_mask &= self.baseboard.pieces_mask(piece_type.unwrap(), self.turn);
         }
         else if re_match.get(2).is_some() && re_match.get(3).is_some() {
-            let m = self.find_move(square(from_file, from_rank), to_square, None);
-            if m.promotion == promotion {
-                return m
+            // Fully specified moves, including castling moves.
+            return match self.try_find_move(square(from_file, from_rank), to_square, promotion) {
+                Some(m) if m.promotion == promotion => Ok(m),
+                _ => Err(illegal()),
+            };
+        }
+        else {
+            from_mask &= self.baseboard.pawns;
+            // Pawn captures must name the file they are made from.
+            if re_match.get(2).is_none() {
+                from_mask &= BB_FILES[square_file(to_square) as usize];
             }
-            else { println!("missing promotion piece type {} in {}", san, self.baseboard.board_fen(true)); return Move::null()}
         }
-        else if re_match.get(5).is_some() && re_match.get(2).is_none() && re_match.get(3).is_none() && re_match.get(4).is_some(){
-            let mut chars = re_match.get(4).unwrap().as_str().chars();
-            let file = parse_file_name(chars.next().unwrap());
-            let mut rank = parse_rank_name(chars.next().unwrap());
-            if self.turn { rank -= 1} else {rank += 1}
 
-            let m = self.find_move(square(file, rank), to_square, promotion);
-            if m.promotion == promotion {
-                return m;
+        let candidates = self.generate_legal_moves(from_mask, to_mask)
+            .filter(|m| m.promotion == promotion)
+            .collect::<Vec<Move>>();
+        match candidates.len() {
+            0 => Err(illegal()),
+            1 => Ok(candidates[0]),
+            _ => Err(SanError::AmbiguousSan { san: san.to_string(), fen: self.fen(false), candidates }),
+        }
+    }
+    /// Parses a move in UCI notation and checks that it is legal in the
+    /// current position. Standard castling notation (e1g1) is accepted.
+    pub fn parse_uci(&self, uci: &str) -> Result<Move, MoveError> {
+        let m = self.to_chess960(Move::try_from_uci(uci)?);
+        if m.bool() && !self.is_legal(m) {
+            return Err(MoveError::IllegalMove { uci: uci.to_string(), fen: self.fen(false) });
+        }
+        Ok(m)
+    }
+    /// UCI notation of a move in this position. Castling is written king
+    /// takes rook in Chess960 mode and as a two square king move otherwise.
+    pub fn uci(&self, m: Move) -> String {
+        self.from_chess960(m).uci()
+    }
+    /// Converts the king takes rook encoding of standard castling moves
+    /// back to standard notation, unless in Chess960 mode.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn from_chess960(&self, m: Move) -> Move {
+        if self.chess960 || m.promotion.is_some() {
+            return m;
+        }
+        let (king, kingside, queenside) = if m.from_square == E1 {
+            (BB_E1, (H1, G1), (A1, C1))
+        } else if m.from_square == E8 {
+            (BB_E8, (H8, G8), (A8, C8))
+        } else {
+            return m;
+        };
+        if self.baseboard.kings & king == 0 {
+            return m;
+        }
+        for (rook, king_to) in [kingside, queenside] {
+            if m.to_square == rook {
+                return Move { from_square: m.from_square, to_square: king_to, promotion: None, drop: None };
             }
-            else { println!("missing promotion piece type {} in {}", san, self.baseboard.board_fen(true)); return Move::null()}
         }
-        else {
-            from_mask &= self.baseboard.pawns
+        m
+    }
+    /// Converts standard castling notation, where the king moves two
+    /// squares, to the king takes rook encoding used internally. Chess960
+    /// mode expects king takes rook already.
+    pub fn to_chess960(&self, m: Move) -> Move {
+        let (king, kingside, queenside) = if m.from_square == E1 {
+            (BB_E1, (G1, H1), (C1, A1))
+        } else if m.from_square == E8 {
+            (BB_E8, (G8, H8), (C8, A8))
+        } else {
+            return m;
+        };
+        if self.chess960 || self.baseboard.kings & king == 0 || m.promotion.is_some() {
+            return m;
         }
-
-        let mut matched_move = None;
-        for m in self.generate_legal_moves(from_mask, to_mask) {
-            if m.promotion != promotion { continue; }
-
-            if matched_move.is_some() {
-                println!("fen: {}", self.fen(false));  println!("ambiguous san: {}", san); return Move::null();
+        for (king_to, rook) in [kingside, queenside] {
+            if m.to_square == king_to && self.baseboard.rooks & BB_SQUARES[king_to as usize] == 0 {
+                return Move { from_square: m.from_square, to_square: rook, promotion: None, drop: None };
             }
-
-            matched_move = Some(m);
-
-            if !matched_move.unwrap().bool() {
-                println!("illegal san: {}", san);
-                return Move::null();
-            } 
         }
-        match matched_move {
-            Some(m) => {m},
-            None => {println!("fen: {}", self.baseboard.board_fen(false)); println!("invalid move: {}", san); Move::null()}
+        m
+    }
+    pub fn push_uci(&mut self, uci: &str) -> Result<Move, MoveError> {
+        let m = self.parse_uci(uci)?;
+        self.push(m);
+        Ok(m)
+    }
+    /// Puts a piece into a pocket from outside the game, as when a
+    /// Bughouse partner passes on a capture.
+    pub fn add_to_pocket(&mut self, color: Color, piece_type: PieceType) {
+        self.zobrist_key ^= zobrist_pocket_key(&self.pockets[color as usize], color);
+        self.pockets[color as usize].add(piece_type);
+        self.zobrist_key ^= zobrist_pocket_key(&self.pockets[color as usize], color);
+    }
+    pub fn remove_from_pocket(&mut self, color: Color, piece_type: PieceType) {
+        self.zobrist_key ^= zobrist_pocket_key(&self.pockets[color as usize], color);
+        self.pockets[color as usize].remove(piece_type);
+        self.zobrist_key ^= zobrist_pocket_key(&self.pockets[color as usize], color);
+    }
+    pub fn push_san(&mut self, san: &str) -> Result<Move, SanError> {
+        let m = self.try_parse_san(san)?;
+        self.push(m);
+        Ok(m)
+    }
+    /// Like `push`, but refuses moves that are not legal in the current
+    /// position instead of corrupting the board.
+    pub fn push_checked(&mut self, m: Move) -> Result<(), MoveError> {
+        if m.bool() && !self.is_legal(m) {
+            return Err(MoveError::IllegalMove { uci: self.uci(m), fen: self.fen(false) });
         }
+        self.push(m);
+        Ok(())
     }
     pub fn remove_piece_at(&mut self, square: Square) -> Option<Piece> {
         let piece = self.baseboard.remove_piece_at(square);
@@ -1653,7 +2281,10 @@ impl Board {
         self.baseboard.set_piece_at(square, piece, promoted);
         self.clear_stack();
     }
-    pub fn generate_pseudo_legal_moves(
+    pub fn generate_pseudo_legal_moves(&self, from_mask: Bitboard, to_mask: Bitboard) -> Box<dyn Iterator<Item = Move> + '_> {
+        self.variant.generate_pseudo_legal_moves(self, from_mask, to_mask)
+    }
+    pub fn generate_standard_pseudo_legal_moves(
         &self,
         from_mask: Bitboard,
         to_mask: Bitboard,
@@ -1669,6 +2300,7 @@ impl Board {
                         from_square: from_square as Square,
                         to_square: to_square as Square,
                         promotion: None,
+                        drop: None,
                     }
                 }
             }
@@ -1693,21 +2325,25 @@ impl Board {
                             from_square: from_square as Square,
                             to_square: to_square as Square,
                             promotion: Some(QUEEN),
+                            drop: None,
                         };
                         yield Move {
                             from_square: from_square as Square,
                             to_square: to_square as Square,
                             promotion: Some(ROOK),
+                            drop: None,
                         };
                         yield Move {
                             from_square: from_square as Square,
                             to_square: to_square as Square,
                             promotion: Some(BISHOP),
+                            drop: None,
                         };
                         yield Move {
                             from_square: from_square as Square,
                             to_square: to_square as Square,
                             promotion: Some(KNIGHT),
+                            drop: None,
                         };
                     }
                     else {
@@ -1715,6 +2351,7 @@ impl Board {
                             from_square: from_square as Square,
                             to_square: to_square as Square,
                             promotion: None,
+                            drop: None,
                         };
                     }
                     
@@ -1737,28 +2374,32 @@ impl Board {
                 let from_square = to_square.wrapping_add(if self.turn == BLACK {
                     8
                 } else {
-                    (8 as u8).wrapping_neg()
+                    8_u8.wrapping_neg()
                 });
                 if square_rank(to_square) == 0 || square_rank(to_square) == 7 {
                     yield Move {
                         from_square: from_square as Square,
                         to_square: to_square as Square,
                         promotion: Some(QUEEN),
+                        drop: None,
                     };
                     yield Move {
                         from_square: from_square as Square,
                         to_square: to_square as Square,
                         promotion: Some(ROOK),
+                        drop: None,
                     };
                     yield Move {
                         from_square: from_square as Square,
                         to_square: to_square as Square,
                         promotion: Some(BISHOP),
+                        drop: None,
                     };
                     yield Move {
                         from_square: from_square as Square,
                         to_square: to_square as Square,
                         promotion: Some(KNIGHT),
+                        drop: None,
                     };
                 }
                 else {
@@ -1766,6 +2407,7 @@ impl Board {
                         from_square: from_square as Square,
                         to_square: to_square as Square,
                         promotion: None,
+                        drop: None,
                     };
                 }
                 
@@ -1775,15 +2417,16 @@ impl Board {
                 let from_square = to_square.wrapping_add(if self.turn == BLACK {
                     16
                 } else {
-                    (16 as u8).wrapping_neg()
+                    16_u8.wrapping_neg()
                 });
                 yield Move {
                     from_square: from_square as Square,
                     to_square: to_square as Square,
                     promotion: None,
+                    drop: None,
                 }
             }
-            if self.ep_square != None {
+            if self.ep_square.is_some() {
                 for ep in self.generate_pseudo_legal_ep(from_mask, to_mask) {
                     yield ep;
                 }
@@ -1791,22 +2434,30 @@ impl Board {
         })
     }
     pub fn fen(&self, promoted: bool) -> String {
-        let mut fen = self.baseboard.board_fen(promoted);
+        self.fen_with_castling(promoted, self.castling_xfen())
+    }
+    /// FEN with the castling rights given as rook files (`HAha`).
+    pub fn shredder_fen(&self, promoted: bool) -> String {
+        self.fen_with_castling(promoted, self.castling_shredder_fen())
+    }
+    fn fen_with_castling(&self, promoted: bool, castling: String) -> String {
+        let mut fen = self.baseboard.board_fen(promoted || self.variant.has_pockets());
+        if self.variant.has_pockets() {
+            fen.push('[');
+            fen.push_str(&self.pockets[WHITE as usize].fen().to_ascii_uppercase());
+            fen.push_str(&self.pockets[BLACK as usize].fen());
+            fen.push(']');
+        }
         fen.push(' ');
         if self.turn { fen.push('w') } else {fen.push('b')}
         fen.push(' ');
-        if self.has_castling_rights(WHITE) || self.has_castling_rights(BLACK){
-            if self.has_kingside_castling_rights(WHITE){ fen.push('K'); }
-            if self.has_queenside_castling_rights(WHITE){ fen.push('Q'); }
-            if self.has_kingside_castling_rights(BLACK){ fen.push('k'); }
-            if self.has_queenside_castling_rights(BLACK){ fen.push('q'); }
-        }
-        else {
-            fen.push('-');
-        }
+        fen.push_str(&castling);
         fen.push(' ');
         if self.has_legal_en_passant() {fen.push_str(SQUARE_NAMES[self.ep_square.unwrap() as usize] )} else {fen.push('-');}
         fen.push(' ');
+        if self.variant.has_check_counts() {
+            fen.push_str(&format!("{}+{} ", self.remaining_checks[WHITE as usize], self.remaining_checks[BLACK as usize]));
+        }
         fen.push_str(&self.halfmove_clock.to_string());
         fen.push(' ');
         fen.push_str(&self.fullmove_number.to_string());
@@ -1838,6 +2489,7 @@ impl Board {
                     from_square: capturer as Square,
                     to_square: self.ep_square.unwrap() as Square,
                     promotion: None,
+                    drop: None,
                 };
             }
         })
@@ -1863,6 +2515,9 @@ impl Board {
         SquareSet::new(self.checkers_mask())
     }
     pub fn is_check(&self) -> bool {
+        self.variant.is_check(self)
+    }
+    pub fn is_standard_check(&self) -> bool {
         self.checkers_mask() != 0
     }
     pub fn gives_check(&mut self, m: Move) -> bool {
@@ -1871,9 +2526,12 @@ impl Board {
         self.pop();
         retval
     }
-    pub fn is_into_check(&self, m: Move) -> bool{
+    pub fn is_into_check(&self, m: Move) -> bool {
+        self.variant.is_into_check(self, m)
+    }
+    pub fn is_standard_into_check(&self, m: Move) -> bool{
         let king = self.baseboard.king(self.turn);
-        if king == None {
+        if king.is_none() {
             return false;
         }
         let checkers = self.baseboard.attackers_mask(!self.turn, king.unwrap());
@@ -1883,15 +2541,21 @@ impl Board {
         !self.is_safe(king.unwrap(), self.slider_blockers(king.unwrap()), m)
     }
     pub fn was_into_check(&self) -> bool {
+        self.variant.was_into_check(self)
+    }
+    pub fn standard_was_into_check(&self) -> bool {
         let king = self.baseboard.king(!self.turn);
-        king != None && self.baseboard.is_attacked_by(self.turn, king.unwrap())
+        king.is_some() && self.baseboard.is_attacked_by(self.turn, king.unwrap())
     }
     pub fn is_pseudo_legal(&self, m: Move) -> bool {
-        if !m.bool() { return false; }
+        self.variant.is_pseudo_legal(self, m)
+    }
+    pub fn is_standard_pseudo_legal(&self, m: Move) -> bool {
+        if !m.bool() || m.drop.is_some() { return false; }
         
         let piece = self.baseboard.piece_type_at(m.from_square);
 
-        if piece == None {
+        if piece.is_none() {
             return false;
         }
 
@@ -1902,17 +2566,16 @@ impl Board {
             return false;
         }
 
-        if let Some(_) = m.promotion{
+        if m.promotion.is_some(){
             if piece.unwrap() != PAWN { return false; }
-            if self.turn == WHITE && square_rank(m.to_square) != 7 { return false; }
-            else if self.turn == BLACK && square_rank(m.to_square) != 0 { return false; }
+            let backrank = if self.turn == WHITE { 7 } else { 0 };
+            if square_rank(m.to_square) != backrank { return false; }
         }
 
-        if piece.unwrap() == KING {
-            if self.generate_castling_moves(BB_ALL, BB_ALL).any(|x| x == m) {
+        if piece.unwrap() == KING
+            && self.generate_castling_moves(BB_ALL, BB_ALL).any(|x| x == m) {
                 return true;
             }
-        }
 
         if self.baseboard.occupied_co[self.turn as usize] & to_mask != 0 {
             return false;
@@ -1925,15 +2588,18 @@ impl Board {
         self.baseboard.attacks_mask(m.from_square) & to_mask != 0
     }
     pub fn is_legal(&self, m: Move) -> bool {
+        self.variant.is_legal(self, m)
+    }
+    pub fn is_standard_legal(&self, m: Move) -> bool {
         !self.is_variant_end() && self.is_pseudo_legal(m) && !self.is_into_check(m)
     }
-    pub fn is_variant_end(&self) -> bool { false }
-    pub fn is_variant_loss(&self) -> bool { false }
-    pub fn is_variant_win(&self) -> bool { false }
-    pub fn is_variant_draw(&self) -> bool { false }
+    pub fn is_variant_end(&self) -> bool { self.variant.is_variant_end(self) }
+    pub fn is_variant_loss(&self) -> bool { self.variant.is_variant_loss(self) }
+    pub fn is_variant_win(&self) -> bool { self.variant.is_variant_win(self) }
+    pub fn is_variant_draw(&self) -> bool { self.variant.is_variant_draw(self) }
     
     pub fn is_game_over(&mut self, claim_draw: bool) -> bool {
-        if let Some(_) = self.outcome(claim_draw){ true } else { false }
+        self.outcome(claim_draw).is_some()
     }
     pub fn result(&mut self, claim_draw: bool) -> String {
         if let Some(outcome) = self.outcome(claim_draw) { 
@@ -1991,12 +2657,15 @@ impl Board {
         if self.is_check() { return false }
         if self.is_variant_end() { return false }
 
-        return !any(self.generate_legal_moves(BB_ALL, BB_ALL))
+        !any(self.generate_legal_moves(BB_ALL, BB_ALL))
     }
     pub fn is_insufficient_material(&self) -> bool {
-        return all(COLORS.map(|col| self.has_insufficient_material(col)));
+        all(COLORS.map(|col| self.has_insufficient_material(col)))
     }
     pub fn has_insufficient_material(&self, color: Color) -> bool {
+        self.variant.has_insufficient_material(self, color)
+    }
+    pub fn has_standard_insufficient_material(&self, color: Color) -> bool {
         if self.baseboard.occupied_co[color as usize] 
         & (self.baseboard.pawns | self.baseboard.rooks | self.baseboard.queens)
         != 0 {
@@ -2010,9 +2679,9 @@ impl Board {
         }
 
         if self.baseboard.occupied_co[color as usize] & self.baseboard.bishops != 0 {
-            let same_color = !self.baseboard.bishops & BB_DARK_SQUARES != 0
-                || !self.baseboard.bishops & BB_LIGHT_SQUARES != 0;
-            return same_color && !self.baseboard.pawns != 0 && !self.baseboard.knights != 0;
+            let same_color = self.baseboard.bishops & BB_DARK_SQUARES == 0
+                || self.baseboard.bishops & BB_LIGHT_SQUARES == 0;
+            return same_color && self.baseboard.pawns == 0 && self.baseboard.knights == 0;
         }
         true
     }
@@ -2022,7 +2691,7 @@ impl Board {
     pub fn is_seventyfive_moves(&self) -> bool {
         self.is_halfmoves(150)
     }
-    pub fn is_fivefold_repetition(&mut self) -> bool {
+    pub fn is_fivefold_repetition(&self) -> bool {
         self.is_repetition(5)
     }
     pub fn can_claim_draw(&mut self) -> bool {
@@ -2050,193 +2719,240 @@ impl Board {
         false
     }
     pub fn can_claim_threefold_repetition(&mut self) -> bool {
-        let transposition_key = self.transposition_key();
-        let mut transpositions = [[transposition_key]].iter().cloned().collect::<Counter<_>>();
-
-        let mut switchyard: Vec<Move> = Vec::new();
-
-        while !self.move_stack.is_empty() {
-            let m = self.pop();
-            switchyard.push(m);
-            if self.is_irreversible(m){
-                break;
-            }
-            let new_transposition = [[self.transposition_key()]].iter().cloned().collect::<Counter<_>>();
-            transpositions.extend(&new_transposition);
-        }
-
-        while !switchyard.is_empty() {
-            self.push(switchyard.pop().unwrap());
-        }
-
-        if transpositions[&[transposition_key]] >= 3 {
+        if self.is_repetition(3) {
             return true;
         }
-        let moves = self.generate_castling_moves(BB_ALL, BB_ALL).collect::<Vec<Move>>();
+        let moves = self.generate_legal_moves(BB_ALL, BB_ALL).collect::<Vec<Move>>();
         for m in moves {
+            if self.is_zeroing(m) {
+                continue;
+            }
             self.push(m);
-            let retval = transpositions[&[self.transposition_key()]] >= 2;
+            let retval = self.is_repetition(3);
             self.pop();
             if retval {
                 return true;
             }
         }
-        return false;
-
+        false
     }
-    pub fn is_repetition(&mut self, c: u32) -> bool {
-        let mut count = c;
-        let mut maybe_repetitions = 1;
+    pub fn is_repetition(&self, count: u32) -> bool {
+        let mut repetitions = 1;
+        // Zeroing moves can not be undone, so positions older than the
+        // halfmove clock can not repeat the current one.
+        let plies = min(self.halfmove_clock as usize, self.stack.len());
 
-        for state in self.stack.iter().rev() {
-            if state.occupied == self.baseboard.occupied {
-                maybe_repetitions += 1;
-                if maybe_repetitions >= count {
-                    break;
-                }
+        for state in self.stack.iter().rev().take(plies).skip(1).step_by(2) {
+            if state.zobrist_key == self.zobrist_key {
+                repetitions += 1;
             }
         }
-        if maybe_repetitions < count {
-            return false;
-        }
-        let transposition_key = self.transposition_key();
-        let mut retval = false;
-        let mut switchyard = Vec::new();
-        loop {
-            if count <= 1 {
-                retval = true;
-                break;
-            }
-            if self.move_stack.len() < (count - 1) as usize {
-                break;
-            }
-
-            let m = self.pop();
-            switchyard.push(m);
-
-            if self.is_irreversible(m) {
-                break;
-            }
-            if self.transposition_key() == transposition_key {
-                count -= 1;
-            }
-        }
-        while !switchyard.is_empty() {
-            self.push(switchyard.pop().unwrap());
-        }
-        retval
+        repetitions >= count
+    }
+    pub fn from_fen(fen: &str) -> Result<Board, FenError> {
+        let mut board = Board::new(None);
+        board.try_set_fen(fen)?;
+        Ok(board)
+    }
+    /// Creates a board set up in the starting position of `variant`.
+    pub fn from_variant(variant: &'static dyn Variant) -> Board {
+        let mut board = Board::new(None);
+        board.variant = variant;
+        board.reset();
+        board
+    }
+    pub fn from_variant_fen(variant: &'static dyn Variant, fen: &str) -> Result<Board, FenError> {
+        let mut board = Board::new(None);
+        board.variant = variant;
+        board.try_set_fen(fen)?;
+        Ok(board)
+    }
+    /// Like `from_fen`, but in Chess960 mode. The castling part may be
+    /// given in Shredder-FEN (`HAha`) or X-FEN (`KQkq`, with files only
+    /// where the outermost rook is not meant).
+    pub fn from_chess960_fen(fen: &str) -> Result<Board, FenError> {
+        let mut board = Board::new(None);
+        board.chess960 = true;
+        board.try_set_fen(fen)?;
+        Ok(board)
     }
     pub fn set_fen(&mut self, fen: &str) {
-        let mut parts = fen.split(' ').collect::<VecDeque<&str>>();
-        let board ;
-        if let Some(board_part) = parts.pop_front() {
-            board = board_part
-        } else {
-            panic!("Empty fen");
-        }
-
-        let turn;
-        if let Some(turn_part) = parts.pop_front() {
-            if turn_part == "w" {
-                turn = WHITE;
-            } else if turn_part == "b" {
-                turn = BLACK;
-            } else {
-                panic!("expected 'w' or 'b' for turn part of fen")
+        if let Err(e) = self.try_set_fen(fen) {
+            panic!("{} in fen {}", e, fen);
+        }
+    }
+    /// Parses a FEN and sets up the position. On error the board is left
+    /// untouched.
+    pub fn try_set_fen(&mut self, fen: &str) -> Result<(), FenError> {
+        let mut parts = VecDeque::new();
+        let mut offset = 0;
+        for part in fen.split(' ') {
+            parts.push_back((offset, part));
+            offset += part.len() + 1;
+        }
+
+        let mut baseboard = BaseBoard::new(None);
+        let mut pockets = [CrazyhousePocket::new(); 2];
+        match parts.pop_front() {
+            Some((_, "")) | None => return Err(FenError::Empty),
+            Some((_, board_part)) if self.variant.has_pockets() => {
+                let (board_part, pocket_offset, pocket_part) = Board::split_pocket_fen(board_part)?;
+                for (i, c) in pocket_part.char_indices() {
+                    match piece_type(Some(c.to_ascii_lowercase())) {
+                        Some(piece_type) if piece_type != KING => {
+                            pockets[c.is_ascii_uppercase() as usize].add(piece_type)
+                        }
+                        _ => return Err(FenError::Pocket { position: pocket_offset + i }),
+                    }
+                }
+                baseboard.try_set_board_fen(board_part)?
             }
-        } else {
-            turn = WHITE
+            Some((_, board_part)) => baseboard.try_set_board_fen(board_part)?,
         }
 
-        let castling ;
-        if let Some(castling_part) = parts.pop_front() {
-            castling = castling_part;
-            if !FEN_CASTLING_REGEX.is_match(castling_part) {
-                panic!("invalid castling part in fen {}", fen);
-            }
-        } else {
-            castling = "-";
+        let turn = match parts.pop_front() {
+            Some((_, "w")) | None => WHITE,
+            Some((_, "b")) => BLACK,
+            Some((position, _)) => return Err(FenError::Turn { position }),
+        };
+
+        let (castling_offset, castling) = parts.pop_front().unwrap_or((offset, "-"));
+        if let Some(i) = Board::invalid_castling_fen_char(castling) {
+            return Err(FenError::Castling { position: castling_offset + i });
         }
 
-        let ep_square: Option<u8>;
-        if let Some(ep_part) = parts.pop_front() {
-            if ep_part.len() != 1 {
-                panic!("invalid ep_part len {}", ep_part);
-            }
-            if let Some(sq) = ep_part.chars().nth(0) {
-                if sq == '-' {
-                    ep_square = None;
-                } else if 0x30 <= sq as u8 && 0x38 > sq as u8 {
-                    ep_square = Some(sq as u8 - 0x30);
-                } else {
-                    panic!("Invalid en passant square {}", sq);
+        let ep_square = match parts.pop_front() {
+            Some((_, "-")) | None => None,
+            Some((position, ep_part)) => {
+                if !SQUARE_NAMES.contains(&ep_part) {
+                    return Err(FenError::EnPassant { position });
                 }
-            } else {
-                panic!("ep_part is not a char");
+                Some(parse_square(ep_part))
             }
-        } else {
-            ep_square = None
-        }
+        };
 
-        let halfmove_clock;
-        if let Some(halfmove_part) = parts.pop_front() {
-            match halfmove_part.parse::<i64>() {
-                Ok(n) => {
-                    if n < 0 {
-                        panic!("halfmove_clock cannot be negative");
-                    }
-                    halfmove_clock = n;
-                }
-                Err(_e) => {
-                    panic!("invalid halfmove clock in fen: {}", fen);
+        let mut remaining_checks = [3, 3];
+        if self.variant.has_check_counts() {
+            if let Some(&(position, check_part)) = parts.front() {
+                if check_part.contains('+') && !check_part.starts_with('+') {
+                    parts.pop_front();
+                    remaining_checks = Board::parse_check_fen(check_part, false).ok_or(FenError::Checks { position })?;
                 }
             }
-        } else {
-            halfmove_clock = 0
         }
 
-        let fullmove_number;
-        if let Some(fullmove_part) = parts.pop_front() {
-            match fullmove_part.parse::<i64>() {
-                Ok(n) => {
-                    if n < 0 {
-                        panic!("fullmove_number cannot be negative");
-                    }
-                    fullmove_number = max(n, 1);
-                }
-                Err(_e) => {
-                    panic!("invalid halfmove clock in fen: {}", fen);
+        let halfmove_clock = match parts.pop_front() {
+            Some((position, halfmove_part)) => halfmove_part
+                .parse::<u64>()
+                .map_err(|_| FenError::HalfmoveClock { position })?,
+            None => 0,
+        };
+
+        let fullmove_number = match parts.pop_front() {
+            Some((position, fullmove_part)) => max(fullmove_part
+                .parse::<u64>()
+                .map_err(|_| FenError::FullmoveNumber { position })?, 1),
+            None => 1,
+        };
+
+        if self.variant.has_check_counts() {
+            if let Some(&(position, check_part)) = parts.front() {
+                if check_part.starts_with('+') {
+                    parts.pop_front();
+                    remaining_checks = Board::parse_check_fen(check_part, true).ok_or(FenError::Checks { position })?;
                 }
             }
-        } else {
-            fullmove_number = 1
         }
 
-        if parts.len() != 0 {
-            panic!("fen string has more parts than expected: {}", fen);
+        if let Some((position, _)) = parts.pop_front() {
+            return Err(FenError::TooManyParts { position });
         }
 
-        self.baseboard.set_board_fen(board);
-
+        self.baseboard = baseboard;
+        self.pockets = pockets;
+        self.remaining_checks = remaining_checks;
         self.turn = turn;
         self._set_castling_fen(castling);
         self.ep_square = ep_square;
-        self.halfmove_clock = halfmove_clock as u64;
-        self.fullmove_number = fullmove_number as u64;
+        self.halfmove_clock = halfmove_clock;
+        self.fullmove_number = fullmove_number;
         self.clear_stack();
+        Ok(())
+    }
+    /// Splits the pocket off the board part of a Crazyhouse FEN, given
+    /// either in brackets (`...RNBQKBNR[Qn]`) or as a ninth rank
+    /// (`...RNBQKBNR/Qn`). Returns the board part, the offset of the
+    /// pocket and the pocket.
+    fn split_pocket_fen(board_part: &str) -> Result<(&str, usize, &str), FenError> {
+        if board_part.ends_with(']') {
+            return match board_part.find('[') {
+                Some(i) => Ok((&board_part[..i], i + 1, &board_part[i + 1..board_part.len() - 1])),
+                None => Err(FenError::Pocket { position: board_part.len() - 1 }),
+            };
+        }
+        if board_part.matches('/').count() == 8 {
+            let i = board_part.rfind('/').unwrap();
+            return Ok((&board_part[..i], i + 1, &board_part[i + 1..]));
+        }
+        Ok((board_part, board_part.len(), ""))
+    }
+    /// Parses the check counts of a Three-check FEN, white first. Without
+    /// `given` this is the checks each side has left, `2+3`, right after the
+    /// en passant square; this is the form `fen()` writes. With `given` it
+    /// is the lichess form after the fullmove number, `+1+0`, which counts
+    /// the checks each side has given so far and so runs the other way.
+    fn parse_check_fen(check_part: &str, given: bool) -> Option<[u8; 2]> {
+        let check_part = if given { check_part.strip_prefix('+')? } else { check_part };
+        let (white, black) = check_part.split_once('+')?;
+        let mut remaining_checks = [3, 3];
+        for (color, count) in [(WHITE, white), (BLACK, black)] {
+            let count = count.parse::<u8>().ok().filter(|&count| count <= 3)?;
+            remaining_checks[color as usize] = if given { 3 - count } else { count };
+        }
+        Some(remaining_checks)
+    }
+    /// Index of the first character that makes the castling part of a FEN
+    /// invalid, if any.
+    fn invalid_castling_fen_char(castling_fen: &str) -> Option<usize> {
+        if castling_fen == "-" {
+            return None;
+        }
+        if castling_fen.is_empty() {
+            return Some(0);
+        }
+        let mut white = 0;
+        let mut black = 0;
+        for (i, flag) in castling_fen.char_indices() {
+            if "KQABCDEFGH".contains(flag) && black == 0 && white < 2 {
+                white += 1;
+            } else if "kqabcdefgh".contains(flag) && black < 2 {
+                black += 1;
+            } else {
+                return Some(i);
+            }
+        }
+        None
+    }
+    pub fn try_set_castling_fen(&mut self, castling_fen: &str) -> Result<(), FenError> {
+        if let Some(position) = Board::invalid_castling_fen_char(castling_fen) {
+            return Err(FenError::Castling { position });
+        }
+        self._set_castling_fen(castling_fen);
+        self.clear_stack();
+        Ok(())
     }
     pub fn _set_castling_fen(&mut self, castling_fen: &str) {
         if castling_fen == "-" {
             self.castling_rights = BB_EMPTY;
             return;
         }
-        if !FEN_CASTLING_REGEX.is_match(castling_fen) {
-            panic!("invalid castling fen {}", castling_fen);
+        if let Some(position) = Board::invalid_castling_fen_char(castling_fen) {
+            panic!("{} in castling fen {}", FenError::Castling { position }, castling_fen);
         }
 
         self.castling_rights = BB_EMPTY;
 
-        for flag in castling_fen.chars().into_iter() {
+        for flag in castling_fen.chars() {
             let color = if flag.is_ascii_uppercase() {
                 WHITE
             } else {
@@ -2249,35 +2965,78 @@ impl Board {
             let king = self.baseboard.king(color);
 
             if flag == 'q' {
-                if king != None && lsb(rooks) < king.unwrap() {
+                if king.is_some() && rooks != 0 && lsb(rooks) < king.unwrap() {
                     self.castling_rights |= rooks & rooks.wrapping_neg();
                 } else {
                     self.castling_rights |= BB_FILE_A & backrank;
                 }
             } else if flag == 'k' {
-                let rook = msb(rooks);
-                if king != None && king.unwrap() < rook as u8 {
-                    self.castling_rights |= BB_SQUARES[rook as usize];
+                if king.is_some() && rooks != 0 && king.unwrap() < msb(rooks) {
+                    self.castling_rights |= BB_SQUARES[msb(rooks) as usize];
                 } else {
                     self.castling_rights |= BB_FILE_H & backrank;
                 }
             } else {
-                self.castling_rights |= BB_FILES[(flag as u8 - 0x30) as usize] & backrank;
+                self.castling_rights |= BB_FILES[parse_file_name(flag) as usize] & backrank;
             }
         }
     }
+    /// Castling part of the FEN in X-FEN notation: `K` and `Q` stand for
+    /// the outermost rooks, files are only used to tell inner rooks apart.
+    pub fn castling_xfen(&self) -> String {
+        let mut castling = String::new();
+        for color in [WHITE, BLACK] {
+            let king = match self.baseboard.king(color) {
+                Some(king) => king,
+                None => continue,
+            };
+            let backrank = if color == WHITE { BB_RANK_1 } else { BB_RANK_8 };
+            for rook in scan_reversed(self.clean_castling_rights() & backrank) {
+                let a_side = square_file(rook) < square_file(king);
+                let other_rooks = self.baseboard.occupied_co[color as usize] & self.baseboard.rooks & backrank & !BB_SQUARES[rook as usize];
+                let flag = if scan_reversed(other_rooks).any(|other| (square_file(other) < square_file(rook)) == a_side) {
+                    FILE_NAMES[square_file(rook) as usize]
+                } else if a_side {
+                    'q'
+                } else {
+                    'k'
+                };
+                castling.push(if color == WHITE { flag.to_ascii_uppercase() } else { flag });
+            }
+        }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+        castling
+    }
+    /// Castling part of the FEN in Shredder-FEN notation, one file per rook.
+    pub fn castling_shredder_fen(&self) -> String {
+        let castling_rights = self.clean_castling_rights();
+        if castling_rights == 0 {
+            return String::from("-");
+        }
+        let mut castling = String::new();
+        for square in scan_reversed(castling_rights & BB_RANK_1) {
+            castling.push(FILE_NAMES[square_file(square) as usize].to_ascii_uppercase());
+        }
+        for square in scan_reversed(castling_rights & BB_RANK_8) {
+            castling.push(FILE_NAMES[square_file(square) as usize]);
+        }
+        castling
+    }
     pub fn set_castling_fen(&mut self, castling_fen: &str) {
         self._set_castling_fen(castling_fen);
         self.clear_stack();
     }
-    pub fn board_state(&mut self) -> BoardState {
-        BoardState::new(self.copy(false))
-    }
     pub fn push(&mut self, m: Move) {
-        let board_state = self.board_state();
+        let mut board_state = BoardState::new(self);
+        let modifies_board = m.bool() && self.variant.push_modifies_board(self, m);
+        if modifies_board {
+            board_state.baseboard = Some(Box::new(self.baseboard));
+        }
+        let mut zobrist_key = self.zobrist_key ^ self.zobrist_state_key();
         self.castling_rights = self.clean_castling_rights();
         self.move_stack.push(m);
-        self.stack.push(board_state);
 
         let ep_square = self.ep_square;
         self.ep_square = None;
@@ -2287,16 +3046,28 @@ impl Board {
             self.fullmove_number += 1;
         }
         if !m.bool() {
+            self.stack.push(board_state);
             self.turn = !self.turn;
+            self.zobrist_key = zobrist_key ^ self.zobrist_state_key();
             return;
         }
         if self.is_zeroing(m) {
             self.halfmove_clock = 0;
         }
+        if let Some(drop) = m.drop {
+            self.stack.push(board_state);
+            self.pockets[self.turn as usize].remove(drop);
+            self.baseboard._set_piece_at(m.to_square, drop, self.turn, false);
+            zobrist_key ^= zobrist_piece_key(drop, self.turn, m.to_square);
+            self.turn = !self.turn;
+            self.zobrist_key = zobrist_key ^ self.zobrist_state_key();
+            return;
+        }
         let from_bb = BB_SQUARES[m.from_square as usize];
         let to_bb = BB_SQUARES[m.to_square as usize];
 
         let mut promoted = self.baseboard.promoted & from_bb != 0;
+        board_state.promoted = promoted;
         let mut piece_type = match self.baseboard._remove_piece_at(m.from_square) {
             Some(p) => p,
             None => {
@@ -2307,8 +3078,9 @@ impl Board {
                 )
             }
         };
+        zobrist_key ^= zobrist_piece_key(piece_type, self.turn, m.from_square);
         let mut capture_square = m.to_square;
-        let captured_piece_type = self.baseboard.piece_type_at(capture_square);
+        let mut captured_piece_type = self.baseboard.piece_type_at(capture_square);
 
         self.castling_rights &= !to_bb & !from_bb;
         if piece_type == KING && !promoted {
@@ -2321,7 +3093,7 @@ impl Board {
             if self.turn == WHITE && square_rank(m.to_square) == 7 {
                 self.castling_rights &= !BB_RANK_8;
             } else if self.turn == BLACK && square_rank(m.to_square) == 0 {
-                self.castling_rights &= BB_RANK_1;
+                self.castling_rights &= !BB_RANK_1;
             }
         }
 
@@ -2332,70 +3104,115 @@ impl Board {
                 self.ep_square = Some(m.from_square + 8);
             } else if diff == -16 && square_rank(m.from_square) == 6 {
                 self.ep_square = Some(m.from_square - 8);
-            } else if ep_square.is_some() && m.to_square == ep_square.unwrap()
+            } else if ep_square == Some(m.to_square)
                 && (diff.abs() == 7 || diff.abs() == 9)
                 && captured_piece_type.is_none() {
 
                 let down = if self.turn == WHITE {
-                    (8 as u8).wrapping_neg()
+                    8_u8.wrapping_neg()
                 } else {
                     8
                 };
-                capture_square = ep_square.unwrap().wrapping_add(down);
-                self.baseboard._remove_piece_at(capture_square);
+                capture_square = m.to_square.wrapping_add(down);
+                captured_piece_type = self.baseboard._remove_piece_at(capture_square);
+                zobrist_key ^= zobrist_piece_key(PAWN, !self.turn, capture_square);
             }
         }
 
-        if m.promotion != None {
+        if let Some(promotion) = m.promotion {
             promoted = true;
-            piece_type = m.promotion.unwrap();
+            piece_type = promotion;
         }
         let castling =
             piece_type == KING && ((self.baseboard.occupied_co[self.turn as usize] & to_bb) != 0);
+        board_state.captured = captured_piece_type;
+        board_state.capture_square = capture_square;
+        board_state.captured_promoted = self.baseboard.promoted & BB_SQUARES[capture_square as usize] != 0;
+        board_state.castling = castling;
+        self.stack.push(board_state);
         if castling {
             let a_side = square_file(m.to_square) < square_file(m.from_square);
 
             self.baseboard._remove_piece_at(m.from_square);
             self.baseboard._remove_piece_at(m.to_square);
+            zobrist_key ^= zobrist_piece_key(ROOK, self.turn, m.to_square);
 
-            if a_side {
-                self.baseboard._set_piece_at(
-                    if self.turn == WHITE { C1 } else { C8 },
-                    KING,
-                    self.turn,
-                    false,
-                );
-                self.baseboard._set_piece_at(
-                    if self.turn == WHITE { D1 } else { D8 },
-                    ROOK,
-                    self.turn,
-                    false,
-                );
+            let (king_to, rook_to) = if a_side {
+                (if self.turn == WHITE { C1 } else { C8 }, if self.turn == WHITE { D1 } else { D8 })
             } else {
-                self.baseboard._set_piece_at(
-                    if self.turn == WHITE { G1 } else { G8 },
-                    KING,
-                    self.turn,
-                    false,
-                );
-                self.baseboard._set_piece_at(
-                    if self.turn == WHITE { F1 } else { F8 },
-                    ROOK,
-                    self.turn,
-                    false,
-                );
-            }
+                (if self.turn == WHITE { G1 } else { G8 }, if self.turn == WHITE { F1 } else { F8 })
+            };
+            self.baseboard._set_piece_at(king_to, KING, self.turn, false);
+            self.baseboard._set_piece_at(rook_to, ROOK, self.turn, false);
+            zobrist_key ^= zobrist_piece_key(KING, self.turn, king_to)
+                ^ zobrist_piece_key(ROOK, self.turn, rook_to);
         }
         if !castling {
+            let was_promoted = self.baseboard.promoted & to_bb != 0;
+            if let Some(captured) = captured_piece_type {
+                if capture_square == m.to_square {
+                    zobrist_key ^= zobrist_piece_key(captured, !self.turn, m.to_square);
+                }
+            }
             self.baseboard
                 ._set_piece_at(m.to_square, piece_type, self.turn, promoted);
+            zobrist_key ^= zobrist_piece_key(piece_type, self.turn, m.to_square);
+            if let Some(captured) = captured_piece_type {
+                self.variant.push_capture(self, m, capture_square, captured, was_promoted);
+            }
         }
         self.turn = !self.turn;
+        if self.variant.has_check_counts() && self.is_check() {
+            let remaining_checks = &mut self.remaining_checks[!self.turn as usize];
+            *remaining_checks = remaining_checks.saturating_sub(1);
+        }
+        self.zobrist_key = if modifies_board {
+            self.compute_zobrist_hash()
+        } else {
+            zobrist_key ^ self.zobrist_state_key()
+        };
     }
+    /// Takes back the last move by reversing it on the board and restoring
+    /// the rest from its undo record.
     pub fn pop(&mut self) -> Move {
-        let m = self.move_stack.pop();
-        self.stack.pop().unwrap().restore(self);
-        m.unwrap()
+        let m = self.move_stack.pop().expect("pop() on empty move stack");
+        let board_state = self.stack.pop().unwrap();
+        self.turn = !self.turn;
+        let turn = self.turn;
+
+        if let Some(baseboard) = board_state.baseboard {
+            self.baseboard = *baseboard;
+        } else if let Some(drop) = m.drop {
+            self.baseboard._remove_piece_at(m.to_square);
+            self.pockets[turn as usize].add(drop);
+        } else if board_state.castling {
+            let a_side = square_file(m.to_square) < square_file(m.from_square);
+            let (king_to, rook_to) = if a_side {
+                (if turn == WHITE { C1 } else { C8 }, if turn == WHITE { D1 } else { D8 })
+            } else {
+                (if turn == WHITE { G1 } else { G8 }, if turn == WHITE { F1 } else { F8 })
+            };
+            self.baseboard._remove_piece_at(king_to);
+            self.baseboard._remove_piece_at(rook_to);
+            self.baseboard._set_piece_at(m.from_square, KING, turn, board_state.promoted);
+            self.baseboard._set_piece_at(m.to_square, ROOK, turn, board_state.captured_promoted);
+        } else if m.bool() {
+            let piece_type = self.baseboard._remove_piece_at(m.to_square).unwrap();
+            let piece_type = if m.promotion.is_some() { PAWN } else { piece_type };
+            self.baseboard._set_piece_at(m.from_square, piece_type, turn, board_state.promoted);
+            if let Some(captured) = board_state.captured {
+                self.baseboard._set_piece_at(board_state.capture_square, captured, !turn, board_state.captured_promoted);
+                self.variant.pop_capture(self, m, board_state.capture_square, captured, board_state.captured_promoted);
+            }
+        }
+
+        self.castling_rights = board_state.castling_rights;
+        self.ep_square = board_state.ep_square;
+        self.halfmove_clock = board_state.halfmove_clock;
+        self.fullmove_number = board_state.fullmove_number;
+        self.remaining_checks = board_state.remaining_checks;
+        self.zobrist_key = board_state.zobrist_key;
+        m
     }
     pub fn has_pseudo_legal_en_passant(&self) -> bool {
         self.ep_square.bool() && any(self.generate_pseudo_legal_ep(BB_ALL, BB_ALL)) 
@@ -2406,11 +3223,11 @@ impl Board {
     pub fn is_en_passant(&self, m: Move) -> bool {
         let diff = m.to_square as i16 - m.from_square as i16;
         if let Some(ep_square) = self.ep_square {
-            return ep_square == m.to_square && self.baseboard.pawns & BB_SQUARES[m.from_square as usize] != 0
-                && (diff == -7 || diff == 7 || diff == 9 || diff == -9) && self.baseboard.occupied & BB_SQUARES[m.to_square as usize] == 0;
+            ep_square == m.to_square && self.baseboard.pawns & BB_SQUARES[m.from_square as usize] != 0
+                && (diff == -7 || diff == 7 || diff == 9 || diff == -9) && self.baseboard.occupied & BB_SQUARES[m.to_square as usize] == 0
         }
         else {
-            return false
+            false
         }
     }
     pub fn is_capture(&self, m: Move) -> bool {
@@ -2421,6 +3238,94 @@ impl Board {
         let touched = BB_SQUARES[m.from_square as usize] ^ BB_SQUARES[m.to_square as usize];
         touched & self.baseboard.pawns != 0
             || touched & self.baseboard.occupied_co[!self.turn as usize] != 0
+            || m.drop == Some(PAWN)
+    }
+    /// Static exchange evaluation: the material balance in centipawns for
+    /// the side to move after `m` and the best sequence of recaptures on
+    /// its target square, where either side may stop capturing. Sliders
+    /// behind a capturer join in, pinned pieces only capture along their
+    /// pin while the pinner is still there, and the king never captures a
+    /// defended piece.
+    pub fn see(&self, m: Move) -> i32 {
+        if !m.bool() || m.drop.is_some() || self.is_castling(m) {
+            return 0;
+        }
+        let b = &self.baseboard;
+        let to_square = m.to_square;
+        let to_bb = BB_SQUARES[to_square as usize];
+        let mut occupied = b.occupied ^ BB_SQUARES[m.from_square as usize];
+
+        let mut gain = [0; 32];
+        let captured = if self.is_en_passant(m) {
+            let capture_square = if self.turn == WHITE { to_square - 8 } else { to_square + 8 };
+            occupied ^= BB_SQUARES[capture_square as usize];
+            Some(PAWN)
+        } else {
+            b.piece_type_at(to_square)
+        };
+        gain[0] = captured.map_or(0, |piece_type| SEE_PIECE_VALUES[piece_type as usize]);
+        let mut on_square = b.piece_type_at(m.from_square).unwrap();
+        if let Some(promotion) = m.promotion {
+            gain[0] += SEE_PIECE_VALUES[promotion as usize] - SEE_PIECE_VALUES[PAWN as usize];
+            on_square = promotion;
+        }
+
+        let pins = [self.see_pins(BLACK), self.see_pins(WHITE)];
+        let mut attackers = (b._attackers_mask(WHITE, to_square, occupied) | b._attackers_mask(BLACK, to_square, occupied)) & occupied;
+        let mut color = !self.turn;
+        let mut depth = 0;
+        loop {
+            let pinned = pins[color as usize]
+                .iter()
+                .filter(|&&(_, pinner, line)| occupied & BB_SQUARES[pinner as usize] != 0 && line & to_bb == 0)
+                .fold(0, |pinned, &(square, _, _)| pinned | BB_SQUARES[square as usize]);
+            let ours = attackers & b.occupied_co[color as usize] & !pinned;
+            let attacker = PIECE_TYPES.iter().find_map(|&piece_type| {
+                let bb = ours & b.pieces_mask(piece_type, color);
+                if bb != 0 { Some((lsb(bb), piece_type)) } else { None }
+            });
+            let (from_square, piece_type) = match attacker {
+                Some(attacker) => attacker,
+                None => break,
+            };
+            if piece_type == KING && attackers & b.occupied_co[!color as usize] != 0 {
+                break;
+            }
+            depth += 1;
+            gain[depth] = SEE_PIECE_VALUES[on_square as usize] - gain[depth - 1];
+            on_square = piece_type;
+            if piece_type == PAWN && to_bb & BB_BACKRANKS != 0 {
+                gain[depth] += SEE_PIECE_VALUES[QUEEN as usize] - SEE_PIECE_VALUES[PAWN as usize];
+                on_square = QUEEN;
+            }
+            occupied ^= BB_SQUARES[from_square as usize];
+            // Sliders on the line behind the capturer now see the square.
+            let x_rays = b._attackers_mask(WHITE, to_square, occupied) | b._attackers_mask(BLACK, to_square, occupied);
+            attackers = (attackers | (x_rays & BB_RAYS[to_square as usize][from_square as usize])) & occupied;
+            color = !color;
+        }
+        while depth > 0 {
+            gain[depth - 1] = -max(-gain[depth - 1], gain[depth]);
+            depth -= 1;
+        }
+        gain[0]
+    }
+    /// Whether the static exchange evaluation of `m` is at least
+    /// `threshold`.
+    pub fn see_ge(&self, m: Move, threshold: i32) -> bool {
+        self.see(m) >= threshold
+    }
+    /// Pieces of `color` pinned to their king, like `slider_blockers`,
+    /// with the pinning slider and the line between king and pinner.
+    fn see_pins(&self, color: Color) -> Vec<(Square, Square, Bitboard)> {
+        let king = match self.baseboard.king(color) {
+            Some(king) => king,
+            None => return Vec::new(),
+        };
+        self.slider_blockers_and_snipers(king, color)
+            .filter(|&(blocker, _)| BB_SQUARES[blocker as usize] & self.baseboard.occupied_co[color as usize] != 0)
+            .map(|(blocker, sniper)| (blocker, sniper, ray(king, sniper)))
+            .collect()
     }
     pub fn reduces_castling_rights(&self, m: Move) -> bool {
         let cr = self.clean_castling_rights();
@@ -2458,6 +3363,10 @@ impl Board {
             (castling & BB_RANK_1) & self.baseboard.occupied_co[WHITE as usize];
         let mut black_castling =
             (castling & BB_RANK_8) & self.baseboard.occupied_co[BLACK as usize];
+        if self.chess960 {
+            return self.clean_chess960_castling_rights(white_castling, BB_RANK_1, WHITE)
+                | self.clean_chess960_castling_rights(black_castling, BB_RANK_8, BLACK);
+        }
         white_castling &= BB_A1 | BB_H1;
         black_castling &= BB_A8 | BB_H8;
         if self.baseboard.occupied_co[WHITE as usize]
@@ -2478,16 +3387,34 @@ impl Board {
         }
         white_castling | black_castling
     }
+    /// In Chess960 the king may stand anywhere on the back rank, but it
+    /// castles with at most one rook on either side of it.
+    fn clean_chess960_castling_rights(&self, castling: Bitboard, backrank: Bitboard, color: Color) -> Bitboard {
+        let king_mask = self.baseboard.occupied_co[color as usize]
+            & self.baseboard.kings
+            & !self.baseboard.promoted
+            & backrank;
+        if king_mask == 0 || castling == 0 {
+            return BB_EMPTY;
+        }
+        let mut a_side = castling & castling.wrapping_neg();
+        let mut h_side = BB_SQUARES[msb(castling) as usize];
+        if a_side > king_mask {
+            a_side = BB_EMPTY;
+        }
+        if h_side < king_mask {
+            h_side = BB_EMPTY;
+        }
+        a_side | h_side
+    }
+    /// Checks if the given side has castling rights.
     pub fn has_castling_rights(&self, color: Color) -> bool {
-        "Checks if the given side has castling rights.";
         let backrank = if color == WHITE { BB_RANK_1 } else { BB_RANK_8 };
-        return self.clean_castling_rights() & backrank != 0;
+        self.clean_castling_rights() & backrank != 0
     }
+    /// Checks if the given side has kingside (that is h-side in Chess960)
+    /// castling rights.
     pub fn has_kingside_castling_rights(&self, color: Color) -> bool {
-        "
-        Checks if the given side has kingside (that is h-side in Chess960)
-        castling rights.
-        ";
         let backrank = if color == WHITE { BB_RANK_1 } else { BB_RANK_8 };
         let king_mask = ((self.baseboard.kings & self.baseboard.occupied_co[color as usize])
             & backrank)
@@ -2503,13 +3430,11 @@ impl Board {
             }
             castling_rights = castling_rights & (castling_rights - 1);
         }
-        return false;
+        false
     }
+    /// Checks if the given side has queenside (that is a-side in Chess960)
+    /// castling rights.
     pub fn has_queenside_castling_rights(&self, color: Color) -> bool {
-        "
-        Checks if the given side has queenside (that is a-side in Chess960)
-        castling rights.
-        ";
         let backrank = if color == WHITE { BB_RANK_1 } else { BB_RANK_8 };
         let king_mask = self.baseboard.kings
             & self.baseboard.occupied_co[color as usize]
@@ -2526,37 +3451,25 @@ impl Board {
             }
             castling_rights = castling_rights & (castling_rights - 1);
         }
-        return false;
+        false
     }
     pub fn status(&self) -> Status {
-        "
-        Gets a bitmask of possible problems with the position.
-    
-        :data:`~chess.STATUS_VALID` if all basic validity requirements are met.
-        This does not imply that the position is actually reachable with a
-        series of legal moves from the starting position.
-    
-        Otherwise, bitwise combinations of:
-        :data:`~chess.STATUS_NO_WHITE_KING`,
-        :data:`~chess.STATUS_NO_BLACK_KING`,
-        :data:`~chess.STATUS_TOO_MANY_KINGS`,
-        :data:`~chess.STATUS_TOO_MANY_WHITE_PAWNS`,
-        :data:`~chess.STATUS_TOO_MANY_BLACK_PAWNS`,
-        :data:`~chess.STATUS_PAWNS_ON_BACKRANK`,
-        :data:`~chess.STATUS_TOO_MANY_WHITE_PIECES`,
-        :data:`~chess.STATUS_TOO_MANY_BLACK_PIECES`,
-        :data:`~chess.STATUS_BAD_CASTLING_RIGHTS`,
-        :data:`~chess.STATUS_INVALID_EP_SQUARE`,
-        :data:`~chess.STATUS_OPPOSITE_CHECK`,
-        :data:`~chess.STATUS_EMPTY`,
-        :data:`~chess.STATUS_RACE_CHECK`,
-        :data:`~chess.STATUS_RACE_OVER`,
-        :data:`~chess.STATUS_RACE_MATERIAL`,
-        :data:`~chess.STATUS_TOO_MANY_CHECKERS`,
-        :data:`~chess.STATUS_IMPOSSIBLE_CHECK`.
-        ";
+        Status::to_enum(self.status_mask())
+    }
+    /// Bitmask of all `STATUS_*` problems with the position, as decided by
+    /// the variant.
+    pub fn status_mask(&self) -> u32 {
+        self.variant.status(self)
+    }
+    /// Gets a bitmask of possible problems with the position.
+    ///
+    /// `STATUS_VALID` if all basic validity requirements are met. This does
+    /// not imply that the position is actually reachable with a series of
+    /// legal moves from the starting position. Otherwise, a bitwise
+    /// combination of the other `STATUS_*` flags.
+    pub fn standard_status(&self) -> u32 {
         let mut errors = STATUS_VALID;
-        if !self.baseboard.occupied != 0 {
+        if self.baseboard.occupied == 0 {
             errors |= STATUS_EMPTY;
         }
         if self.baseboard.occupied_co[WHITE as usize] & self.baseboard.kings == 0 {
@@ -2601,7 +3514,7 @@ impl Board {
             if popcount(checkers) == 2 && ray(lsb(checkers), msb(checkers)) & our_kings != 0 {
                 errors |= STATUS_IMPOSSIBLE_CHECK;
             } else {
-                if valid_ep_square != None
+                if valid_ep_square.is_some()
                     && any(scan_reversed(checkers)
                         .map(|checker| ray(checker, valid_ep_square.unwrap()) & our_kings)
                         .collect::<Vec<_>>())
@@ -2610,12 +3523,10 @@ impl Board {
                 }
             }
         }
-        Status::to_enum(errors)
+        errors
     }
     pub fn valid_ep_square(&self) -> Option<Square> {
-        if self.ep_square == None {
-            return None;
-        }
+        self.ep_square?;
         let ep_rank;
         let pawn_mask;
         let seventh_rank_mask;
@@ -2640,20 +3551,16 @@ impl Board {
         if self.baseboard.occupied & seventh_rank_mask != 0 {
             return None;
         }
-        return self.ep_square;
+        self.ep_square
     }
+    /// Checks some basic validity requirements, see `status` for details.
     pub fn is_valid(&self) -> bool {
-        "
-        Checks some basic validity requirements.
-
-        See :func:`~chess.Board.status()` for details.
-        ";
-        return self.status() == Status::VALID;
+        self.status_mask() == STATUS_VALID
     }
     pub fn ep_skewered(&self, king: Square, capturer: Square) -> bool {
         let last_double = self.ep_square.unwrap().wrapping_add(
              if self.turn == WHITE {
-                (8 as u8).wrapping_neg()
+                8_u8.wrapping_neg()
             } else {
                 8
             });
@@ -2664,7 +3571,7 @@ impl Board {
 
         let horizontal_attackers = self.baseboard.occupied_co[!self.turn as usize]
             & (self.baseboard.rooks | self.baseboard.queens);
-        if BB_RANK_ATTACKS[king as usize][&(BB_RANK_MASKS[king as usize] & occupancy)]
+        if BB_RANK_ATTACKS[king as usize].get(BB_RANK_MASKS[king as usize] & occupancy)
             & horizontal_attackers
             != 0
         {
@@ -2672,44 +3579,53 @@ impl Board {
         }
         let diagonal_attackers = self.baseboard.occupied_co[!self.turn as usize]
             & (self.baseboard.bishops | self.baseboard.queens);
-        if (BB_DIAG_ATTACKS[king as usize][&(BB_DIAG_MASKS[king as usize] & occupancy)]
+        if (BB_DIAG_ATTACKS[king as usize].get(BB_DIAG_MASKS[king as usize] & occupancy)
             & diagonal_attackers)
             != 0
         {
             return true;
         }
-        return false;
+        false
     }
     pub fn slider_blockers(&self, king: Square) -> Bitboard {
-        let rooks_and_queens = self.baseboard.rooks | self.baseboard.queens;
-        let bishops_and_queens = self.baseboard.bishops | self.baseboard.queens;
-        let snipers = (BB_RANK_ATTACKS[king as usize][&0] & rooks_and_queens)
-            | (BB_FILE_ATTACKS[king as usize][&0] & rooks_and_queens)
-            | (BB_DIAG_ATTACKS[king as usize][&0] & bishops_and_queens);
         let mut blockers = 0;
-        for sniper in scan_reversed(snipers & self.baseboard.occupied_co[!self.turn as usize]) {
-            let b = between(king, sniper as Square) & self.baseboard.occupied;
-            if b != 0 && BB_SQUARES[msb(b) as usize] == b {
-                blockers |= b;
-            }
+        for (blocker, _) in self.slider_blockers_and_snipers(king, self.turn) {
+            blockers |= BB_SQUARES[blocker as usize];
         }
-        return blockers & self.baseboard.occupied_co[self.turn as usize];
+        blockers & self.baseboard.occupied_co[self.turn as usize]
+    }
+    /// Lone pieces of either color between the king of `color` on `king`
+    /// and an enemy slider aiming at it, as `(blocker, sniper)` pairs.
+    fn slider_blockers_and_snipers(&self, king: Square, color: Color) -> impl Iterator<Item = (Square, Square)> + '_ {
+        gen_iter!({
+            let rooks_and_queens = self.baseboard.rooks | self.baseboard.queens;
+            let bishops_and_queens = self.baseboard.bishops | self.baseboard.queens;
+            let snipers = (BB_RANK_ATTACKS[king as usize].empty() & rooks_and_queens)
+                | (BB_FILE_ATTACKS[king as usize].empty() & rooks_and_queens)
+                | (BB_DIAG_ATTACKS[king as usize].empty() & bishops_and_queens);
+            for sniper in scan_reversed(snipers & self.baseboard.occupied_co[!color as usize]) {
+                let b = between(king, sniper) & self.baseboard.occupied;
+                if b != 0 && BB_SQUARES[msb(b) as usize] == b {
+                    yield (msb(b), sniper);
+                }
+            }
+        })
     }
     pub fn is_safe(&self, king: Square, blockers: Bitboard, m: Move) -> bool {
         if m.from_square == king {
             if self.is_castling(m) {
-                return true;
+                true
             } else {
-                return !self.baseboard.is_attacked_by(!self.turn, m.to_square);
+                !self.baseboard.is_attacked_by(!self.turn, m.to_square)
             }
         } else {
             if self.is_en_passant(m) {
-                return self.baseboard.pin_mask(self.turn, m.from_square)
+                self.baseboard.pin_mask(self.turn, m.from_square)
                     & BB_SQUARES[m.to_square as usize] != 0
-                    && !self.ep_skewered(king, m.from_square);
+                    && !self.ep_skewered(king, m.from_square)
             } else {
-                return (blockers & BB_SQUARES[m.from_square as usize]) == 0
-                    || (ray(m.from_square, m.to_square) & BB_SQUARES[king as usize]) != 0;
+                (blockers & BB_SQUARES[m.from_square as usize]) == 0
+                    || (ray(m.from_square, m.to_square) & BB_SQUARES[king as usize]) != 0
             }
         }
     }
@@ -2727,19 +3643,19 @@ impl Board {
                 for to_square in scan_reversed(BB_KING_ATTACKS[king as usize] 
                     & !self.baseboard.occupied_co[self.turn as usize] & !attacked & to_mask){
 
-                    yield Move {from_square: king, to_square: to_square, promotion: None };
+                    yield Move {from_square: king, to_square, promotion: None, drop: None };
                 }
             }
             let checker = msb(checkers);
             if BB_SQUARES[checker as usize] == checkers {
                 let target = between(king, checker) | checkers;
 
-                for m in self.generate_pseudo_legal_moves(!self.baseboard.kings & from_mask, target & to_mask) {
+                for m in self.generate_standard_pseudo_legal_moves(!self.baseboard.kings & from_mask, target & to_mask) {
                     yield m;
                 }
 
-                if self.ep_square != None && !BB_SQUARES[self.ep_square.unwrap() as usize] & target != 0 {
-                    let last_double = self.ep_square.unwrap().wrapping_add(if self.turn == WHITE {(8 as u8).wrapping_neg()} else {8});
+                if self.ep_square.is_some() && !BB_SQUARES[self.ep_square.unwrap() as usize] & target != 0 {
+                    let last_double = self.ep_square.unwrap().wrapping_add(if self.turn == WHITE {8_u8.wrapping_neg()} else {8});
                     if last_double == checker {
                         for m in self.generate_pseudo_legal_ep(from_mask, to_mask) {
                             yield m;
@@ -2750,7 +3666,10 @@ impl Board {
         })
         
     }
-    pub fn generate_legal_moves(&self, from_mask: Bitboard, to_mask: Bitboard) -> impl Iterator<Item = Move> + '_{
+    pub fn generate_legal_moves(&self, from_mask: Bitboard, to_mask: Bitboard) -> Box<dyn Iterator<Item = Move> + '_> {
+        self.variant.generate_legal_moves(self, from_mask, to_mask)
+    }
+    pub fn generate_standard_legal_moves(&self, from_mask: Bitboard, to_mask: Bitboard) -> impl Iterator<Item = Move> + '_{
         gen_iter!({
             if self.is_variant_end() { return }
 
@@ -2768,7 +3687,7 @@ impl Board {
                     }
                 }
                 else {
-                    for m in self.generate_pseudo_legal_moves(from_mask, to_mask) {
+                    for m in self.generate_standard_pseudo_legal_moves(from_mask, to_mask) {
                         if self.is_safe(king, blockers, m){
                             yield m;
                         }
@@ -2776,7 +3695,7 @@ impl Board {
                 }
             }
             else {
-                for m in self.generate_pseudo_legal_moves(from_mask, to_mask) {
+                for m in self.generate_standard_pseudo_legal_moves(from_mask, to_mask) {
                     yield m;
                 }
             }
@@ -2801,8 +3720,42 @@ impl Board {
         )
     }
     pub fn attacked_for_king(&self, path: Bitboard, occupied: Bitboard) -> bool {
+        self.variant.attacked_for_king(self, path, occupied)
+    }
+    pub fn standard_attacked_for_king(&self, path: Bitboard, occupied: Bitboard) -> bool {
         any(scan_reversed(path).map(|sq| self.baseboard._attackers_mask(!self.turn, sq, occupied)))
     }
+    /// Squares where a piece can be dropped without leaving the king in
+    /// check: any empty square, or the squares that block a single checker.
+    pub fn legal_drop_squares_mask(&self) -> Bitboard {
+        let king = match self.baseboard.king(self.turn) {
+            Some(king) => king,
+            None => return !self.baseboard.occupied,
+        };
+        let king_attackers = self.baseboard.attackers_mask(!self.turn, king);
+        if king_attackers == 0 {
+            !self.baseboard.occupied
+        } else if popcount(king_attackers) == 1 {
+            between(king, msb(king_attackers)) & !self.baseboard.occupied
+        } else {
+            BB_EMPTY
+        }
+    }
+    pub fn generate_pseudo_legal_drops(&self, to_mask: Bitboard) -> impl Iterator<Item = Move> + '_{
+        gen_iter!({
+            for to_square in scan_forward(to_mask & !self.baseboard.occupied) {
+                for piece_type in [PAWN, KNIGHT, BISHOP, ROOK, QUEEN] {
+                    if self.pockets[self.turn as usize].count(piece_type) > 0
+                        && (piece_type != PAWN || BB_BACKRANKS & BB_SQUARES[to_square as usize] == 0) {
+                        yield Move { from_square: to_square, to_square, promotion: None, drop: Some(piece_type) };
+                    }
+                }
+            }
+        })
+    }
+    pub fn generate_legal_drops(&self, to_mask: Bitboard) -> impl Iterator<Item = Move> + '_{
+        self.generate_pseudo_legal_drops(self.legal_drop_squares_mask() & to_mask)
+    }
     pub fn generate_castling_moves(&self, from_mask: Bitboard, to_mask: Bitboard) -> impl Iterator<Item = Move> + '_{
         gen_iter!({
             if self.is_variant_end(){
@@ -2836,11 +3789,51 @@ impl Board {
                     || self.attacked_for_king(king_path | king, self.baseboard.occupied ^ king)
                     || self.attacked_for_king(king_to, self.baseboard.occupied ^ king ^ rook ^ rook_to)) {
 
-                        yield Move{from_square: msb(king), to_square: msb(rook), promotion: None};
+                        yield Move{from_square: msb(king), to_square: msb(rook), promotion: None, drop: None};
                     }
             }
         })
     }
+    pub fn zobrist_hash(&self) -> u64 {
+        self.zobrist_key
+    }
+    pub fn compute_zobrist_hash(&self) -> u64 {
+        let mut key = self.zobrist_state_key();
+        for square in scan_reversed(self.baseboard.occupied) {
+            let piece = self.baseboard.piece_at(square).unwrap();
+            key ^= zobrist_piece_key(piece.piece_type, piece.color, square);
+        }
+        key
+    }
+    fn zobrist_state_key(&self) -> u64 {
+        let mut key = 0;
+        if self.has_kingside_castling_rights(WHITE) {
+            key ^= ZOBRIST_KEYS[ZOBRIST_CASTLING_OFFSET];
+        }
+        if self.has_queenside_castling_rights(WHITE) {
+            key ^= ZOBRIST_KEYS[ZOBRIST_CASTLING_OFFSET + 1];
+        }
+        if self.has_kingside_castling_rights(BLACK) {
+            key ^= ZOBRIST_KEYS[ZOBRIST_CASTLING_OFFSET + 2];
+        }
+        if self.has_queenside_castling_rights(BLACK) {
+            key ^= ZOBRIST_KEYS[ZOBRIST_CASTLING_OFFSET + 3];
+        }
+        if self.has_pseudo_legal_en_passant() {
+            key ^= ZOBRIST_KEYS[ZOBRIST_EP_OFFSET + square_file(self.ep_square.unwrap()) as usize];
+        }
+        if self.turn == WHITE {
+            key ^= ZOBRIST_KEYS[ZOBRIST_TURN_OFFSET];
+        }
+        for color in COLORS {
+            let remaining_checks = self.remaining_checks[color as usize];
+            if remaining_checks < 3 {
+                key ^= ZOBRIST_EXTRA_KEYS.1[color as usize][remaining_checks as usize];
+            }
+        }
+        key ^ zobrist_pocket_key(&self.pockets[WHITE as usize], WHITE)
+            ^ zobrist_pocket_key(&self.pockets[BLACK as usize], BLACK)
+    }
     pub fn transposition_key(&self) -> Option<Transposition> {
         if self.has_legal_en_passant() {
             return None;
@@ -2864,7 +3857,7 @@ impl Board {
         let mut san = self.algebraic_without_suffix(m, long);
         self.push(m);
         let is_check = self.is_check();
-        let is_checkmate = self.is_check() && self.is_checkmate();
+        let is_checkmate = (is_check && self.is_checkmate()) || self.is_variant_loss() || self.is_variant_draw();
         
         if is_checkmate && m.bool() {
             san.push('#');
@@ -2878,12 +3871,21 @@ impl Board {
         if ! m.bool() {
             return String::from("--");
         }
+        if let Some(drop) = m.drop {
+            let mut san = String::new();
+            if drop != PAWN {
+                san.push(piece_symbol(drop).unwrap().to_ascii_uppercase());
+            }
+            san.push('@');
+            san.push_str(SQUARE_NAMES[m.to_square as usize]);
+            return san;
+        }
         if self.is_castling(m) {
             if square_file(m.to_square) < square_file(m.from_square) {
-                return String::from("0-0-0");
+                return String::from("O-O-O");
             }
             else {
-                return String::from("0-0");
+                return String::from("O-O");
             }
         }
 
@@ -2966,6 +3968,37 @@ impl Board {
         }
         san.join(" ")
     }
+    /// Counts the leaf nodes of the legal move tree `depth` plies deep.
+    pub fn perft(&mut self, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let moves = self.generate_legal_moves(BB_ALL, BB_ALL).collect::<Vec<Move>>();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        let mut nodes = 0;
+        for m in moves {
+            self.push(m);
+            nodes += self.perft(depth - 1);
+            self.pop();
+        }
+        nodes
+    }
+    /// Same as `perft`, but split by root move.
+    pub fn divide(&mut self, depth: u32) -> Vec<(Move, u64)> {
+        if depth == 0 {
+            return Vec::new();
+        }
+        let moves = self.generate_legal_moves(BB_ALL, BB_ALL).collect::<Vec<Move>>();
+        let mut result = Vec::new();
+        for m in moves {
+            self.push(m);
+            result.push((m, self.perft(depth - 1)));
+            self.pop();
+        }
+        result
+    }
 }
 #[derive(Hash, PartialEq, Eq, Clone, Copy)]
 pub struct Transposition {
@@ -2987,10 +4020,11 @@ pub struct LegalMoveGenerator {
 }
 impl LegalMoveGenerator {
     fn new(board: Board) -> LegalMoveGenerator {
-        LegalMoveGenerator { board: board }
+        LegalMoveGenerator { board }
     }
 }
 pub trait IntoSquareSet {
+    #[allow(clippy::wrong_self_convention)]
     fn into_square_set(&self) -> SquareSet;
 }
 macro_rules! add_into_square_set_for_arrays {
@@ -3001,7 +4035,7 @@ macro_rules! add_into_square_set_for_arrays {
                 for square in self.into_iter() {
                     mask |= BB_SQUARES[*square as usize];
                 }
-                SquareSet { mask: mask }
+                SquareSet { mask }
             }
         }
     };
@@ -3014,7 +4048,7 @@ macro_rules! add_into_square_set_for_iterables {
                 for square in self {
                     mask |= BB_SQUARES[*square as usize];
                 }
-                SquareSet { mask: mask }
+                SquareSet { mask }
             }
         }
     };
@@ -3033,7 +4067,7 @@ impl IntoSquareSet for Square {
 impl IntoSquareSet for u64 {
     fn into_square_set(&self) -> SquareSet {
         SquareSet {
-            mask: *self as u64 & BB_ALL,
+            mask: *self & BB_ALL,
         }
     }
 }
@@ -3190,7 +4224,7 @@ overload_squareset_operator!(
     BitOr,
     fn bitor(self, rhs: T) -> Self::Output {
         let mut r = rhs.into_square_set();
-        r.mask = r.mask | self.mask;
+        r.mask |= self.mask;
         r
     }
 );
@@ -3198,7 +4232,7 @@ overload_squareset_operator!(
     BitAnd,
     fn bitand(self, rhs: T) -> Self::Output {
         let mut r = rhs.into_square_set();
-        r.mask = r.mask + self.mask;
+        r.mask &= self.mask;
         r
     }
 );
@@ -3206,7 +4240,7 @@ overload_squareset_operator!(
     BitXor,
     fn bitxor(self, rhs: T) -> Self::Output {
         let mut r = rhs.into_square_set();
-        r.mask = r.mask ^ self.mask;
+        r.mask ^= self.mask;
         r
     }
 );
@@ -3247,4 +4281,353 @@ impl std::ops::Not for SquareSet {
     fn not(self) -> Self::Output {
         SquareSet { mask: !self.mask }
     }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variant::{CRAZYHOUSE, THREE_CHECK};
+
+    fn board_after(sans: &[&str]) -> Board {
+        let mut board = Board::new(Some(STARTING_FEN));
+        for san in sans {
+            board.push_san(san).unwrap();
+        }
+        board
+    }
+
+    #[test]
+    fn zobrist_start_position_matches_polyglot() {
+        assert_eq!(Board::new(Some(STARTING_FEN)).zobrist_hash(), 0x463b_9618_1691_fc9c);
+    }
+
+    #[test]
+    fn zobrist_matches_polyglot_reference_positions() {
+        let cases: [(&[&str], u64); 8] = [
+            (&["e4"], 0x823c_9b50_fd11_4196),
+            (&["e4", "d5"], 0x0756_b944_61c5_0fb0),
+            (&["e4", "d5", "e5"], 0x662f_afb9_65db_29d4),
+            (&["e4", "d5", "e5", "f5"], 0x22a4_8b5a_8e47_ff78),
+            (&["e4", "d5", "e5", "f5", "Ke2"], 0x652a_607c_a3f2_42c1),
+            (&["e4", "d5", "e5", "f5", "Ke2", "Kf7"], 0x00fd_d303_c946_bdd9),
+            (&["a4", "b5", "h4", "b4", "c4"], 0x3c81_23ea_7b06_7637),
+            (&["a4", "b5", "h4", "b4", "c4", "bxc3", "Ra3"], 0x5c3f_9b82_9b27_9560),
+        ];
+        for (sans, key) in cases {
+            let board = board_after(sans);
+            assert_eq!(board.zobrist_hash(), key, "{:?}", sans);
+            assert_eq!(board.compute_zobrist_hash(), key, "{:?}", sans);
+        }
+    }
+
+    #[test]
+    fn zobrist_incremental_key_matches_recompute() {
+        // Castling, en passant, captures and a capturing promotion.
+        let fen = "r3k2r/1P3ppp/8/3pP3/8/8/6PP/R3K2R w KQkq d6 0 1";
+        let mut board = Board::from_fen(fen).unwrap();
+        let start = board.zobrist_hash();
+        assert_eq!(start, board.compute_zobrist_hash());
+        let moves = board.generate_legal_moves(BB_ALL, BB_ALL).collect::<Vec<_>>();
+        for m in moves {
+            board.push(m);
+            assert_eq!(board.zobrist_hash(), board.compute_zobrist_hash(), "{}", m.uci());
+            let replies = board.generate_legal_moves(BB_ALL, BB_ALL).collect::<Vec<_>>();
+            for reply in replies {
+                board.push(reply);
+                assert_eq!(board.zobrist_hash(), board.compute_zobrist_hash(), "{} {}", m.uci(), reply.uci());
+                board.pop();
+            }
+            board.pop();
+            assert_eq!(board.zobrist_hash(), start);
+        }
+    }
+
+    #[test]
+    fn try_set_fen_reports_the_failing_part() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR";
+        let cases = [
+            (String::new(), FenError::Empty),
+            (format!("{} x KQkq - 0 1", start), FenError::Turn { position: 44 }),
+            (format!("{} w KQkx - 0 1", start), FenError::Castling { position: 49 }),
+            (format!("{} w KQkq e9 0 1", start), FenError::EnPassant { position: 51 }),
+            (format!("{} w KQkq - x 1", start), FenError::HalfmoveClock { position: 53 }),
+            (format!("{} w KQkq - 0 -1", start), FenError::FullmoveNumber { position: 55 }),
+            (format!("{} w KQkq - 0 1 x", start), FenError::TooManyParts { position: 57 }),
+        ];
+        for (fen, error) in cases {
+            let mut board = Board::new(None);
+            assert_eq!(board.try_set_fen(&fen), Err(error), "{}", fen);
+            assert_eq!(Board::from_fen(&fen).err(), board.try_set_fen(&fen).err());
+        }
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR/ w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/44/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/ppppxppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        ] {
+            assert!(matches!(Board::from_fen(fen), Err(FenError::Board { .. })), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn valid_fens_round_trip() {
+        for fen in [
+            STARTING_FEN,
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 12 40",
+            "4k3/8/8/8/8/8/8/4K2R b K - 99 150",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            assert_eq!(board.fen(false), fen);
+        }
+        // Missing trailing parts take their defaults.
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/4K3").unwrap();
+        assert_eq!(board.fen(false), "4k3/8/8/8/8/8/8/4K3 w - - 0 1");
+    }
+
+    #[test]
+    fn parse_san_errors() {
+        let board = Board::new(Some(STARTING_FEN));
+        assert_eq!(board.try_parse_san("Zz9"), Err(SanError::InvalidSan("Zz9".to_string())));
+        assert_eq!(
+            board.try_parse_san("e5"),
+            Err(SanError::IllegalSan { san: "e5".to_string(), fen: STARTING_FEN.to_string() })
+        );
+        assert_eq!(board.try_parse_san("Nf3").map(|m| m.uci()), Ok("g1f3".to_string()));
+
+        let fen = "4k3/8/8/8/8/8/8/N1N1K3 w - - 0 1";
+        let board = Board::from_fen(fen).unwrap();
+        match board.try_parse_san("Nb3") {
+            Err(SanError::AmbiguousSan { san, fen: error_fen, candidates }) => {
+                assert_eq!(san, "Nb3");
+                assert_eq!(error_fen, fen);
+                let mut candidates = candidates.iter().map(|m| m.uci()).collect::<Vec<_>>();
+                candidates.sort();
+                assert_eq!(candidates, ["a1b3", "c1b3"]);
+            }
+            other => panic!("expected ambiguous san, got {:?}", other),
+        }
+        assert_eq!(board.try_parse_san("Nab3").map(|m| m.uci()), Ok("a1b3".to_string()));
+        assert_eq!(board.try_parse_san("Ncb3").map(|m| m.uci()), Ok("c1b3".to_string()));
+    }
+
+    #[test]
+    fn push_san_error_leaves_board_untouched() {
+        let mut board = Board::new(Some(STARTING_FEN));
+        assert!(matches!(board.push_san("Ke2"), Err(SanError::IllegalSan { .. })));
+        assert_eq!(board.fen(false), STARTING_FEN);
+        assert!(board.move_stack.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "illegal san")]
+    fn parse_san_panics_on_illegal_move() {
+        Board::new(Some(STARTING_FEN)).parse_san("e5");
+    }
+
+    #[test]
+    fn parse_uci_moves() {
+        let mut board = Board::new(Some(STARTING_FEN));
+        let null = board.parse_uci("0000").unwrap();
+        assert!(!null.bool());
+        assert_eq!(board.uci(null), "0000");
+        board.push_uci("0000").unwrap();
+        assert_eq!(board.turn, BLACK);
+        board.pop();
+
+        let m = board.parse_uci("g1f3").unwrap();
+        assert_eq!((m.from_square, m.to_square, m.promotion, m.drop), (G1, F3, None, None));
+        assert_eq!(board.uci(m), "g1f3");
+
+        let board = Board::from_fen("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        for (uci, promotion) in [("b7b8q", QUEEN), ("b7b8r", ROOK), ("b7b8b", BISHOP), ("b7b8n", KNIGHT)] {
+            let m = board.parse_uci(uci).unwrap();
+            assert_eq!(m.promotion, Some(promotion));
+            assert_eq!(board.uci(m), uci);
+        }
+
+        let board = Board::from_variant_fen(&CRAZYHOUSE, "4k3/8/8/8/8/8/8/4K3[Nq] w - - 0 1").unwrap();
+        let m = board.parse_uci("N@e4").unwrap();
+        assert_eq!((m.to_square, m.drop), (E4, Some(KNIGHT)));
+        assert_eq!(board.uci(m), "N@e4");
+    }
+
+    #[test]
+    fn parse_uci_rejects_illegal_moves() {
+        let board = Board::new(Some(STARTING_FEN));
+        for uci in ["e2e5", "e1g1", "g1g3", "N@e4"] {
+            assert_eq!(
+                board.parse_uci(uci),
+                Err(MoveError::IllegalMove { uci: uci.to_string(), fen: STARTING_FEN.to_string() })
+            );
+        }
+        let board = Board::from_fen("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert!(matches!(board.parse_uci("b7b8"), Err(MoveError::IllegalMove { .. })));
+        assert!(matches!(board.parse_uci("b7b8k"), Err(MoveError::IllegalMove { .. })));
+
+        // The pocket holds a knight for white and a queen for black only.
+        let board = Board::from_variant_fen(&CRAZYHOUSE, "4k3/8/8/8/8/8/8/4K3[Nq] w - - 0 1").unwrap();
+        for uci in ["Q@e4", "N@e1", "P@a1"] {
+            assert!(matches!(board.parse_uci(uci), Err(MoveError::IllegalMove { .. })), "{}", uci);
+        }
+    }
+
+    #[test]
+    fn parse_uci_rejects_malformed_input() {
+        let mut board = Board::new(Some(STARTING_FEN));
+        for uci in ["", "e2", "e2e", "e2e9", "i2e4", "e2e2", "e2e4qq", "e7e8p", "e7e8x", "X@e4", "N@e9", "Ne4", "é2e4"] {
+            assert_eq!(board.parse_uci(uci), Err(MoveError::InvalidUci(uci.to_string())), "{}", uci);
+            assert_eq!(board.push_uci(uci), Err(MoveError::InvalidUci(uci.to_string())), "{}", uci);
+        }
+        assert_eq!(board.fen(false), STARTING_FEN);
+    }
+
+    #[test]
+    fn chess960_position_518_is_the_standard_position() {
+        let board = Board::from_chess960_pos(518);
+        assert!(board.chess960);
+        assert_eq!(board.fen(false), STARTING_FEN);
+        assert_eq!(board.shredder_fen(false), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w HAha - 0 1");
+        assert_eq!(Board::from_chess960_pos(0).fen(false), "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w KQkq - 0 1");
+    }
+
+    #[test]
+    fn chess960_castling_fen_forms() {
+        let board = Board::from_chess960_pos(0);
+        assert_eq!(board.castling_xfen(), "KQkq");
+        assert_eq!(board.castling_shredder_fen(), "HFhf");
+
+        // X-FEN only names the file of a rook that is not the outermost one.
+        let board = Board::from_chess960_fen("rr2k2r/8/8/8/8/8/8/RR2K2R w BHbh - 0 1").unwrap();
+        assert_eq!(board.castling_xfen(), "KBkb");
+        assert_eq!(board.castling_shredder_fen(), "HBhb");
+        assert_eq!(board.fen(false), "rr2k2r/8/8/8/8/8/8/RR2K2R w KBkb - 0 1");
+        assert_eq!(Board::from_chess960_fen(&board.fen(false)).unwrap().castling_rights, board.castling_rights);
+    }
+
+    #[test]
+    fn chess960_castling_is_king_takes_rook() {
+        let mut board = Board::from_chess960_fen("4k3/8/8/8/8/8/8/RK6 w A - 0 1").unwrap();
+        let m = board.parse_uci("b1a1").unwrap();
+        assert_eq!(board.uci(m), "b1a1");
+        assert_eq!(board.san(m), "O-O-O");
+        assert_eq!(board.push_san("O-O-O").unwrap(), m);
+        assert_eq!(board.fen(false), "4k3/8/8/8/8/8/8/2KR4 b - - 1 1");
+
+        // Standard boards write the two square king move but accept both.
+        let fen = "4k3/8/8/8/8/8/8/4K2R w K - 0 1";
+        let mut board = Board::from_fen(fen).unwrap();
+        let m = board.parse_uci("e1h1").unwrap();
+        assert_eq!(board.parse_uci("e1g1").unwrap(), m);
+        assert_eq!(board.uci(m), "e1g1");
+        assert_eq!(board.san(m), "O-O");
+
+        let mut board = Board::from_chess960_fen(fen).unwrap();
+        let m = board.parse_uci("e1h1").unwrap();
+        assert_eq!(board.uci(m), "e1h1");
+        assert_eq!(board.san(m), "O-O");
+        assert!(board.parse_uci("e1g1").is_err());
+        board.push(m);
+        assert_eq!(board.fen(false), "4k3/8/8/8/8/8/8/5RK1 b - - 1 1");
+    }
+
+    #[test]
+    fn mirror_swaps_colors() {
+        let board = Board::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1").unwrap();
+        assert_eq!(board.mirror().fen(false), "rnbqkbnr/pppp1ppp/8/4p3/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        assert_eq!(board.mirror().ep_square, Some(E6));
+
+        let board = Board::from_variant_fen(&CRAZYHOUSE, "4k3/8/8/8/8/8/8/4K3[Qnn] w - - 0 1").unwrap();
+        assert_eq!(board.mirror().fen(false), "4k3/8/8/8/8/8/8/4K3[NNq] b - - 0 1");
+
+        let board = Board::from_variant_fen(&THREE_CHECK, "4k3/8/8/8/8/8/8/4K3 w - - 1+3 0 1").unwrap();
+        assert_eq!(board.mirror().remaining_checks[BLACK as usize], 1);
+        assert_eq!(board.mirror().fen(false), "4k3/8/8/8/8/8/8/4K3 b - - 3+1 0 1");
+    }
+
+    #[test]
+    fn mirror_twice_is_identity() {
+        let boards = [
+            Board::new(Some(STARTING_FEN)),
+            Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w Kq - 3 12").unwrap(),
+            Board::from_fen("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3").unwrap(),
+            Board::from_variant_fen(&CRAZYHOUSE, "r3k2r/8/8/8/8/8/8/R3K2R[QRrpp] b Qk - 0 20").unwrap(),
+            Board::from_variant_fen(&THREE_CHECK, "4k3/8/8/8/8/8/8/4K3 w - - 2+1 0 1").unwrap(),
+            Board::from_chess960_pos(0),
+        ];
+        for board in boards {
+            let mirrored = board.mirror();
+            assert_ne!(mirrored.fen(false), board.fen(false));
+            assert_eq!(mirrored.mirror().fen(false), board.fen(false));
+            assert_eq!(mirrored.mirror().zobrist_hash(), board.zobrist_hash());
+        }
+    }
+
+    #[test]
+    fn mirrored_positions_share_a_canonical_form() {
+        for fen in [
+            STARTING_FEN,
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "8/8/3k4/8/8/2NB4/8/5K2 b - - 0 1",
+        ] {
+            let board = Board::from_fen(fen).unwrap();
+            let canonical = board.canonical().fen(false);
+            assert_eq!(board.mirror().canonical().fen(false), canonical, "{}", fen);
+            assert_eq!(board.canonical().canonical().fen(false), canonical, "{}", fen);
+        }
+        // Without castling rights the files may be flipped, without pawns
+        // the board may be rotated as well.
+        let board = Board::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1").unwrap();
+        assert_eq!(board.transform(flip_horizontal).canonical().fen(false), board.canonical().fen(false));
+        let board = Board::from_fen("8/8/3k4/8/8/2NB4/8/5K2 b - - 0 1").unwrap();
+        assert_eq!(board.transform(flip_diagonal).canonical().fen(false), board.canonical().fen(false));
+        assert_eq!(board.transform(flip_vertical).canonical().fen(false), board.canonical().fen(false));
+    }
+
+    fn see(fen: &str, uci: &str) -> i32 {
+        let board = Board::from_fen(fen).unwrap();
+        board.see(board.parse_uci(uci).unwrap())
+    }
+
+    #[test]
+    fn see_en_passant() {
+        assert_eq!(see("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), 100);
+        let fen = "4k3/2p5/8/3pP3/8/8/8/4K3 w - d6 0 1";
+        assert_eq!(see(fen, "e5d6"), 0);
+        let board = Board::from_fen(fen).unwrap();
+        let m = board.parse_uci("e5d6").unwrap();
+        assert!(board.see_ge(m, 0));
+        assert!(!board.see_ge(m, 1));
+    }
+
+    #[test]
+    fn see_promotions() {
+        assert_eq!(see("4k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7a8q"), 800);
+        // The queen is lost on a defended square, the pawn is gone too.
+        assert_eq!(see("3rk3/2P5/8/8/8/8/8/4K3 w - - 0 1", "c7c8q"), -100);
+        // Rook plus promotion for the queen.
+        assert_eq!(see("3rk3/2P5/8/8/8/8/8/4K3 w - - 0 1", "c7d8q"), 400);
+    }
+
+    #[test]
+    fn see_x_ray_recapture() {
+        assert_eq!(see("3rk3/8/8/3p4/8/8/3R4/4K3 w - - 0 1", "d2d5"), -400);
+        assert_eq!(see("3rk3/8/8/3p4/8/8/3R4/3RK3 w - - 0 1", "d2d5"), 100);
+        assert_eq!(see("3rk3/8/8/3p4/8/8/3R4/3QK3 w - - 0 1", "d2d5"), 100);
+        // The queen behind the bishop recaptures along the diagonal.
+        assert_eq!(see("4k3/5p2/4n3/8/2B5/8/8/4K3 w - - 0 1", "c4e6"), 0);
+        assert_eq!(see("4k3/5p2/4n3/8/2B5/1Q6/8/4K3 w - - 0 1", "c4e6"), 100);
+    }
+
+    #[test]
+    fn see_pinned_attacker() {
+        assert_eq!(see("4k3/4n3/8/3p4/8/2N5/8/R4K2 w - - 0 1", "c3d5"), -200);
+        assert_eq!(see("4k3/4n3/8/3p4/8/2N5/8/4RK2 w - - 0 1", "c3d5"), 100);
+        // The king does not take a defended piece.
+        assert_eq!(see("8/8/3k4/3p4/8/8/3R4/3RK3 w - - 0 1", "d2d5"), 100);
+        assert_eq!(see("8/8/3k4/3p4/8/8/3R4/4K3 w - - 0 1", "d2d5"), -400);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,209 @@
+use std::thread;
+
+use crate::init::{Board, Move, BB_ALL, STARTING_FEN};
+
+pub struct PerftPosition {
+    pub name: &'static str,
+    pub fen: &'static str,
+    pub depth: u32,
+    pub nodes: u64,
+    pub chess960: bool,
+}
+macro_rules! perft_position {
+    ($name: expr, $fen: expr, $depth: expr, $nodes: expr) => {
+        PerftPosition { name: $name, fen: $fen, depth: $depth, nodes: $nodes, chess960: false }
+    };
+    (chess960 $name: expr, $fen: expr, $depth: expr, $nodes: expr) => {
+        PerftPosition { name: $name, fen: $fen, depth: $depth, nodes: $nodes, chess960: true }
+    };
+}
+/// Reference node counts for positions known to trip up move generators.
+pub const PERFT_SUITE: [PerftPosition; 22] = [
+    perft_position!("start position", STARTING_FEN, 5, 4865609),
+    perft_position!("kiwipete", "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 4, 4085603),
+    perft_position!("position 3", "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 5, 674624),
+    perft_position!("position 4", "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", 4, 422333),
+    perft_position!("position 5", "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", 3, 62379),
+    perft_position!("position 6", "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10", 3, 89890),
+    perft_position!("illegal en passant, pinned on rank", "3k4/3p4/8/K1P4r/8/8/8/8 b - - 0 1", 6, 1134888),
+    perft_position!("illegal en passant, pinned on diagonal", "8/8/4k3/8/2p5/8/B2P2K1/8 w - - 0 1", 6, 1015133),
+    perft_position!("en passant gives check", "8/8/1k6/2b5/2pP4/8/5K2/8 b - d3 0 1", 6, 1440467),
+    perft_position!("short castling gives check", "5k2/8/8/8/8/8/8/4K2R w K - 0 1", 6, 661072),
+    perft_position!("long castling gives check", "3k4/8/8/8/8/8/8/R3K3 w Q - 0 1", 6, 803711),
+    perft_position!("castling rights", "r3k2r/1b4bq/8/8/8/8/7B/R3K2R w KQkq - 0 1", 4, 1274206),
+    perft_position!("castling prevented", "r3k2r/8/3Q4/8/8/5q2/8/R3K2R b KQkq - 0 1", 4, 1720476),
+    perft_position!("promotion out of check", "2K2r2/4P3/8/8/8/8/8/3k4 w - - 0 1", 6, 3821001),
+    perft_position!("discovered check", "8/8/1P2K3/8/2n5/1q6/8/5k2 b - - 0 1", 5, 1004658),
+    perft_position!("promotion gives check", "4k3/1P6/8/8/8/8/K7/8 w - - 0 1", 6, 217342),
+    perft_position!("underpromotion gives check", "8/P1k5/K7/8/8/8/8/8 w - - 0 1", 6, 92683),
+    perft_position!("self stalemate", "K1k5/8/P7/8/8/8/8/8 w - - 0 1", 6, 2217),
+    perft_position!("stalemate and checkmate", "8/k1P5/8/1K6/8/8/8/8 w - - 0 1", 7, 567584),
+    perft_position!(chess960 "chess960, adjacent king and rook", "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9", 4, 326672),
+    perft_position!(chess960 "chess960, rook on e-file", "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9", 4, 667366),
+    perft_position!(chess960 "chess960, white only", "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9", 4, 273318),
+];
+
+fn perft_board(fen: &str, chess960: bool) -> Board {
+    if chess960 {
+        Board::from_chess960_fen(fen).unwrap_or_else(|e| panic!("{} in fen {}", e, fen))
+    } else {
+        Board::new(Some(fen))
+    }
+}
+/// Splits the root moves of `fen` across `threads` threads and returns
+/// the node count below every root move, in move generation order.
+pub fn divide_threaded(fen: &str, chess960: bool, depth: u32, threads: usize) -> Vec<(Move, u64)> {
+    let mut board = perft_board(fen, chess960);
+    if depth <= 1 || threads <= 1 {
+        return board.divide(depth);
+    }
+    let moves = board.generate_legal_moves(BB_ALL, BB_ALL).collect::<Vec<Move>>();
+    let chunk_size = moves.len().div_ceil(threads);
+
+    thread::scope(|scope| {
+        let handles = moves
+            .chunks(chunk_size.max(1))
+            .map(|chunk| {
+                scope.spawn(move || {
+                    let mut board = perft_board(fen, chess960);
+                    let mut result = Vec::new();
+                    for m in chunk {
+                        board.push(*m);
+                        result.push((*m, board.perft(depth - 1)));
+                        board.pop();
+                    }
+                    result
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("perft thread panicked"))
+            .collect()
+    })
+}
+pub fn perft_threaded(fen: &str, chess960: bool, depth: u32, threads: usize) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    divide_threaded(fen, chess960, depth, threads).iter().map(|(_, nodes)| nodes).sum()
+}
+/// Runs every position of `PERFT_SUITE` and returns the names of the
+/// positions whose node count did not match.
+pub fn run_suite(threads: usize) -> Vec<&'static str> {
+    let mut failed = Vec::new();
+    for position in PERFT_SUITE.iter() {
+        let start = std::time::Instant::now();
+        let nodes = perft_threaded(position.fen, position.chess960, position.depth, threads);
+        let status = if nodes == position.nodes { "ok" } else { "FAILED" };
+        println!(
+            "{:<40} depth {} nodes {:>10} expected {:>10} {:>8.2?} {}",
+            position.name, position.depth, nodes, position.nodes, start.elapsed(), status
+        );
+        if nodes != position.nodes {
+            failed.push(position.name);
+        }
+    }
+    failed
+}
+/// Entry point of the `perft` subcommand:
+///
+/// `perft --suite [--threads N]` checks the move generator against the reference suite,
+/// `perft <depth> [--threads N] [--chess960] [fen]` prints the node count below every root move.
+pub fn run(args: &[String]) {
+    let mut threads = 1;
+    let mut chess960 = false;
+    let mut depth = None;
+    let mut suite = false;
+    let mut fen_parts = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--suite" => suite = true,
+            "--chess960" => chess960 = true,
+            "--threads" => {
+                threads = iter.next().and_then(|t| t.parse().ok()).expect("expected number of threads after --threads");
+            }
+            _ if depth.is_none() && !suite => {
+                depth = Some(arg.parse::<u32>().expect("expected perft depth"));
+            }
+            _ => fen_parts.push(arg.as_str()),
+        }
+    }
+
+    if suite {
+        let failed = run_suite(threads);
+        if failed.is_empty() {
+            println!("all {} positions passed", PERFT_SUITE.len());
+        } else {
+            println!("failed: {}", failed.join(", "));
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let fen = if fen_parts.is_empty() { STARTING_FEN.to_string() } else { fen_parts.join(" ") };
+    let depth = depth.expect("usage: perft <depth> [--threads N] [--chess960] [fen] | perft --suite [--threads N]");
+    let board = perft_board(&fen, chess960);
+    let start = std::time::Instant::now();
+    let mut total = 0;
+    for (m, nodes) in divide_threaded(&fen, chess960, depth, threads) {
+        println!("{}: {}", board.uci(m), nodes);
+        total += nodes;
+    }
+    println!();
+    println!("Nodes searched: {}", total);
+    println!("Time: {:.2?}", start.elapsed());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Shallow counts of the first suite positions, from the Chess
+    // Programming Wiki perft results.
+    const SHALLOW: [(usize, &[u64]); 6] = [
+        (0, &[20, 400, 8902]),
+        (1, &[48, 2039, 97862]),
+        (2, &[14, 191, 2812, 43238]),
+        (3, &[6, 264, 9467]),
+        (4, &[44, 1486, 62379]),
+        (5, &[46, 2079, 89890]),
+    ];
+
+    #[test]
+    fn perft_suite_shallow() {
+        for (index, counts) in SHALLOW {
+            let position = &PERFT_SUITE[index];
+            let mut board = perft_board(position.fen, position.chess960);
+            for (depth, nodes) in counts.iter().enumerate() {
+                assert_eq!(board.perft(depth as u32 + 1), *nodes, "{} depth {}", position.name, depth + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn perft_suite_small_positions() {
+        for position in PERFT_SUITE.iter().filter(|p| p.nodes <= 250_000) {
+            let mut board = perft_board(position.fen, position.chess960);
+            assert_eq!(board.perft(position.depth), position.nodes, "{}", position.name);
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn perft_suite_deep() {
+        for position in PERFT_SUITE.iter() {
+            let mut board = perft_board(position.fen, position.chess960);
+            assert_eq!(board.perft(position.depth), position.nodes, "{}", position.name);
+        }
+    }
+
+    #[test]
+    fn perft_threaded_matches_single_threaded() {
+        let position = &PERFT_SUITE[1];
+        assert_eq!(perft_threaded(position.fen, position.chess960, 3, 4), 97862);
+    }
+}
@@ -1,48 +1,109 @@
 
 
-use std::{cell::RefCell, collections::{HashSet, VecDeque, HashMap}, rc::Rc};
+use std::{cell::RefCell, collections::{HashSet, VecDeque}, rc::Rc};
 use ahash::AHashMap;
 use std::fs::File;
 use std::io::{self, prelude::*};
 
 use regex::Regex;
-use crate::{gen_iter, init::{Board, Boolean, Color, Move, STARTING_BOARD_FEN, STARTING_FEN, BB_ALL}};
+use crate::{gen_iter, init::{Board, Color, Move, SanError, STARTING_FEN, BB_ALL, WHITE}};
 use lazy_static::lazy_static;
 use std::ops::Index;
 use thiserror::Error;
 
-const NAG_NULL: u8 = 0;
-const NAG_GOOD_MOVE: u8 = 1;
-const NAG_MISTAKE: u8 = 2;
-const NAG_BRILLIANT_MOVE: u8 = 3;
-const NAG_BLUNDER: u8 = 4;
-const NAG_SPECULATIVE_MOVE: u8 = 5;
-const NAG_DUBIOUS_MOVE: u8 = 6;
-const NAG_FORCED_MOVE: u8 = 7;
-const NAG_SINGULAR_MOVE: u8 = 8;
-const NAG_WORST_MOVE: u8 = 9;
-const NAG_DRAWISH_POSITION: u8 = 10;
-const NAG_QUIET_POSITION: u8 = 11;
-const NAG_ACTIVE_POSITION: u8 = 12;
-const NAG_UNCLEAR_POSITION: u8 = 13;
-const NAG_WHITE_SLIGHT_ADVANTAGE: u8 = 14;
-const NAG_BLACK_SLIGHT_ADVANTAGE: u8 = 15;
-const NAG_WHITE_MODERATE_ADVANTAGE: u8 = 16;
-const NAG_BLACK_MODERATE_ADVANTAGE: u8 = 17;
-const NAG_WHITE_DECISIVE_ADVANTAGE: u8 = 18;
-const NAG_BLACK_DECISIVE_ADVANTAGE: u8 = 19;
-const NAG_WHITE_ZUGZWANG: u8 = 22;
-const NAG_BLACK_ZUGZWANG: u8 = 23;
-const NAG_WHITE_MODERATE_COUNTERPLAY: u8 = 132;
-const NAG_BLACK_MODERATE_COUNTERPLAY: u8 = 133;
-const NAG_WHITE_DECISIVE_COUNTERPLAY: u8 = 134;
-const NAG_BLACK_DECISIVE_COUNTERPLAY: u8 = 135;
-const NAG_WHITE_MODERATE_TIME_PRESSURE: u8 = 136;
-const NAG_BLACK_MODERATE_TIME_PRESSURE: u8 = 137;
-const NAG_WHITE_SEVERE_TIME_PRESSURE: u8 = 138;
-const NAG_BLACK_SEVERE_TIME_PRESSURE: u8 = 139;
-const NAG_NOVELTY: u8 = 146;
+pub const NAG_NULL: u64 = 0;
+pub const NAG_GOOD_MOVE: u64 = 1;
+pub const NAG_MISTAKE: u64 = 2;
+pub const NAG_BRILLIANT_MOVE: u64 = 3;
+pub const NAG_BLUNDER: u64 = 4;
+pub const NAG_SPECULATIVE_MOVE: u64 = 5;
+pub const NAG_DUBIOUS_MOVE: u64 = 6;
+pub const NAG_FORCED_MOVE: u64 = 7;
+pub const NAG_SINGULAR_MOVE: u64 = 8;
+pub const NAG_WORST_MOVE: u64 = 9;
+pub const NAG_DRAWISH_POSITION: u64 = 10;
+pub const NAG_QUIET_POSITION: u64 = 11;
+pub const NAG_ACTIVE_POSITION: u64 = 12;
+pub const NAG_UNCLEAR_POSITION: u64 = 13;
+pub const NAG_WHITE_SLIGHT_ADVANTAGE: u64 = 14;
+pub const NAG_BLACK_SLIGHT_ADVANTAGE: u64 = 15;
+pub const NAG_WHITE_MODERATE_ADVANTAGE: u64 = 16;
+pub const NAG_BLACK_MODERATE_ADVANTAGE: u64 = 17;
+pub const NAG_WHITE_DECISIVE_ADVANTAGE: u64 = 18;
+pub const NAG_BLACK_DECISIVE_ADVANTAGE: u64 = 19;
+pub const NAG_WHITE_ZUGZWANG: u64 = 22;
+pub const NAG_BLACK_ZUGZWANG: u64 = 23;
+pub const NAG_WHITE_MODERATE_COUNTERPLAY: u64 = 132;
+pub const NAG_BLACK_MODERATE_COUNTERPLAY: u64 = 133;
+pub const NAG_WHITE_DECISIVE_COUNTERPLAY: u64 = 134;
+pub const NAG_BLACK_DECISIVE_COUNTERPLAY: u64 = 135;
+pub const NAG_WHITE_MODERATE_TIME_PRESSURE: u64 = 136;
+pub const NAG_BLACK_MODERATE_TIME_PRESSURE: u64 = 137;
+pub const NAG_WHITE_SEVERE_TIME_PRESSURE: u64 = 138;
+pub const NAG_BLACK_SEVERE_TIME_PRESSURE: u64 = 139;
+pub const NAG_NOVELTY: u64 = 146;
 
+/// Symbol of a NAG, such as `!?` for `NAG_SPECULATIVE_MOVE`.
+pub fn nag_symbol(nag: u64) -> Option<&'static str> {
+    match nag {
+        NAG_GOOD_MOVE => Some("!"),
+        NAG_MISTAKE => Some("?"),
+        NAG_BRILLIANT_MOVE => Some("!!"),
+        NAG_BLUNDER => Some("??"),
+        NAG_SPECULATIVE_MOVE => Some("!?"),
+        NAG_DUBIOUS_MOVE => Some("?!"),
+        NAG_FORCED_MOVE => Some("□"),
+        NAG_DRAWISH_POSITION => Some("="),
+        NAG_UNCLEAR_POSITION => Some("∞"),
+        NAG_WHITE_SLIGHT_ADVANTAGE => Some("⩲"),
+        NAG_BLACK_SLIGHT_ADVANTAGE => Some("⩱"),
+        NAG_WHITE_MODERATE_ADVANTAGE => Some("±"),
+        NAG_BLACK_MODERATE_ADVANTAGE => Some("∓"),
+        NAG_WHITE_DECISIVE_ADVANTAGE => Some("+-"),
+        NAG_BLACK_DECISIVE_ADVANTAGE => Some("-+"),
+        NAG_WHITE_ZUGZWANG | NAG_BLACK_ZUGZWANG => Some("⨀"),
+        NAG_WHITE_MODERATE_COUNTERPLAY | NAG_BLACK_MODERATE_COUNTERPLAY => Some("⇆"),
+        NAG_NOVELTY => Some("N"),
+        _ => None
+    }
+}
+/// English description of a NAG from the PGN standard.
+pub fn nag_description(nag: u64) -> Option<&'static str> {
+    match nag {
+        NAG_NULL => Some("null annotation"),
+        NAG_GOOD_MOVE => Some("good move"),
+        NAG_MISTAKE => Some("mistake"),
+        NAG_BRILLIANT_MOVE => Some("brilliant move"),
+        NAG_BLUNDER => Some("blunder"),
+        NAG_SPECULATIVE_MOVE => Some("speculative move"),
+        NAG_DUBIOUS_MOVE => Some("dubious move"),
+        NAG_FORCED_MOVE => Some("forced move"),
+        NAG_SINGULAR_MOVE => Some("singular move"),
+        NAG_WORST_MOVE => Some("worst move"),
+        NAG_DRAWISH_POSITION => Some("drawish position"),
+        NAG_QUIET_POSITION => Some("equal chances, quiet position"),
+        NAG_ACTIVE_POSITION => Some("equal chances, active position"),
+        NAG_UNCLEAR_POSITION => Some("unclear position"),
+        NAG_WHITE_SLIGHT_ADVANTAGE => Some("White has a slight advantage"),
+        NAG_BLACK_SLIGHT_ADVANTAGE => Some("Black has a slight advantage"),
+        NAG_WHITE_MODERATE_ADVANTAGE => Some("White has a moderate advantage"),
+        NAG_BLACK_MODERATE_ADVANTAGE => Some("Black has a moderate advantage"),
+        NAG_WHITE_DECISIVE_ADVANTAGE => Some("White has a decisive advantage"),
+        NAG_BLACK_DECISIVE_ADVANTAGE => Some("Black has a decisive advantage"),
+        NAG_WHITE_ZUGZWANG => Some("White is in zugzwang"),
+        NAG_BLACK_ZUGZWANG => Some("Black is in zugzwang"),
+        NAG_WHITE_MODERATE_COUNTERPLAY => Some("White has moderate counterplay"),
+        NAG_BLACK_MODERATE_COUNTERPLAY => Some("Black has moderate counterplay"),
+        NAG_WHITE_DECISIVE_COUNTERPLAY => Some("White has decisive counterplay"),
+        NAG_BLACK_DECISIVE_COUNTERPLAY => Some("Black has decisive counterplay"),
+        NAG_WHITE_MODERATE_TIME_PRESSURE => Some("White has moderate time control pressure"),
+        NAG_BLACK_MODERATE_TIME_PRESSURE => Some("Black has moderate time control pressure"),
+        NAG_WHITE_SEVERE_TIME_PRESSURE => Some("White has severe time control pressure"),
+        NAG_BLACK_SEVERE_TIME_PRESSURE => Some("Black has severe time control pressure"),
+        NAG_NOVELTY => Some("novelty"),
+        _ => None
+    }
+}
 
 macro_rules! create_regex{
     ($name: ident, $s: tt) => {
@@ -63,6 +124,29 @@ create_regex!(EVAL_REGEX, r"\[%eval\s(?:\#([+-]?\d+)|([+-]?(?:\d{0,10}\.\d{1,2}|
 create_regex!(ARROWS_REGEX, r"\[%(?:csl|cal)\s([RGYB][a-h][1-8](?:[a-h][1-8])?(?:,[RGYB][a-h][1-8](?:[a-h][1-8])?)*)\]");
 
 pub const TAG_ROASTER: [&str; 7] = ["Event", "Site", "Date", "Round", "White", "Black", "Result"];
+/// Value written for a missing tag of the Seven Tag Roster.
+fn tag_default(key: &str) -> &'static str {
+    match key {
+        "Date" => "????.??.??",
+        "Result" => "*",
+        _ => "?"
+    }
+}
+/// Undoes the `\\` and `\"` escapes of a quoted tag value.
+fn unescape_tag_value(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some(next @ ('\\' | '"'))) => {
+                unescaped.push(next);
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
 #[derive(PartialEq)]
 pub struct NodeBase {
     pub is_root: bool,
@@ -81,6 +165,54 @@ pub struct Game {
     pub root: Node,
     pub headers: Headers
 }
+impl Game {
+    /// Visits the headers, the root comment and the whole move tree in
+    /// PGN order, ending with the result.
+    pub fn accept<V: BaseVisitor>(&self, visitor: &mut V) {
+        if visitor.begin_game().is_some() {
+            visitor.end_game();
+            return;
+        }
+        for key in TAG_ROASTER {
+            visitor.visit_header(key, self.headers.get(key).unwrap_or(tag_default(key)));
+        }
+        for (key, value) in &self.headers.others {
+            visitor.visit_header(key, value);
+        }
+        visitor.end_headers();
+
+        let mut board = self.root.board();
+        visitor.visit_board(&board);
+        let root = self.root.0.borrow();
+        if !root.comment.is_empty() {
+            visitor.visit_comment(&root.comment);
+        }
+        if let Some(main) = root.variations.front() {
+            Node(main.clone())._accept(&mut board, visitor, true);
+        }
+        visitor.visit_result(self.headers.get("Result").unwrap_or("*"));
+        visitor.end_game();
+    }
+}
+impl std::fmt::Display for Game {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut exporter = StringExporter::default();
+        self.accept(&mut exporter);
+        write!(f, "{}", exporter.result())
+    }
+}
+impl Drop for Game {
+    // Nodes hold strong references to their parents, so the cycles are
+    // broken here to free the tree.
+    fn drop(&mut self) {
+        let mut stack = vec![self.root.0.clone()];
+        while let Some(node) = stack.pop() {
+            let mut node = node.borrow_mut();
+            node.parent = None;
+            stack.extend(node.variations.drain(..));
+        }
+    }
+}
 impl std::fmt::Debug for Node {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut builder = String::new();
@@ -93,9 +225,9 @@ impl std::fmt::Debug for Node {
         write!(f, "Game: {}", builder)
     }
 }
-impl<'a> PartialEq for Node {
+impl PartialEq for Node {
     fn eq(&self, other: &Self) -> bool {
-        self as *const _ == other as *const _
+        std::ptr::eq(self, other)
     }
 }
 impl Node {
@@ -112,19 +244,20 @@ impl Node {
             }))
         )
     }
-    pub fn init(parent:NodeRef , m: Move, comment: &str, starting_comment: &str, _nags: HashSet<u64>) -> NodeRef{
+    pub fn init(parent:NodeRef , m: Move, comment: &str, starting_comment: &str, nags: HashSet<u64>) -> NodeRef{
         let node = Node::new(comment).0;
         node.borrow_mut().m = Some(m);
+        node.borrow_mut().nags = nags;
         node.borrow_mut().starting_comment = String::from(starting_comment);
-        parent.borrow_mut().variations.push_front(node.clone());
+        parent.borrow_mut().variations.push_back(node.clone());
         node.borrow_mut().parent = Some(parent);
         node
     }
     pub fn ply(&self) -> u64 {
-        return 5;
+        5
     }
     pub fn turn(&self) -> Color {
-        self.ply() % 2 == 0
+        self.ply().is_multiple_of(2)
     }
     pub fn root(&self) -> NodeRef {
         let mut node = self.0.clone();
@@ -137,8 +270,7 @@ impl Node {
         node
     }
     pub fn game(&self) -> NodeRef {
-        let root = self.root();
-        root
+        self.root()
     }
     pub fn end(self) -> NodeRef {
         let mut node =self.0;
@@ -223,9 +355,9 @@ impl Node {
         Node(n)
     }
     pub fn add_main_variation(&mut self, m: Move, comment: &str, nags: HashSet<u64>) -> Node {
+        let node = self.add_variation(m, comment, "", nags);
         let val= self.0.borrow_mut().variations.pop_back().unwrap();
         self.0.borrow_mut().variations.push_front(val);
-        let node = self.add_variation(m, comment, "", nags);
         node
     }
     pub fn next(&self) -> Option<NodeRef> {
@@ -248,14 +380,14 @@ impl Node {
         let mut stack: Vec<Move> = Vec::new();
         let mut node = self.0.clone();
         while node.borrow().m.is_some() && node.borrow().parent.is_some() {
-            stack.push(node.borrow().m.clone().unwrap());
+            stack.push(node.borrow().m.unwrap());
             node = node.clone().borrow().parent.as_ref().unwrap().clone();
         }
 
         let mut board= Board::new(None);
         board.reset();
-        while !stack.is_empty() {
-            board.push(stack.pop().unwrap());
+        while let Some(element) = stack.pop() {
+            board.push(element);
         }
         board
     }
@@ -284,7 +416,7 @@ impl Node {
             }
         }
     }
-    fn _accept(&self, parent_board: &mut Board, visitor: &mut GameBuilder, sidelines: bool) {
+    fn _accept<V: BaseVisitor>(&self, parent_board: &mut Board, visitor: &mut V, sidelines: bool) {
         let mut stack = Vec::from([Rc::new(RefCell::new(AcceptFrame::new(self.0.clone(), false, sidelines)))]); 
 
         while !stack.is_empty() {
@@ -300,11 +432,11 @@ impl Node {
                 top.state = "variations".to_string();
             }    
             else if top.state == "variations" {
-                let var_opt = top.variations.iter().next();
+                let var_opt = top.variations.pop_front();
 
                 if let Some(variation) = var_opt {
-                    if visitor.begin_variation() == () {
-                        stack.push(Rc::new(RefCell::new(AcceptFrame::new(variation.clone(), true, false))));
+                    if visitor.begin_variation().is_none() {
+                        stack.push(Rc::new(RefCell::new(AcceptFrame::new(variation, true, false))));
                     }
                     top.in_variation = true;
                 }
@@ -325,21 +457,22 @@ impl Node {
 
         }
     }
-    pub fn accept(&mut self, mut visitor: GameBuilder) -> GameBuilder {
+    /// Visits this move and the moves after it, with their sidelines but
+    /// not the siblings of this node.
+    pub fn accept<V: BaseVisitor>(&self, visitor: &mut V) {
         let mut parent_board = Node(self.0.borrow().parent.as_ref().unwrap().clone()).board();
-        self._accept(&mut parent_board, &mut visitor, false);
-        visitor
+        self._accept(&mut parent_board, visitor, false);
     }
 }
 impl NodeBase {
-    fn accept_node(&self, parent_board: &mut Board, visitor: &mut GameBuilder) {
+    fn accept_node<V: BaseVisitor>(&self, parent_board: &mut Board, visitor: &mut V) {
         if !self.starting_comment.is_empty() {
             visitor.visit_comment(&self.starting_comment);
         }
-        visitor.visit_move(&parent_board, self.m.unwrap());
+        visitor.visit_move(parent_board, self.m.unwrap());
 
         parent_board.push(self.m.unwrap());
-        visitor.visit_board(&Node(self.parent.as_ref().unwrap().clone()).board());
+        visitor.visit_board(parent_board);
         parent_board.pop();
 
         let mut nags =  self.nags.iter().collect::<Vec<&u64>>();
@@ -381,7 +514,8 @@ impl<T> Mainline<T> {
 #[derive(Debug)]
 pub struct Headers{
     tag_roaster: AHashMap<String, String>,
-    others: AHashMap<String, String>,
+    /// Tags outside the Seven Tag Roster, in the order they were set.
+    others: Vec<(String, String)>,
     data: AHashMap<String, String>
 }
 impl Headers {
@@ -390,7 +524,7 @@ impl Headers {
         if d.is_none() {
             d = Some(AHashMap::with_capacity(std::mem::size_of::<String>() * 12));
         }
-        Headers { tag_roaster: AHashMap::new(), others: AHashMap::new(), data: d.unwrap() }
+        Headers { tag_roaster: AHashMap::new(), others: Vec::new(), data: d.unwrap() }
     }
     pub fn set(&mut self, key: &str, value: &str){
         if TAG_ROASTER.contains(&key) {
@@ -402,18 +536,18 @@ impl Headers {
         else if value.contains("\n") || value.contains("\r") {
             panic!("line break in pgn header {}", value)
         }
+        else if let Some(other) = self.others.iter_mut().find(|(k, _)| k == key) {
+            other.1 = value.to_string();
+        }
         else {
-            self.others.insert(key.to_string(), value.to_string());
+            self.others.push((key.to_string(), value.to_string()));
         }
     }
     pub fn get(&self, key: &str) -> Option<&str>{
-        if TAG_ROASTER.contains(&key){
-            match self.tag_roaster.get(key) {
-                Some(tag) => { return Some(tag)},
-                None => { return None}
-            }
+        if TAG_ROASTER.contains(&key) {
+            return self.tag_roaster.get(key).map(String::as_str);
         }
-        if let Some(a) = self.others.get(key) { Some(a) } else { None }
+        self.others.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
     }
     pub fn iter(&self) -> impl Iterator<Item = &str> {
         gen_iter!({
@@ -422,9 +556,13 @@ impl Headers {
                     yield key;
                 }
             }
+            for (key, _) in &self.others {
+                yield key.as_str();
+            }
         })
     }
 }
+#[allow(clippy::upper_case_acronyms)]
 pub enum SkipType {
     SKIP = 0
 }
@@ -448,22 +586,21 @@ impl AcceptFrame{
         frame
     }
 }
+/// Receives the parts of a game in PGN order, from the reader or from
+/// `Game::accept`. Returning `Some(SkipType::SKIP)` from `begin_game` or
+/// `begin_variation` skips the game or the variation.
 pub trait BaseVisitor {
-    fn begin_game(&self) -> Option<SkipType>;
-    fn begin_headers(&self) -> Option<Headers>;
-    fn visit_header(&self, tagname: &str, tagvalue: &str);
-    fn end_headers(&self) -> Option<SkipType>;
-    fn parse_san(&self, board: Board, san: &str) -> Move;
-    fn visit_move(&self, board: Board, m: Move);
-    fn visit_board(&self, board: Board);
-    fn visit_comment(&self, comment: &str);
-    fn visit_nag(&self, nag: u64);
-    fn begin_variation(&self) -> Option<SkipType>;
-    fn end_variation(&self);
-    fn visit_result(&self, result: &str);
-    fn end_game(&self);
-    fn result<T>(&self) -> T;
-    fn handle_error(&self, error: &str);
+    fn begin_game(&mut self) -> Option<SkipType> { None }
+    fn visit_header(&mut self, _tagname: &str, _tagvalue: &str) {}
+    fn end_headers(&mut self) {}
+    fn visit_move(&mut self, _board: &Board, _m: Move) {}
+    fn visit_board(&mut self, _board: &Board) {}
+    fn visit_comment(&mut self, _comment: &str) {}
+    fn visit_nag(&mut self, _nag: u64) {}
+    fn begin_variation(&mut self) -> Option<SkipType> { None }
+    fn end_variation(&mut self) {}
+    fn visit_result(&mut self, _result: &str) {}
+    fn end_game(&mut self) {}
 }
 pub struct GameBuilder {
     pub game: Game,
@@ -480,18 +617,9 @@ impl GameBuilder {
             in_variation: false
         }
     }
-    fn begin_game(&mut self) -> Option<SkipType>{
-        self.game = Game{root: Node::new(""), headers: Headers::new(None)};
-        self.variation_stack = Vec::new();
-        self.variation_stack.push(self.game.root.0.clone());
-        self.starting_comment = String::new();
-        self.in_variation = false;
-        None
-    }
     fn new_and_begin() -> GameBuilder {
         let game = Game{root: Node::new(""), headers: Headers::new(None)};
-        let mut variation_stack = Vec::new();
-        variation_stack.push(game.root.0.clone());
+        let variation_stack = vec![game.root.0.clone()];
         let starting_comment = String::new();
         let in_variation = false;
         GameBuilder {
@@ -504,16 +632,29 @@ impl GameBuilder {
     fn begin_headers(&self) -> Option<&Headers>{
         Some(&self.game.headers)
     }
+    pub fn result(&self) -> &Game {
+        &self.game
+    }
+    pub fn parse_san(&self, board: &Board, san: &str) -> Result<Move, SanError> {
+        board.try_parse_san(san)
+    }
+}
+impl BaseVisitor for GameBuilder {
+    fn begin_game(&mut self) -> Option<SkipType>{
+        self.game = Game{root: Node::new(""), headers: Headers::new(None)};
+        self.variation_stack = Vec::new();
+        self.variation_stack.push(self.game.root.0.clone());
+        self.starting_comment = String::new();
+        self.in_variation = false;
+        None
+    }
     fn visit_header(&mut self, tagname: &str, tagvalue: &str){
         self.game.headers.set(tagname, tagvalue)
     }
-    // fn end_headers(&self) -> Option<SkipType>;
-    // fn parse_san(&self, board: Board, san: &str) -> Move;
-    fn visit_nag(&self, nag: u64) -> Option<SkipType> {
+    fn visit_nag(&mut self, nag: u64) {
         self.variation_stack.last().unwrap().borrow_mut().nags.insert(nag);
-        None
     }
-    fn begin_variation(&mut self){
+    fn begin_variation(&mut self) -> Option<SkipType> {
         if let Some(parent) = self.variation_stack.last().cloned().unwrap().borrow().parent.clone() {
             self.variation_stack.push(parent.clone());
             self.in_variation = false;
@@ -521,6 +662,7 @@ impl GameBuilder {
         else {
             panic!("begin variation called, but root node on top of stack");
         }
+        None
     }
     fn end_variation(&mut self) {
         self.variation_stack.pop();
@@ -531,12 +673,16 @@ impl GameBuilder {
         }
     }
     fn visit_comment(&mut self, comment: &str){
-        if self.in_variation || (self.variation_stack.last().unwrap().borrow().parent.is_some() && Node(self.variation_stack.last().unwrap().clone()).is_end()){
-            self.starting_comment = String::from(self.variation_stack.last().unwrap().borrow().starting_comment.clone());
+        let top = self.variation_stack.last().unwrap().clone();
+        // A comment after a move belongs to it, one before any move to the
+        // game. Otherwise it starts the next variation.
+        if self.in_variation || (top.borrow().parent.is_none() && Node(top.clone()).is_end()) {
+            let mut node = top.borrow_mut();
+            node.comment = [node.comment.as_str(), comment].join("\n").trim().to_string();
+        }
+        else {
+            self.starting_comment = [self.starting_comment.as_str(), comment].join("\n").trim().to_string();
         }
-        self.starting_comment.push('\n');
-        self.starting_comment.push_str(comment.trim());
-
     }
     fn visit_move(&mut self, _board: &Board, m: Move){
         let last_copy = self.variation_stack.pop().unwrap();
@@ -546,230 +692,526 @@ impl GameBuilder {
         self.starting_comment = "".to_string();
         self.in_variation = true;
     }
-    fn visit_board(&self, _board: &Board){}
-    fn end_game(&self){}
-    pub fn result(&self) -> &Game {
-        &self.game
+}
+/// Writes a game as PGN through `Game::accept`, wrapping movetext lines
+/// at `columns` characters.
+pub struct StringExporter {
+    /// Line width, or `None` to keep the movetext on one line.
+    pub columns: Option<usize>,
+    pub headers: bool,
+    pub comments: bool,
+    pub variations: bool,
+    lines: Vec<String>,
+    current_line: String,
+    variation_depth: usize,
+    /// Whether the next black move needs a `N...` move number, at the
+    /// start and after comments and variations.
+    force_movenumber: bool,
+}
+impl StringExporter {
+    pub fn new(columns: Option<usize>) -> StringExporter {
+        StringExporter {
+            columns,
+            headers: true,
+            comments: true,
+            variations: true,
+            lines: Vec::new(),
+            current_line: String::new(),
+            variation_depth: 0,
+            force_movenumber: true,
+        }
+    }
+    fn flush_current_line(&mut self) {
+        if !self.current_line.is_empty() {
+            self.lines.push(self.current_line.trim_end().to_string());
+        }
+        self.current_line.clear();
     }
-    pub fn parse_san(&self, board: &Board, san: &str) -> Move {
-        board.parse_san(san)
+    fn write_token(&mut self, token: &str) {
+        if let Some(columns) = self.columns {
+            if columns.saturating_sub(self.current_line.chars().count()) < token.chars().count() {
+                self.flush_current_line();
+            }
+        }
+        self.current_line.push_str(token);
+    }
+    fn write_line(&mut self, line: &str) {
+        self.flush_current_line();
+        self.lines.push(line.trim_end().to_string());
+    }
+    /// Whether moves at the current depth are written at all.
+    fn writes_moves(&self) -> bool {
+        self.variations || self.variation_depth == 0
+    }
+    /// The PGN written so far.
+    pub fn result(&self) -> String {
+        let mut lines = self.lines.clone();
+        if !self.current_line.is_empty() {
+            lines.push(self.current_line.trim_end().to_string());
+        }
+        lines.join("\n").trim_end().to_string()
     }
-    // fn handle_error(&self, error: &str);
 }
-pub struct BufReader {
-    reader: io::BufReader<File>,
-    buffer: String
+impl Default for StringExporter {
+    fn default() -> StringExporter {
+        StringExporter::new(Some(80))
+    }
 }
-
-impl BufReader {
+impl BaseVisitor for StringExporter {
+    fn visit_header(&mut self, tagname: &str, tagvalue: &str) {
+        if self.headers {
+            self.write_line(&format!("[{} \"{}\"]", tagname, tagvalue.replace('\\', "\\\\").replace('"', "\\\"")));
+        }
+    }
+    fn end_headers(&mut self) {
+        if self.headers {
+            self.write_line("");
+        }
+    }
+    fn begin_variation(&mut self) -> Option<SkipType> {
+        self.variation_depth += 1;
+        if !self.variations {
+            return Some(SkipType::SKIP);
+        }
+        self.write_token("( ");
+        self.force_movenumber = true;
+        None
+    }
+    fn end_variation(&mut self) {
+        self.variation_depth -= 1;
+        if self.variations {
+            self.write_token(") ");
+            self.force_movenumber = true;
+        }
+    }
+    fn visit_comment(&mut self, comment: &str) {
+        if self.comments && self.writes_moves() {
+            let comment = comment.replace("}", "").split_whitespace().collect::<Vec<_>>().join(" ");
+            self.write_token(&format!("{{ {} }} ", comment));
+            self.force_movenumber = true;
+        }
+    }
+    fn visit_nag(&mut self, nag: u64) {
+        if self.comments && self.writes_moves() {
+            self.write_token(&format!("${} ", nag));
+        }
+    }
+    fn visit_move(&mut self, board: &Board, m: Move) {
+        if self.writes_moves() {
+            if board.turn == WHITE {
+                self.write_token(&format!("{}. ", board.fullmove_number));
+            }
+            else if self.force_movenumber {
+                self.write_token(&format!("{}... ", board.fullmove_number));
+            }
+            // SAN only needs the position, not the move stack.
+            self.write_token(&format!("{} ", board.copy(false).san(m)));
+            self.force_movenumber = false;
+        }
+    }
+    fn visit_result(&mut self, result: &str) {
+        self.write_token(&format!("{} ", result));
+    }
+    fn end_game(&mut self) {
+        self.write_line("");
+    }
+}
+/// Reads games one at a time from any buffered reader, so that memory use
+/// does not grow with the size of the database. Lines may end in `\n` or
+/// `\r\n` and the input may start with a byte order mark.
+pub struct PgnReader<R: BufRead> {
+    reader: R,
+    /// Header line of the next game, read while looking for the end of
+    /// the previous one.
+    pending: Option<String>,
+}
+impl PgnReader<io::BufReader<File>> {
     pub fn open(filename: &str) -> io::Result<Self> {
-        let file = File::open(filename).unwrap();
-        let reader = io::BufReader::new(file);
-        let buffer = String::new();
-        Ok(Self { reader, buffer})
+        Ok(PgnReader::new(io::BufReader::new(File::open(filename)?)))
+    }
+}
+impl<R: BufRead> PgnReader<R> {
+    pub fn new(reader: R) -> PgnReader<R> {
+        PgnReader { reader, pending: None }
+    }
+    /// Next line with its line ending, empty at the end of the input.
+    fn read_line(&mut self) -> Result<String, ParsingError> {
+        if let Some(line) = self.pending.take() {
+            return Ok(line);
+        }
+        let mut line = String::new();
+        self.reader.read_line(&mut line).map_err(|_| ParsingError::ReadLineError)?;
+        Ok(line)
     }
+    fn read_until_end_of_game(&mut self, mut line: String) -> Result<(), ParsingError> {
+        while !isspace(&line) {
+            if line.starts_with("[") {
+                self.pending = Some(line);
+                break;
+            }
+            line = self.read_line()?;
+        }
+        Ok(())
+    }
+    /// Parses the next game, or returns `None` at the end of the input.
+    pub fn read_game_builder(&mut self) -> Result<Option<GameBuilder>, ParsingError> {
+        let mut visitor = GameBuilder::new_and_begin();
+        let mut found_game = false;
+
+        // Ignore leading empty lines and comments.
+        let mut line = self.read_line()?;
+        if let Some(stripped) = line.strip_prefix('\u{feff}') {
+            line = stripped.to_string();
+        }
+        while !line.is_empty() && (isspace(&line) || line.starts_with("%") || line.starts_with(";")) {
+            line = self.read_line()?;
+        }
+
+        let mut consecutive_empty_lines = 0;
+        while !line.is_empty() {
+            if line.starts_with("%") || line.starts_with(";") {
+                line = self.read_line()?;
+                continue;
+            }
+            // Up to one empty line may separate headers.
+            if consecutive_empty_lines < 1 && isspace(&line) {
+                consecutive_empty_lines += 1;
+                line = self.read_line()?;
+                continue;
+            }
+            found_game = true;
+            if !line.starts_with("[") { break; }
+
+            consecutive_empty_lines = 0;
+            if let Some(tag) = TAG_REGEX.captures(&line) {
+                visitor.visit_header(&tag[1], &unescape_tag_value(&tag[2]));
+            }
+            line = self.read_line()?;
+        }
+        if !found_game { return Ok(None); }
+
+        // One board per open variation, the main line at the bottom.
+        let mut board_stack: Vec<Board> = vec![Board::new(None)];
+        board_stack[0].reset();
+
+        let mut fresh_line = true;
+        while !line.is_empty() {
+            let mut next_line = None;
 
-    pub fn read_line(&mut self) -> Option<io::Result<&String>> {
-        self.buffer.clear();
+            if fresh_line {
+                if line.starts_with("%") {
+                    line = self.read_line()?;
+                    continue;
+                }
+                // An empty line ends the game, and so do the headers of the
+                // next one.
+                if line.starts_with("[") {
+                    self.pending = Some(line);
+                    visitor.end_game();
+                    return Ok(Some(visitor));
+                }
+                if isspace(&line) {
+                    visitor.end_game();
+                    return Ok(Some(visitor));
+                }
+            }
 
-        self.reader
-            .read_line(&mut self.buffer)
-            .map(|u| if u == 0 { None } else { Some(&self.buffer) })
-            .transpose()
+            for re_match in MOVETEXT_REGEX.find_iter(&line) {
+                let token = re_match.as_str();
+
+                if let Some(comment) = token.strip_prefix("{") {
+                    // Consume lines until the closing brace, then continue
+                    // with the rest of that line.
+                    let mut comment_line = comment.to_string();
+                    let mut comment_lines = Vec::new();
+                    while !comment_line.is_empty() && !comment_line.contains("}") {
+                        comment_lines.push(comment_line.trim_end_matches(&['\r', '\n'][..]).to_string());
+                        comment_line = self.read_line()?;
+                    }
+                    match comment_line.find("}") {
+                        Some(end_index) => {
+                            comment_lines.push(comment_line[..end_index].to_string());
+                            next_line = Some(comment_line[end_index + 1..].to_string());
+                        }
+                        None => { next_line = Some(String::new()); }
+                    }
+                    visitor.visit_comment(comment_lines.join("\n").trim());
+                    break;
+                }
+                else if token == "(" {
+                    // A variation replaces the last move, so it starts from the
+                    // position before it. One before any move is ignored.
+                    if !board_stack.last().unwrap().move_stack.is_empty() {
+                        visitor.begin_variation();
+                        let mut board = board_stack.last().unwrap().copy(true);
+                        board.pop();
+                        board_stack.push(board);
+                    }
+                }
+                else if token == ")" {
+                    if board_stack.len() > 1 {
+                        visitor.end_variation();
+                        board_stack.pop();
+                    }
+                }
+                else if let Some(comment) = token.strip_prefix(";") {
+                    visitor.visit_comment(comment.trim());
+                    break;
+                }
+                else if let Some(nag) = token.strip_prefix("$") {
+                    if let Ok(nag) = nag.parse() {
+                        visitor.visit_nag(nag);
+                    }
+                }
+                else if token == "?" { visitor.visit_nag(NAG_MISTAKE); }
+                else if token == "??" { visitor.visit_nag(NAG_BLUNDER); }
+                else if token == "!" { visitor.visit_nag(NAG_GOOD_MOVE); }
+                else if token == "!!" { visitor.visit_nag(NAG_BRILLIANT_MOVE); }
+                else if token == "!?" { visitor.visit_nag(NAG_SPECULATIVE_MOVE); }
+                else if token == "?!" { visitor.visit_nag(NAG_DUBIOUS_MOVE); }
+                else if ["1-0", "0-1", "1/2-1/2", "*"].contains(&token) {
+                    visitor.visit_result(token);
+                }
+                else {
+                    let board = board_stack.last_mut().unwrap();
+                    let m = match visitor.parse_san(board, token) {
+                        Ok(m) => m,
+                        Err(e) => {
+                            let token = token.to_string();
+                            let line = self.read_line()?;
+                            self.read_until_end_of_game(line)?;
+                            return Err(ParsingError::InvalidMoveError { token, source: e });
+                        }
+                    };
+                    visitor.visit_move(board, m);
+                    board.push(m);
+                    visitor.visit_board(board);
+                }
+            }
+            match next_line {
+                Some(rest) if !isspace(&rest) => {
+                    line = rest;
+                    fresh_line = false;
+                }
+                _ => {
+                    line = self.read_line()?;
+                    fresh_line = true;
+                }
+            }
+        }
+        visitor.end_game();
+        Ok(Some(visitor))
+    }
+}
+impl<R: BufRead> Iterator for PgnReader<R> {
+    type Item = Result<Game, ParsingError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_game_builder().transpose().map(|visitor| visitor.map(|visitor| visitor.game))
     }
 }
 fn isspace(s: &str) -> bool {
     s.chars().all(|x| x.is_whitespace())
 }
-fn read_line_or_empty<'a>(lines: &'a mut std::str::Lines) -> &'a str {
-    match lines.next() {Some(s) => {s}, None => {""}}
-}
-// fn read_line_or_empty<>(handle: &mut BufReader) -> & str {
-//     match handle.read_line() {Some(s) => {s.expect("error while reading line")}, None => {""}}
-// }
-fn read_until_end_of_game<>(lines: &mut std::str::Lines) {
-    let mut line = read_line_or_empty(lines);
-    while !isspace(line){
-        line = read_line_or_empty(lines);
-    }
-}
-pub fn read_game_async(games: &[&str]) -> AHashMap<usize, Vec<u64>> {
-    //println!("games_len: {}", games.len());
-        let mut j = 0;
-        // let start_time = std::time::Instant::now();
+/// Numbers of legal moves by ply, with the errors of the games that could not
+/// be read by game index.
+pub type MoveCounts = (AHashMap<usize, Vec<u64>>, Vec<(usize, ParsingError)>);
+/// Number of legal moves at each ply of the main line of every game. A read
+/// error ends the input.
+pub fn read_game_async<R: BufRead>(reader: PgnReader<R>) -> MoveCounts {
         let mut a: AHashMap<usize, Vec<u64>> = AHashMap::new();
-        for i in 0..500 {
-            a.insert(i, Vec::new());
-        }
-        let mut total_moves:u64 = 0;
-        let mut size = 0;
-        for game_str in games {
-            let visitor = match read_game_str("[".to_string() + game_str) {
-                Ok(game) => {j += 1; game},
-                Err(ParsingError::ReadLineError) => {println!("Readline error: {}", j); break;}
-                Err(ParsingError::EmptyMoves) => {println!("Game has no moves: {}", j); continue;}
-                Err(ParsingError::InvalidMoveError) => {println!("Invalid move: {}", j); continue;}
+        let mut errors = Vec::new();
+        for (j, game) in reader.enumerate() {
+            let game = match game {
+                Ok(game) => game,
+                Err(e @ ParsingError::ReadLineError) => { errors.push((j, e)); break; }
+                Err(e) => { errors.push((j, e)); continue; }
             };
             let mut board = Board::new(Some(STARTING_FEN));
-            for (i, m) in visitor.borrow_mut().game.root.mainline_moves().iter().enumerate() {
-                if let Some(mu) = m {
-                    let no_moves = board.generate_legal_moves(BB_ALL, BB_ALL).count() as u64;
-                    total_moves += no_moves;
-                    size += 1;
-                    a.get_mut(&i).unwrap().push(no_moves);
-                    board.push(mu);
-                }
-                else {
-                    println!("break");
-                    break;
-                }
+            for (i, m) in game.root.mainline_moves().iter().flatten().enumerate() {
+                let no_moves = board.generate_legal_moves(BB_ALL, BB_ALL).count() as u64;
+                a.entry(i).or_default().push(no_moves);
+                board.push(m);
             }
-
         }
-        println!("total moves: {}, size: {}", total_moves, size);
-        return a;
+        (a, errors)
 }
+/// Parses the first game in `string`.
 pub fn read_game_str(string: String) -> Result<Rc<RefCell<GameBuilder>>, ParsingError> {
-    let visitor = Rc::new(RefCell::new(GameBuilder::new_and_begin()));
-    let handle = "[".to_owned() + &string;
-    // println!("handle: {}", handle);
-    let mut found_game = false;
-    let mut skipping_game = false;
-    // let mut headers: Option<&Headers> = None;
+    match PgnReader::new(string.as_bytes()).read_game_builder()? {
+        Some(visitor) => Ok(Rc::new(RefCell::new(visitor))),
+        None => Err(ParsingError::EmptyMoves),
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ParsingError {
+    #[error("Error while reading the line")]
+    ReadLineError,
+    #[error("Invalid move {token}: {source}")]
+    InvalidMoveError { token: String, source: SanError },
+    #[error("Error while reading the line")]
+    EmptyMoves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn mainline_uci(game: &Game) -> Vec<String> {
+        game.root.mainline_moves().iter().flatten().map(|m| m.uci()).collect()
+    }
+
+    #[test]
+    fn reader_streams_games_from_one_cursor() {
+        let pgn = "\u{feff}[Event \"a\"]\r\n\r\n1. e4 e5 1-0\r\n\r\n\
+                   [Event \"b\"]\n\n1. e4 Ke3 2. d4\n3. c4 *\n\n\
+                   [Event \"c\"]\n\n1. d4 d5 *\n\
+                   [Event \"d\"]\n1. c4 *\n";
+        let games: Vec<Result<Game, ParsingError>> = PgnReader::new(Cursor::new(pgn)).collect();
+        assert_eq!(games.len(), 4);
 
-    let mut board: Board = Board::new(None);
+        let a = games[0].as_ref().unwrap();
+        assert_eq!(a.headers.get("Event"), Some("a"));
+        assert_eq!(a.headers.get("Result"), Some("1-0"));
+        assert_eq!(mainline_uci(a), ["e2e4", "e7e5"]);
 
-    let mut lines = handle.lines();
+        assert!(matches!(&games[1], Err(ParsingError::InvalidMoveError { token, .. }) if token == "Ke3"));
 
-    let mut line = match lines.next() {
-        Some(line) => { line.trim_start_matches("\u{feff}")}
-        None => { return Err(ParsingError::ReadLineError);}
-    };
+        let c = games[2].as_ref().unwrap();
+        assert_eq!(c.headers.get("Event"), Some("c"));
+        assert_eq!(mainline_uci(c), ["d2d4", "d7d5"]);
 
-    if !line.starts_with("[Event"){
-        while !isspace(line) { line = read_line_or_empty(&mut lines); }
+        let d = games[3].as_ref().unwrap();
+        assert_eq!(d.headers.get("Event"), Some("d"));
+        assert_eq!(mainline_uci(d), ["c2c4"]);
     }
-    while isspace(line) || line.starts_with("%") || line.starts_with(";") {
-        line = match lines.next() {
-            Some(l) => {l},
-            _ => { println!("i: {}", unsafe{crate::I}); return Err(ParsingError::InvalidMoveError);}
-        }
+
+    #[test]
+    fn reader_recovers_without_blank_line_after_bad_game() {
+        let pgn = "[Event \"x\"]\n1. e4 Qh4 2. d4\n[Event \"y\"]\n1. Nf3 *\n";
+        let mut reader = PgnReader::new(Cursor::new(pgn));
+        assert!(reader.next().unwrap().is_err());
+        let y = reader.next().unwrap().unwrap();
+        assert_eq!(y.headers.get("Event"), Some("y"));
+        assert_eq!(mainline_uci(&y), ["g1f3"]);
+        assert!(reader.next().is_none());
     }
-    
-    let mut consecutive_empty_lines = 0;
 
-    while !line.is_empty() {
-        if line.starts_with("%") || line.starts_with(";") {
-            line = read_line_or_empty(&mut lines);
-            continue;
-        }
+    #[test]
+    fn read_game_async_collects_errors() {
+        let pgn = "[Event \"a\"]\n\n1. e4 e5 *\n\n[Event \"b\"]\n\n1. e5 *\n\n[Event \"c\"]\n\n1. d4 *\n";
+        let (counts, errors) = read_game_async(PgnReader::new(Cursor::new(pgn)));
+        assert_eq!(counts[&0], [20, 20]);
+        assert_eq!(counts[&1], [20]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 1);
+    }
 
-        if consecutive_empty_lines < 1 && isspace(line) {
-            consecutive_empty_lines += 1;
-            line = read_line_or_empty(&mut lines);
-            continue;
-        }
 
-        if !found_game {
-            found_game = true;
-            skipping_game = false;
-        }
-        if !line.starts_with("[") { break; }
+    fn game(movetext: &str) -> Game {
+        PgnReader::new(Cursor::new(format!("[Event \"?\"]\n\n{}\n", movetext))).next().unwrap().unwrap()
+    }
 
-        consecutive_empty_lines = 0;
+    fn uci(node: &NodeRef) -> String {
+        node.borrow().m.unwrap().uci()
+    }
 
-        if !skipping_game {
-            let tag_match = TAG_REGEX.captures(line);
-            if let Some(tag) = tag_match {
-                visitor.borrow_mut().visit_header(&tag[1], &tag[2]);
-            }
-        }
-        line = read_line_or_empty(&mut lines);
+    #[test]
+    fn nested_variations() {
+        let game = game("1. e4 e5 (1... c5 2. Nf3 (2. c3 d5) 2... d6) (1... e6) 2. Nf3 Nc6 *");
+        assert_eq!(mainline_uci(&game), ["e2e4", "e7e5", "g1f3", "b8c6"]);
 
-    }
-    if !found_game { return Err(ParsingError::EmptyMoves); }
+        let e4 = game.root.next().unwrap();
+        let replies: Vec<String> = e4.borrow().variations.iter().map(uci).collect();
+        assert_eq!(replies, ["e7e5", "c7c5", "e7e6"]);
 
-    if !skipping_game {
-        skipping_game = false;
+        let c5 = e4.borrow().variations[1].clone();
+        let moves: Vec<String> = c5.borrow().variations.iter().map(uci).collect();
+        assert_eq!(moves, ["g1f3", "c2c3"]);
+        let c3 = c5.borrow().variations[1].clone();
+        assert!(Rc::ptr_eq(c3.borrow().parent.as_ref().unwrap(), &c5));
+        let d5 = Node(c3.borrow().variations[0].clone());
+        assert_eq!(d5.board().fen(false), "rnbqkbnr/pp2pppp/8/2pp4/4P3/2P5/PP1P1PPP/RNBQKBNR w KQkq - 0 3");
+        let nf3 = c5.borrow().variations[0].clone();
+        assert_eq!(uci(&nf3.borrow().variations[0]), "d7d6");
     }
 
-    if !skipping_game {
-        board.reset();
+
+    #[test]
+    fn comments() {
+        let game = game("{Game comment} 1. e4 {best by test} e5 {multi\nline} 2. Nf3 ; rest of line\n2... Nc6 ({starts} 2... d6 {end}) 3. Bb5 *");
+        let root = game.root.0.clone();
+        assert_eq!(root.borrow().comment, "Game comment");
+        let e4 = root.borrow().variations[0].clone();
+        assert_eq!(e4.borrow().comment, "best by test");
+        let e5 = e4.borrow().variations[0].clone();
+        assert_eq!(e5.borrow().comment, "multi\nline");
+        let nf3 = e5.borrow().variations[0].clone();
+        assert_eq!(nf3.borrow().comment, "rest of line");
+        let d6 = nf3.borrow().variations[1].clone();
+        assert_eq!(d6.borrow().starting_comment, "starts");
+        assert_eq!(d6.borrow().comment, "end");
+        assert_eq!(mainline_uci(&game), ["e2e4", "e7e5", "g1f3", "b8c6", "f1b5"]);
     }
 
-    while !line.is_empty() {
-        let mut read_next_line = true;
 
-        if line.starts_with("%") || line.starts_with(";"){
-            line = read_line_or_empty(&mut lines);
-            continue;
-        }
-        if isspace(line) {
-            visitor.borrow_mut().end_game();
-            return Ok(visitor);
-        }
+    #[test]
+    fn nags() {
+        let game = game("1. e4! e5?! 2. Nf3!? $14 Nc6 $146 3. Bb5!! a6? 4. Ba4?? $4 *");
+        let nags: Vec<Vec<u64>> = game.root.mainline().iter().map(|node| {
+            let mut nags: Vec<u64> = node.borrow().nags.iter().copied().collect();
+            nags.sort();
+            nags
+        }).collect();
+        assert_eq!(nags, [
+            vec![NAG_GOOD_MOVE],
+            vec![NAG_DUBIOUS_MOVE],
+            vec![NAG_SPECULATIVE_MOVE, NAG_WHITE_SLIGHT_ADVANTAGE],
+            vec![NAG_NOVELTY],
+            vec![NAG_BRILLIANT_MOVE],
+            vec![NAG_MISTAKE],
+            vec![NAG_BLUNDER],
+        ]);
+        assert!(game.root.0.borrow().nags.is_empty());
+        assert_eq!(nag_symbol(NAG_SPECULATIVE_MOVE), Some("!?"));
+        assert_eq!(nag_symbol(200), None);
+    }
 
-        for re_match in MOVETEXT_REGEX.find_iter(line) {
-            let token = re_match.as_str();
 
-            if token.starts_with("{") {
-                //TODO: implement properly
-                while !line.is_empty() && !line.contains("}") { line = read_line_or_empty(&mut lines)}
+    #[test]
+    fn export_round_trip() {
+        let pgn = "[Event \"?\"]\n[Result \"1-0\"]\n\n{ Intro } 1. e4 e5 $1 ( 1... c5 { Sicilian } 2. Nf3 ( 2. c3 d5 ) 2... d6 ) ( { French } 1... e6 ) 2. Qh5?? { multi\nline   comment } 2... Nc6 3. Bc4 Nf6 $4 4. Qxf7# 1-0\n";
+        let game = PgnReader::new(Cursor::new(pgn)).next().unwrap().unwrap();
+        let exported = game.to_string();
+        let movetext = "{ Intro } 1. e4 e5 $1 ( 1... c5 { Sicilian } 2. Nf3 ( 2. c3 d5 ) 2... d6 ) ( { French } 1... e6 ) 2. Qh5 $4 { multi line comment } 2... Nc6 3. Bc4 Nf6 $4 4. Qxf7# 1-0";
+        assert_eq!(exported.lines().filter(|line| !line.starts_with('[')).collect::<Vec<_>>().join(" ").trim(), movetext);
 
-                if !line.is_empty() {
-                    read_next_line = true;
-                } 
-                break;
-            }
-            else if token == "(" {
-                panic!("( found")
-            }
-            else if token == ")" {
-                panic!(") found")
-            }
-            else if token.starts_with(";") { break; }
-            else if token.starts_with("$") {}
-            else if token == "?" {}
-            else if token == "??" {}
-            else if token == "!" {}
-            else if token == "!!" {}
-            else if token == "!?" {}
-            else if token == "?!" {}
-            else if ["1-0", "0-1", "1/2-1/2", "*"].contains(&token) {
-                visitor.borrow_mut().visit_result(token);
-            }
-            else {
-                let m = visitor.borrow().parse_san(&board, token);
-                if !m.bool() {
-                    let mut new_board = Board::new(Some(STARTING_BOARD_FEN));
-                    // for m in board.move_stack {
-                    //     println!("------------------");
-                    //     println!("{}", new_board.san_and_push(m));
-                    // }
-                    read_until_end_of_game(&mut lines);
-                    return Err(ParsingError::InvalidMoveError);
-                }
-                visitor.borrow_mut().visit_move(&board, m);
-                board.push(m);
-                visitor.borrow().visit_board(&board);
-            }
-        }
-        if read_next_line {
-            line = read_line_or_empty(&mut lines);
-        }
+        let reparsed = PgnReader::new(Cursor::new(exported.clone())).next().unwrap().unwrap();
+        assert_eq!(reparsed.to_string(), exported);
+
+        let mut exporter = StringExporter::new(Some(30));
+        game.accept(&mut exporter);
+        let wrapped = exporter.result();
+        assert!(wrapped.lines().all(|line| line.chars().count() <= 30 || line.starts_with('[')), "{}", wrapped);
+        assert_eq!(PgnReader::new(Cursor::new(wrapped)).next().unwrap().unwrap().to_string(), exported);
     }
-    Ok(visitor)
-}
 
+    #[test]
+    fn export_escapes_header_values() {
+        let pgn = "[Event \"The \\\"Immortal\\\" Game\"]\n[Site \"C:\\\\London\"]\n\n1. e4 *\n";
+        let game = PgnReader::new(Cursor::new(pgn)).next().unwrap().unwrap();
+        assert_eq!(game.headers.get("Event"), Some("The \"Immortal\" Game"));
+        assert_eq!(game.headers.get("Site"), Some("C:\\London"));
 
-#[derive(Error, Debug)]
-pub enum ParsingError {
-    #[error("Error while reading the line")]
-    ReadLineError,
-    #[error("Error while reading the line")]
-    InvalidMoveError,
-    #[error("Error while reading the line")]
-    EmptyMoves
+        let exported = game.to_string();
+        assert!(exported.starts_with(pgn.lines().next().unwrap()), "{}", exported);
+        let reparsed = PgnReader::new(Cursor::new(exported.clone())).next().unwrap().unwrap();
+        assert_eq!(reparsed.headers.get("Event"), Some("The \"Immortal\" Game"));
+        assert_eq!(reparsed.headers.get("Site"), Some("C:\\London"));
+        assert_eq!(reparsed.to_string(), exported);
+    }
 }
 
 
@@ -1,10 +1,6 @@
 
 use std::fmt::Debug;
 
-use requests;
-use requests::ToJson;
-use json;
-use str;
 use crate::init::{Move, WHITE, BLACK};
 
 static URL: &str = "http://tablebase.lichess.ovh/standard/mainline";
@@ -28,22 +24,22 @@ impl QueryResult {
         QueryResult{
             dtz50: dtz,
             mainline: moves,
-            winner: winner
+            winner
         }
     }
 }
 pub fn make_api_call(fen: &str) -> Option<QueryResult>{
-    let response = match requests::get(
-        format!("{}?fen={}", URL, str::replace(fen, " ", "_"))
-    ) {
+    let response = match ureq::get(URL).query("fen", fen).call() {
         Ok(r) => {r},
         Err(e) => {println!("bad request: {:?}", e); return None;}
     };
-    let json = match response.json() {
+    let body = match response.into_string() {
         Ok(r) => {r},
-        Err(e) => {println!("response: ({})", 
-            std::str::from_utf8(response.content())
-                .expect("error while converting Vec<u8> to string")); return None;}
+        Err(e) => {println!("bad response: {:?}", e); return None;}
+    };
+    let json = match json::parse(&body) {
+        Ok(r) => {r},
+        Err(_) => {println!("response: ({})", body); return None;}
     };
     match json["winner"].as_str() {
         Some(win) => {
@@ -0,0 +1,460 @@
+use crate::init::{
+    between, popcount, ray, scan_reversed, square, square_distance, square_file, square_rank, BaseBoard, Bitboard, Board,
+    Boolean, Color, Move, PieceType, Square, BB_CORNERS, BB_FILES, BB_FILE_A, BB_FILE_B, BB_FILE_G, BB_FILE_H,
+    BB_KING_ATTACKS, BB_RANKS, BB_RANK_1, BB_RANK_2, BB_RANK_7, BB_RANK_8, BB_SQUARES, BLACK, KING, SEE_PIECE_VALUES,
+    WHITE,
+};
+
+/// A tactical motif on the board, with the squares of the pieces involved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tactic {
+    /// The piece on `square` is attacked by `attackers` and is either
+    /// undefended or attacked by a less valuable piece.
+    Hanging { square: Square, attackers: Bitboard },
+    /// The piece on `attacker` attacks all of `targets` at once: the king,
+    /// more valuable or undefended pieces.
+    Fork { attacker: Square, targets: Bitboard },
+    /// The slider on `pinner` attacks the piece on `pinned`, which shields
+    /// the more valuable piece on `target`. Absolute if that is the king.
+    Pin { pinner: Square, pinned: Square, target: Square, absolute: bool },
+    /// The slider on `attacker` attacks the piece on `front`, which exposes
+    /// the less valuable piece on `behind` when it moves away.
+    Skewer { attacker: Square, front: Square, behind: Square },
+    /// The move from `from_square` uncovered attacks of the slider on
+    /// `attacker` on `targets`, giving check if the king is among them.
+    DiscoveredAttack { from_square: Square, attacker: Square, targets: Bitboard, check: bool },
+    /// The piece on `defender` is the only defender of all of the attacked
+    /// pieces in `defended`.
+    Overloaded { defender: Square, defended: Bitboard },
+}
+impl Tactic {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Tactic::Hanging { .. } => "hanging piece",
+            Tactic::Fork { .. } => "fork",
+            Tactic::Pin { absolute: true, .. } => "absolute pin",
+            Tactic::Pin { absolute: false, .. } => "relative pin",
+            Tactic::Skewer { .. } => "skewer",
+            Tactic::DiscoveredAttack { check: true, .. } => "discovered check",
+            Tactic::DiscoveredAttack { check: false, .. } => "discovered attack",
+            Tactic::Overloaded { .. } => "overloaded defender",
+        }
+    }
+}
+
+/// Piece value for comparing targets, with the king above everything.
+fn value(piece_type: PieceType) -> i32 {
+    if piece_type == KING { 100_000 } else { SEE_PIECE_VALUES[piece_type as usize] }
+}
+
+/// All motifs on the board for both sides. Discovered attacks need the
+/// move that was just played.
+pub fn find_tactics(board: &Board, last_move: Option<Move>) -> Vec<Tactic> {
+    let mut tactics = hanging_pieces(board);
+    tactics.extend(forks(board));
+    tactics.extend(pins_and_skewers(board));
+    if let Some(m) = last_move {
+        tactics.extend(discovered_attacks(board, m));
+    }
+    tactics.extend(overloaded_defenders(board));
+    tactics
+}
+
+pub fn hanging_pieces(board: &Board) -> Vec<Tactic> {
+    let b = &board.baseboard;
+    let mut tactics = Vec::new();
+    for square in scan_reversed(b.occupied & !b.kings) {
+        let color = b.color_at(square).unwrap();
+        let piece_value = value(b.piece_type_at(square).unwrap());
+        let attackers = b.attackers_mask(!color, square);
+        if attackers == 0 {
+            continue;
+        }
+        let cheapest = scan_reversed(attackers).map(|attacker| value(b.piece_type_at(attacker).unwrap())).min().unwrap();
+        if b.attackers_mask(color, square) == 0 || cheapest < piece_value {
+            tactics.push(Tactic::Hanging { square, attackers });
+        }
+    }
+    tactics
+}
+
+pub fn forks(board: &Board) -> Vec<Tactic> {
+    let b = &board.baseboard;
+    let mut tactics = Vec::new();
+    for attacker in scan_reversed(b.occupied) {
+        let color = b.color_at(attacker).unwrap();
+        let attacker_value = value(b.piece_type_at(attacker).unwrap());
+        let mut targets = 0;
+        for target in scan_reversed(b.attacks_mask(attacker) & b.occupied_co[!color as usize]) {
+            if value(b.piece_type_at(target).unwrap()) > attacker_value || b.attackers_mask(!color, target) == 0 {
+                targets |= BB_SQUARES[target as usize];
+            }
+        }
+        if popcount(targets) >= 2 {
+            tactics.push(Tactic::Fork { attacker, targets });
+        }
+    }
+    tactics
+}
+
+/// Pins and skewers, found by looking through the first enemy piece a
+/// slider attacks to the next piece on the same line.
+pub fn pins_and_skewers(board: &Board) -> Vec<Tactic> {
+    let b = &board.baseboard;
+    let mut tactics = Vec::new();
+    let sliders = b.bishops | b.rooks | b.queens;
+    for attacker in scan_reversed(sliders) {
+        let color = b.color_at(attacker).unwrap();
+        let enemies = b.occupied_co[!color as usize];
+        for front in scan_reversed(b.attacks_mask(attacker) & enemies) {
+            let front_bb = BB_SQUARES[front as usize];
+            let behind = scan_reversed(ray(attacker, front) & enemies & !front_bb)
+                .find(|&square| between(attacker, square) & b.occupied == front_bb);
+            let behind = match behind {
+                Some(behind) => behind,
+                None => continue,
+            };
+            let front_type = b.piece_type_at(front).unwrap();
+            let behind_type = b.piece_type_at(behind).unwrap();
+            if value(front_type) < value(behind_type) {
+                tactics.push(Tactic::Pin { pinner: attacker, pinned: front, target: behind, absolute: behind_type == KING });
+            } else if value(front_type) > value(behind_type) {
+                tactics.push(Tactic::Skewer { attacker, front, behind });
+            }
+        }
+    }
+    tactics
+}
+
+/// Attacks uncovered by `m`, which must be the move just played.
+pub fn discovered_attacks(board: &Board, m: Move) -> Vec<Tactic> {
+    let b = &board.baseboard;
+    let color: Color = !board.turn;
+    let from_bb = BB_SQUARES[m.from_square as usize];
+    let mut tactics = Vec::new();
+    if !m.bool() || m.drop.is_some() {
+        return tactics;
+    }
+    let sliders = (b.bishops | b.rooks | b.queens) & b.occupied_co[color as usize] & !BB_SQUARES[m.to_square as usize];
+    for attacker in scan_reversed(sliders) {
+        let targets = scan_reversed(b.attacks_mask(attacker) & b.occupied_co[!color as usize])
+            .filter(|&target| between(attacker, target) & from_bb != 0)
+            .fold(0, |targets, target| targets | BB_SQUARES[target as usize]);
+        if targets != 0 {
+            let check = targets & b.kings != 0;
+            tactics.push(Tactic::DiscoveredAttack { from_square: m.from_square, attacker, targets, check });
+        }
+    }
+    tactics
+}
+
+pub fn overloaded_defenders(board: &Board) -> Vec<Tactic> {
+    let b = &board.baseboard;
+    let mut tactics = Vec::new();
+    for color in [WHITE, BLACK] {
+        let ours = b.occupied_co[color as usize];
+        for defender in scan_reversed(ours) {
+            let defender_bb = BB_SQUARES[defender as usize];
+            let defended = scan_reversed(b.attacks_mask(defender) & ours & !b.kings)
+                .filter(|&square| b.is_attacked_by(!color, square) && b.attackers_mask(color, square) == defender_bb)
+                .fold(0, |defended, square| defended | BB_SQUARES[square as usize]);
+            if popcount(defended) >= 2 {
+                tactics.push(Tactic::Overloaded { defender, defended });
+            }
+        }
+    }
+    tactics
+}
+
+/// A named checkmate pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatePattern {
+    /// A rook or queen mates on the back rank, where the king is shut in
+    /// by its own pieces.
+    BackRank,
+    /// A knight mates a king surrounded by its own pieces.
+    Smothered,
+    /// A rook or queen mates on the edge file, with a knight three squares
+    /// away covering the escape past the king's own blocker.
+    Anastasia,
+    /// A rook next to the cornered king, defended by a knight.
+    Arabian,
+    /// Two bishops from both sides of the king on crossing diagonals.
+    Boden,
+    /// Two bishops from the same side of the king.
+    DoubleBishop,
+    /// A queen diagonally next to the king, whose own pieces block the
+    /// two squares behind it.
+    Dovetail,
+    /// A queen mates from two squares in front of a king whose own pieces
+    /// stand on both sides of it along the edge.
+    Epaulette,
+    /// A rook next to the king, defended by a nearby knight that a pawn
+    /// defends.
+    Hook,
+    /// A rook or queen on the edge, with another one cutting off the line
+    /// next to it.
+    Ladder,
+}
+impl MatePattern {
+    pub fn label(&self) -> &'static str {
+        match self {
+            MatePattern::BackRank => "back rank mate",
+            MatePattern::Smothered => "smothered mate",
+            MatePattern::Anastasia => "Anastasia's mate",
+            MatePattern::Arabian => "Arabian mate",
+            MatePattern::Boden => "Boden's mate",
+            MatePattern::DoubleBishop => "double bishop mate",
+            MatePattern::Dovetail => "dovetail mate",
+            MatePattern::Epaulette => "epaulette mate",
+            MatePattern::Hook => "hook mate",
+            MatePattern::Ladder => "ladder mate",
+        }
+    }
+}
+
+/// Patterns of the mate of the side to move, or none if it is not mated.
+pub fn checkmate_patterns(board: &Board) -> Vec<MatePattern> {
+    if !board.is_checkmate() {
+        return Vec::new();
+    }
+    mate_patterns(&board.baseboard, board.turn)
+}
+
+/// Patterns matching the final position of a mate of `mated`. The mate
+/// itself is not verified, see `checkmate_patterns`.
+pub fn mate_patterns(board: &BaseBoard, mated: Color) -> Vec<MatePattern> {
+    let king = match board.king(mated) {
+        Some(king) => king,
+        None => return Vec::new(),
+    };
+    let winner = !mated;
+    let ours = board.occupied_co[mated as usize];
+    let theirs = board.occupied_co[winner as usize];
+    let heavy = (board.rooks | board.queens) & theirs;
+    let checkers = board.attackers_mask(winner, king);
+    let zone = BB_KING_ATTACKS[king as usize];
+    let (file, rank) = (square_file(king), square_rank(king));
+    let mut patterns = Vec::new();
+
+    let (back_rank, front_rank) = if mated == WHITE { (0, 1) } else { (7, 6) };
+    let front = zone & BB_RANKS[front_rank as usize];
+    if rank == back_rank
+        && checkers & heavy & BB_RANKS[back_rank as usize] != 0
+        && front & !ours == 0
+        && scan_reversed(front).all(|square| !board.is_attacked_by(winner, square))
+    {
+        patterns.push(MatePattern::BackRank);
+    }
+
+    if checkers & board.knights != 0 && zone & !ours == 0 {
+        patterns.push(MatePattern::Smothered);
+    }
+
+    if (file == 0 || file == 7) && rank != 0 && rank != 7 && checkers & heavy & BB_FILES[file as usize] != 0 {
+        let inward = |steps: u8| BB_SQUARES[square(if file == 0 { steps } else { 7 - steps }, rank) as usize];
+        if ours & inward(1) != 0 && theirs & board.knights & inward(3) != 0 {
+            patterns.push(MatePattern::Anastasia);
+        }
+    }
+
+    let rook_checkers = checkers & board.rooks & zone;
+    let diagonal_two = |square: Square| {
+        (square_file(square) as i8 - file as i8).abs() == 2 && (square_rank(square) as i8 - rank as i8).abs() == 2
+    };
+    if BB_CORNERS & BB_SQUARES[king as usize] != 0
+        && scan_reversed(rook_checkers)
+            .any(|rook| scan_reversed(board.attackers_mask(winner, rook) & board.knights).any(diagonal_two))
+    {
+        patterns.push(MatePattern::Arabian);
+    }
+    if scan_reversed(rook_checkers).any(|rook| {
+        scan_reversed(board.attackers_mask(winner, rook) & board.knights)
+            .filter(|&knight| square_distance(knight, king) == 2)
+            .any(|knight| board.attackers_mask(winner, knight) & board.pawns != 0)
+    }) {
+        patterns.push(MatePattern::Hook);
+    }
+
+    // Every square around the king must be covered by bishops alone.
+    let bishops = board.bishops & theirs;
+    if checkers & bishops != 0 {
+        let attackers = scan_reversed(zone | BB_SQUARES[king as usize])
+            .fold(0, |attackers, square| attackers | board.attackers_mask(winner, square));
+        if attackers & !bishops == 0 && popcount(attackers) >= 2 {
+            let files: Vec<Square> = scan_reversed(attackers).map(square_file).collect();
+            if files.iter().any(|&f| f < file) && files.iter().any(|&f| f > file) {
+                patterns.push(MatePattern::Boden);
+            } else {
+                patterns.push(MatePattern::DoubleBishop);
+            }
+        }
+    }
+
+    if file != 0 && file != 7 && rank != 0 && rank != 7 {
+        let dovetail = scan_reversed(checkers & board.queens & zone).any(|queen| {
+            let queen_bb = BB_SQUARES[queen as usize];
+            square_file(queen) != file
+                && square_rank(queen) != rank
+                && scan_reversed(zone & !queen_bb).all(|square| match board.attackers_mask(winner, square) {
+                    0 => true,
+                    attackers => attackers == queen_bb && board.occupied & BB_SQUARES[square as usize] == 0,
+                })
+        });
+        if dovetail {
+            patterns.push(MatePattern::Dovetail);
+        }
+    }
+
+    // The edge lines the king stands on, the line next to each and the
+    // line across it through the king.
+    let edges = [
+        (rank == 0, BB_RANK_1, BB_RANK_2, BB_FILES[file as usize]),
+        (rank == 7, BB_RANK_8, BB_RANK_7, BB_FILES[file as usize]),
+        (file == 0, BB_FILE_A, BB_FILE_B, BB_RANKS[rank as usize]),
+        (file == 7, BB_FILE_H, BB_FILE_G, BB_RANKS[rank as usize]),
+    ];
+    for (on_edge, edge, inward, across) in edges {
+        if !on_edge {
+            continue;
+        }
+        let sides = zone & edge;
+        if popcount(sides) == 2
+            && sides & !ours == 0
+            && scan_reversed(checkers & board.queens & across).any(|queen| square_distance(queen, king) == 2)
+            && !patterns.contains(&MatePattern::Epaulette)
+        {
+            patterns.push(MatePattern::Epaulette);
+        }
+        if checkers & heavy & edge != 0 && heavy & inward & !checkers != 0 && !patterns.contains(&MatePattern::Ladder) {
+            patterns.push(MatePattern::Ladder);
+        }
+    }
+    patterns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init::parse_square;
+
+    fn sq(name: &str) -> Square {
+        parse_square(name)
+    }
+
+    fn bb(names: &[&str]) -> Bitboard {
+        names.iter().fold(0, |bb, name| bb | BB_SQUARES[sq(name) as usize])
+    }
+
+    fn board(fen: &str) -> Board {
+        Board::from_fen(fen).unwrap()
+    }
+
+    #[test]
+    fn hanging_pieces_are_undefended_or_attacked_by_cheaper_pieces() {
+        let tactics = hanging_pieces(&board("6k1/8/2n5/8/8/2R5/8/4K3 w - - 0 1"));
+        assert_eq!(tactics, [Tactic::Hanging { square: sq("c6"), attackers: bb(&["c3"]) }]);
+        assert_eq!(tactics[0].label(), "hanging piece");
+        // Defended, but attacked by a less valuable piece.
+        let tactics = hanging_pieces(&board("6k1/1p6/2r5/8/3N4/8/8/4K3 w - - 0 1"));
+        assert_eq!(tactics, [Tactic::Hanging { square: sq("c6"), attackers: bb(&["d4"]) }]);
+        // Defended and only attacked by a more valuable piece.
+        assert!(hanging_pieces(&board("6k1/1p6/2n5/8/8/2R5/8/4K3 w - - 0 1")).is_empty());
+    }
+
+    #[test]
+    fn forks_need_two_valuable_or_undefended_targets() {
+        let mut forked = board("r3k3/8/8/1N6/8/8/8/4K3 w - - 0 1");
+        let m = forked.push_san("Nc7+").unwrap();
+        let tactics = find_tactics(&forked, Some(m));
+        assert!(tactics.contains(&Tactic::Fork { attacker: sq("c7"), targets: bb(&["a8", "e8"]) }), "{:?}", tactics);
+        assert_eq!(Tactic::Fork { attacker: sq("c7"), targets: 0 }.label(), "fork");
+        // Two defended pawns are not worth a knight.
+        assert!(forks(&board("4k3/8/1p1p4/2p5/1N6/8/8/4K3 w - - 0 1")).is_empty());
+    }
+
+    #[test]
+    fn pins_and_skewers_compare_the_front_and_back_piece() {
+        let tactics = pins_and_skewers(&board("4k3/4n3/8/8/1b6/8/3N4/3QK3 w - - 0 1"));
+        let pin = Tactic::Pin { pinner: sq("b4"), pinned: sq("d2"), target: sq("e1"), absolute: true };
+        assert!(tactics.contains(&pin), "{:?}", tactics);
+        assert_eq!(pin.label(), "absolute pin");
+
+        let tactics = pins_and_skewers(&board("3qk3/8/8/8/8/8/3B4/3QK3 b - - 0 1"));
+        let pin = Tactic::Pin { pinner: sq("d8"), pinned: sq("d2"), target: sq("d1"), absolute: false };
+        assert!(tactics.contains(&pin), "{:?}", tactics);
+        assert_eq!(pin.label(), "relative pin");
+
+        let tactics = pins_and_skewers(&board("7r/8/8/8/3q4/8/1B6/K3k3 w - - 0 1"));
+        assert!(tactics.contains(&Tactic::Skewer { attacker: sq("b2"), front: sq("d4"), behind: sq("h8") }), "{:?}", tactics);
+
+        // Pieces of equal value on the line are neither.
+        assert!(pins_and_skewers(&board("7n/8/8/8/3b4/8/1B6/4K2k w - - 0 1")).is_empty());
+    }
+
+    #[test]
+    fn discovered_attacks_come_from_the_vacated_square() {
+        let mut board = board("4k3/8/8/8/8/8/4N3/4R1K1 w - - 0 1");
+        let m = board.push_san("Nc3+").unwrap();
+        let tactics = discovered_attacks(&board, m);
+        assert_eq!(tactics, [Tactic::DiscoveredAttack { from_square: sq("e2"), attacker: sq("e1"), targets: bb(&["e8"]), check: true }]);
+        assert_eq!(tactics[0].label(), "discovered check");
+        board.pop();
+
+        let m = board.push_san("Kh1").unwrap();
+        assert!(discovered_attacks(&board, m).is_empty());
+        board.pop();
+
+        board.set_fen("4k3/4q3/8/8/8/8/4N3/4R1K1 w - - 0 1");
+        let m = board.push_san("Nc3").unwrap();
+        let tactics = discovered_attacks(&board, m);
+        assert_eq!(tactics, [Tactic::DiscoveredAttack { from_square: sq("e2"), attacker: sq("e1"), targets: bb(&["e7"]), check: false }]);
+        assert_eq!(tactics[0].label(), "discovered attack");
+    }
+
+    #[test]
+    fn overloaded_defenders_guard_several_attacked_pieces() {
+        let tactics = overloaded_defenders(&board("6k1/8/2n1b3/3q4/8/2R1R3/8/4K3 w - - 0 1"));
+        let overloaded = Tactic::Overloaded { defender: sq("d5"), defended: bb(&["c6", "e6"]) };
+        assert_eq!(tactics, [overloaded]);
+        assert_eq!(overloaded.label(), "overloaded defender");
+        // A second defender of c6 takes the load off the queen.
+        assert!(overloaded_defenders(&board("6k1/1p6/2n1b3/3q4/8/2R1R3/8/4K3 w - - 0 1")).is_empty());
+    }
+
+    #[test]
+    fn mate_patterns_by_name() {
+        let cases = [
+            ("3R2k1/5ppp/8/8/8/8/5PPP/6K1 b - - 0 1", MatePattern::BackRank),
+            ("6rk/5Npp/8/8/8/8/8/6K1 b - - 0 1", MatePattern::Smothered),
+            ("8/4N1pk/8/7R/8/8/8/6K1 b - - 0 1", MatePattern::Anastasia),
+            ("7k/7R/5N2/8/8/8/8/6K1 b - - 0 1", MatePattern::Arabian),
+            ("2kr4/3p4/B7/8/5B2/8/8/6K1 b - - 0 1", MatePattern::Boden),
+            ("7k/7p/8/8/8/8/B7/B5K1 b - - 0 1", MatePattern::DoubleBishop),
+            ("8/4pp2/4kp2/3Q4/2P5/8/8/6K1 b - - 0 1", MatePattern::Dovetail),
+            ("3rkr2/8/4Q3/8/8/8/8/6K1 b - - 0 1", MatePattern::Epaulette),
+            ("5kr1/5R2/3N4/2P5/8/8/8/6K1 b - - 0 1", MatePattern::Hook),
+            ("R6k/1R6/8/8/8/8/8/6K1 b - - 0 1", MatePattern::Ladder),
+        ];
+        for (fen, pattern) in cases {
+            let board = board(fen);
+            assert!(board.is_checkmate(), "{}", fen);
+            assert_eq!(checkmate_patterns(&board), [pattern], "{}", fen);
+        }
+        assert_eq!(MatePattern::Anastasia.label(), "Anastasia's mate");
+    }
+
+    #[test]
+    fn mate_patterns_need_a_mate() {
+        // Scholar's mate has no name here.
+        let scholars = board("r1bqkb1r/pppp1Qpp/2n2n2/4p3/2B1P3/8/PPPP1PPP/RNB1K1NR b KQkq - 0 4");
+        assert!(scholars.is_checkmate());
+        assert!(checkmate_patterns(&scholars).is_empty());
+        // The king escapes through the gap in front of it.
+        assert!(checkmate_patterns(&board("3R2k1/5p1p/8/8/8/8/5PPP/6K1 b - - 0 1")).is_empty());
+        // The bishop takes the checking knight.
+        assert!(checkmate_patterns(&board("6rk/5Npp/8/8/8/8/b7/6K1 b - - 0 1")).is_empty());
+        assert!(checkmate_patterns(&board(crate::init::STARTING_FEN)).is_empty());
+        assert!(mate_patterns(&Board::new(None).baseboard, BLACK).is_empty());
+    }
+}
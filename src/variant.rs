@@ -0,0 +1,961 @@
+use crate::init::{
+    msb, piece_symbol, popcount, scan_forward, Bitboard, Board, Color, Move, PieceType, Square, BB_ALL, BB_BACKRANKS,
+    BB_CENTER, BB_DARK_SQUARES, BB_KING_ATTACKS, BB_LIGHT_SQUARES, BB_RANK_1, BB_RANK_8, BB_SQUARES, BLACK, KING,
+    PAWN, PIECE_TYPES, QUEEN, ROOK, STARTING_FEN, STATUS_BAD_CASTLING_RIGHTS, STATUS_IMPOSSIBLE_CHECK,
+    STATUS_NO_BLACK_KING, STATUS_NO_WHITE_KING, STATUS_OPPOSITE_CHECK, STATUS_PAWNS_ON_BACKRANK, STATUS_RACE_CHECK,
+    STATUS_RACE_MATERIAL, STATUS_RACE_OVER, STATUS_TOO_MANY_BLACK_PAWNS, STATUS_TOO_MANY_BLACK_PIECES,
+    STATUS_TOO_MANY_CHECKERS, STATUS_TOO_MANY_KINGS, STATUS_TOO_MANY_WHITE_PAWNS, STATUS_TOO_MANY_WHITE_PIECES, WHITE,
+};
+
+/// Rules of a chess variant. Every hook defaults to the rules of standard
+/// chess, so a variant only overrides what it changes. The board keeps a
+/// `&'static dyn Variant` and routes move generation, game end detection
+/// and validation through it.
+pub trait Variant: Sync {
+    /// Name used in the `Variant` PGN header.
+    fn name(&self) -> &'static str;
+    /// Alternative names accepted when looking the variant up.
+    fn aliases(&self) -> &'static [&'static str] {
+        &[]
+    }
+    /// Value of the `UCI_Variant` engine option.
+    fn uci_variant(&self) -> &'static str;
+    fn starting_fen(&self) -> &'static str {
+        STARTING_FEN
+    }
+    /// Whether captured pieces go to a pocket and can be dropped, which
+    /// also adds the pocket to the FEN.
+    fn has_pockets(&self) -> bool {
+        false
+    }
+    /// Whether `Board::push` counts the checks given, which also adds the
+    /// remaining checks to the FEN.
+    fn has_check_counts(&self) -> bool {
+        false
+    }
+    /// Whether the rules treat both colors and all ranks alike, so that
+    /// `Board::canonical` may mirror colors and, without pawns, rotate the
+    /// board.
+    fn has_symmetric_rules(&self) -> bool {
+        true
+    }
+    fn generate_pseudo_legal_moves<'a>(&self, board: &'a Board, from_mask: Bitboard, to_mask: Bitboard) -> Box<dyn Iterator<Item = Move> + 'a> {
+        Box::new(board.generate_standard_pseudo_legal_moves(from_mask, to_mask))
+    }
+    fn generate_legal_moves<'a>(&self, board: &'a Board, from_mask: Bitboard, to_mask: Bitboard) -> Box<dyn Iterator<Item = Move> + 'a> {
+        Box::new(board.generate_standard_legal_moves(from_mask, to_mask))
+    }
+    fn is_pseudo_legal(&self, board: &Board, m: Move) -> bool {
+        board.is_standard_pseudo_legal(m)
+    }
+    fn is_legal(&self, board: &Board, m: Move) -> bool {
+        board.is_standard_legal(m)
+    }
+    /// Called by `Board::push` after `m` captured a piece of type
+    /// `piece_type` on `capture_square`, before the turn changes.
+    fn push_capture(&self, _board: &mut Board, _m: Move, _capture_square: Square, _piece_type: PieceType, _was_promoted: bool) {}
+    /// Called by `Board::pop` after the captured piece is back on the
+    /// board, to reverse `push_capture`.
+    fn pop_capture(&self, _board: &mut Board, _m: Move, _capture_square: Square, _piece_type: PieceType, _was_promoted: bool) {}
+    /// Whether pushing `m` changes squares other than those the move
+    /// touches. The whole board is then saved for `Board::pop`.
+    fn push_modifies_board(&self, _board: &Board, _m: Move) -> bool {
+        false
+    }
+    fn is_check(&self, board: &Board) -> bool {
+        board.is_standard_check()
+    }
+    /// Whether `m` would leave the king of the side to move in check.
+    fn is_into_check(&self, board: &Board, m: Move) -> bool {
+        board.is_standard_into_check(m)
+    }
+    /// Whether the side that just moved left its king in check.
+    fn was_into_check(&self, board: &Board) -> bool {
+        board.standard_was_into_check()
+    }
+    /// Whether the king may not pass through any square of `path` when
+    /// castling, with `occupied` as the occupancy.
+    fn attacked_for_king(&self, board: &Board, path: Bitboard, occupied: Bitboard) -> bool {
+        board.standard_attacked_for_king(path, occupied)
+    }
+    /// Whether the game ended for a reason specific to the variant.
+    fn is_variant_end(&self, _board: &Board) -> bool {
+        false
+    }
+    /// Whether the side to move lost by the rules of the variant.
+    fn is_variant_loss(&self, _board: &Board) -> bool {
+        false
+    }
+    /// Whether the side to move won by the rules of the variant.
+    fn is_variant_win(&self, _board: &Board) -> bool {
+        false
+    }
+    fn is_variant_draw(&self, _board: &Board) -> bool {
+        false
+    }
+    fn has_insufficient_material(&self, board: &Board, color: Color) -> bool {
+        board.has_standard_insufficient_material(color)
+    }
+    /// Bitmask of `STATUS_*` flags, see `Board::status`.
+    fn status(&self, board: &Board) -> u32 {
+        board.standard_status()
+    }
+}
+
+pub struct Standard;
+impl Variant for Standard {
+    fn name(&self) -> &'static str {
+        "Standard"
+    }
+    fn aliases(&self) -> &'static [&'static str] {
+        &["Standard", "Chess", "Classical", "Normal", "Illegal", "From Position"]
+    }
+    fn uci_variant(&self) -> &'static str {
+        "chess"
+    }
+}
+pub static STANDARD: Standard = Standard;
+
+/// Captured pieces change sides and can be dropped back onto the board
+/// instead of making a move. Promoted pieces turn back into pawns when
+/// captured.
+pub struct Crazyhouse;
+impl Variant for Crazyhouse {
+    fn name(&self) -> &'static str {
+        "Crazyhouse"
+    }
+    fn aliases(&self) -> &'static [&'static str] {
+        &["Crazyhouse", "Crazy House", "House", "ZH"]
+    }
+    fn uci_variant(&self) -> &'static str {
+        "crazyhouse"
+    }
+    fn starting_fen(&self) -> &'static str {
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR[] w KQkq - 0 1"
+    }
+    fn has_pockets(&self) -> bool {
+        true
+    }
+    fn generate_pseudo_legal_moves<'a>(&self, board: &'a Board, from_mask: Bitboard, to_mask: Bitboard) -> Box<dyn Iterator<Item = Move> + 'a> {
+        Box::new(board.generate_standard_pseudo_legal_moves(from_mask, to_mask)
+            .chain(board.generate_pseudo_legal_drops(from_mask & to_mask)))
+    }
+    fn generate_legal_moves<'a>(&self, board: &'a Board, from_mask: Bitboard, to_mask: Bitboard) -> Box<dyn Iterator<Item = Move> + 'a> {
+        Box::new(board.generate_standard_legal_moves(from_mask, to_mask)
+            .chain(board.generate_legal_drops(from_mask & to_mask)))
+    }
+    fn is_pseudo_legal(&self, board: &Board, m: Move) -> bool {
+        match m.drop {
+            Some(drop) => {
+                let to_bb = BB_SQUARES[m.to_square as usize];
+                m.from_square == m.to_square
+                    && m.promotion.is_none()
+                    && drop != KING
+                    && board.baseboard.occupied & to_bb == 0
+                    && !(drop == PAWN && BB_BACKRANKS & to_bb != 0)
+                    && board.pockets[board.turn as usize].count(drop) > 0
+            }
+            None => board.is_standard_pseudo_legal(m),
+        }
+    }
+    fn is_legal(&self, board: &Board, m: Move) -> bool {
+        if m.drop.is_some() {
+            self.is_pseudo_legal(board, m)
+                && board.legal_drop_squares_mask() & BB_SQUARES[m.to_square as usize] != 0
+        } else {
+            board.is_standard_legal(m)
+        }
+    }
+    fn push_capture(&self, board: &mut Board, _m: Move, _capture_square: Square, piece_type: PieceType, was_promoted: bool) {
+        let pocket = &mut board.pockets[board.turn as usize];
+        pocket.add(if was_promoted { PAWN } else { piece_type });
+    }
+    fn pop_capture(&self, board: &mut Board, _m: Move, _capture_square: Square, piece_type: PieceType, was_promoted: bool) {
+        let pocket = &mut board.pockets[board.turn as usize];
+        pocket.remove(if was_promoted { PAWN } else { piece_type });
+    }
+    fn has_insufficient_material(&self, board: &Board, _color: Color) -> bool {
+        // No material ever leaves the game, so this only happens in
+        // positions set up that way.
+        let b = &board.baseboard;
+        let pockets = &board.pockets;
+        popcount(b.occupied) + pockets[WHITE as usize].len() + pockets[BLACK as usize].len() <= 3
+            && b.promoted == 0
+            && b.pawns == 0
+            && b.rooks == 0
+            && b.queens == 0
+            && [PAWN, ROOK, QUEEN].iter().all(|&piece_type| {
+                pockets[WHITE as usize].count(piece_type) == 0 && pockets[BLACK as usize].count(piece_type) == 0
+            })
+    }
+    fn status(&self, board: &Board) -> u32 {
+        let mut status = board.standard_status();
+        let pockets = &board.pockets;
+        let pawns = popcount(board.baseboard.pawns)
+            + pockets[WHITE as usize].count(PAWN) as u32
+            + pockets[BLACK as usize].count(PAWN) as u32;
+        if pawns <= 16 {
+            status &= !(STATUS_TOO_MANY_WHITE_PAWNS | STATUS_TOO_MANY_BLACK_PAWNS);
+        }
+        if popcount(board.baseboard.occupied) + pockets[WHITE as usize].len() + pockets[BLACK as usize].len() <= 32 {
+            status &= !(STATUS_TOO_MANY_WHITE_PIECES | STATUS_TOO_MANY_BLACK_PIECES);
+        }
+        status
+    }
+}
+pub static CRAZYHOUSE: Crazyhouse = Crazyhouse;
+
+/// Captures explode: the capturing piece and every piece but pawns on the
+/// surrounding squares leave the board. Blowing up the enemy king wins,
+/// and kings may stand next to each other since neither can capture.
+pub struct Atomic;
+impl Atomic {
+    fn kings_connected(board: &Board) -> bool {
+        let b = &board.baseboard;
+        let black_kings = b.kings & b.occupied_co[BLACK as usize];
+        scan_forward(b.kings & b.occupied_co[WHITE as usize]).any(|king| BB_KING_ATTACKS[king as usize] & black_kings != 0)
+    }
+}
+impl Variant for Atomic {
+    fn name(&self) -> &'static str {
+        "Atomic"
+    }
+    fn aliases(&self) -> &'static [&'static str] {
+        &["Atomic", "Atom", "Atomic chess"]
+    }
+    fn uci_variant(&self) -> &'static str {
+        "atomic"
+    }
+    fn generate_legal_moves<'a>(&self, board: &'a Board, from_mask: Bitboard, to_mask: Bitboard) -> Box<dyn Iterator<Item = Move> + 'a> {
+        Box::new(board.generate_pseudo_legal_moves(from_mask, to_mask).filter(move |&m| Atomic.is_legal(board, m)))
+    }
+    fn is_legal(&self, board: &Board, m: Move) -> bool {
+        if board.is_variant_end() || !board.is_pseudo_legal(m) {
+            return false;
+        }
+        let mut after = board.copy(false);
+        after.push(m);
+        after.baseboard.kings != 0 && !after.is_variant_win() && (after.is_variant_loss() || !after.was_into_check())
+    }
+    fn is_check(&self, board: &Board) -> bool {
+        !Atomic::kings_connected(board) && board.is_standard_check()
+    }
+    fn is_into_check(&self, board: &Board, m: Move) -> bool {
+        let mut after = board.copy(false);
+        after.push(m);
+        after.was_into_check()
+    }
+    fn was_into_check(&self, board: &Board) -> bool {
+        !Atomic::kings_connected(board) && board.standard_was_into_check()
+    }
+    fn attacked_for_king(&self, board: &Board, mut path: Bitboard, occupied: Bitboard) -> bool {
+        // Squares next to the enemy king can not be attacked by it.
+        let b = &board.baseboard;
+        for king in scan_forward(b.kings & b.occupied_co[!board.turn as usize]) {
+            path &= !BB_KING_ATTACKS[king as usize];
+        }
+        board.standard_attacked_for_king(path, occupied)
+    }
+    fn push_modifies_board(&self, board: &Board, m: Move) -> bool {
+        board.is_capture(m)
+    }
+    fn push_capture(&self, board: &mut Board, m: Move, _capture_square: Square, _piece_type: PieceType, _was_promoted: bool) {
+        let b = &mut board.baseboard;
+        let explosion_radius = BB_KING_ATTACKS[m.to_square as usize] & !b.pawns;
+
+        board.castling_rights &= !explosion_radius;
+        if explosion_radius & b.kings & b.occupied_co[WHITE as usize] & !b.promoted != 0 {
+            board.castling_rights &= !BB_RANK_1;
+        }
+        if explosion_radius & b.kings & b.occupied_co[BLACK as usize] & !b.promoted != 0 {
+            board.castling_rights &= !BB_RANK_8;
+        }
+
+        b._remove_piece_at(m.to_square);
+        for square in scan_forward(explosion_radius) {
+            b._remove_piece_at(square);
+        }
+    }
+    fn is_variant_end(&self, board: &Board) -> bool {
+        let b = &board.baseboard;
+        b.kings & b.occupied_co[WHITE as usize] == 0 || b.kings & b.occupied_co[BLACK as usize] == 0
+    }
+    fn is_variant_win(&self, board: &Board) -> bool {
+        let b = &board.baseboard;
+        b.kings != 0 && b.kings & b.occupied_co[!board.turn as usize] == 0
+    }
+    fn is_variant_loss(&self, board: &Board) -> bool {
+        let b = &board.baseboard;
+        b.kings != 0 && b.kings & b.occupied_co[board.turn as usize] == 0
+    }
+    fn has_insufficient_material(&self, board: &Board, color: Color) -> bool {
+        let b = &board.baseboard;
+        let ours = b.occupied_co[color as usize];
+        let theirs = b.occupied_co[!color as usize];
+        // Remaining material does not matter once the enemy king exploded.
+        if theirs & b.kings == 0 {
+            return false;
+        }
+        // A bare king can not win.
+        if ours & !b.kings == 0 {
+            return true;
+        }
+        // Any enemy piece may still explode next to the enemy king, unless
+        // only bishops are left that can never meet.
+        if theirs & !b.kings != 0 {
+            if b.occupied == b.bishops | b.kings {
+                let white_bishops = b.bishops & b.occupied_co[WHITE as usize];
+                let black_bishops = b.bishops & b.occupied_co[BLACK as usize];
+                if white_bishops & BB_DARK_SQUARES == 0 {
+                    return black_bishops & BB_LIGHT_SQUARES == 0;
+                }
+                if white_bishops & BB_LIGHT_SQUARES == 0 {
+                    return black_bishops & BB_DARK_SQUARES == 0;
+                }
+            }
+            return false;
+        }
+        // A queen or a future queen can mate a bare king.
+        if b.queens != 0 || b.pawns != 0 {
+            return false;
+        }
+        // A single knight, bishop or rook can not.
+        if popcount(b.knights | b.bishops | b.rooks) == 1 {
+            return true;
+        }
+        // Neither can two knights.
+        if b.occupied == b.knights | b.kings {
+            return popcount(b.knights) <= 2;
+        }
+        false
+    }
+    fn status(&self, board: &Board) -> u32 {
+        let b = &board.baseboard;
+        let mut status = board.standard_status();
+        status &= !STATUS_OPPOSITE_CHECK;
+        // The side not to move may have lost its king.
+        if board.turn == WHITE {
+            if b.occupied_co[WHITE as usize] & b.kings != 0 {
+                status &= !STATUS_NO_BLACK_KING;
+            }
+        } else if b.occupied_co[BLACK as usize] & b.kings != 0 {
+            status &= !STATUS_NO_WHITE_KING;
+        }
+        if popcount(board.checkers_mask()) <= 14 {
+            status &= !STATUS_TOO_MANY_CHECKERS;
+        }
+        status & !STATUS_IMPOSSIBLE_CHECK
+    }
+}
+pub static ATOMIC: Atomic = Atomic;
+
+/// Captures are compulsory and the goal is to lose every piece. The king
+/// is an ordinary piece that can be captured or promoted to, there is no
+/// check and no castling, and a side without legal moves wins as well.
+pub struct Antichess;
+impl Variant for Antichess {
+    fn name(&self) -> &'static str {
+        "Antichess"
+    }
+    fn aliases(&self) -> &'static [&'static str] {
+        &["Antichess", "Anti chess", "Anti"]
+    }
+    fn uci_variant(&self) -> &'static str {
+        "antichess"
+    }
+    fn starting_fen(&self) -> &'static str {
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1"
+    }
+    fn generate_pseudo_legal_moves<'a>(&self, board: &'a Board, from_mask: Bitboard, to_mask: Bitboard) -> Box<dyn Iterator<Item = Move> + 'a> {
+        Box::new(board.generate_standard_pseudo_legal_moves(from_mask, to_mask)
+            .filter(move |&m| !board.is_castling(m))
+            .flat_map(|m| {
+                let king_promotion = Move { promotion: Some(KING), ..m };
+                std::iter::once(m).chain((m.promotion == Some(QUEEN)).then_some(king_promotion))
+            }))
+    }
+    fn generate_legal_moves<'a>(&self, board: &'a Board, from_mask: Bitboard, to_mask: Bitboard) -> Box<dyn Iterator<Item = Move> + 'a> {
+        if board.is_variant_end() {
+            return Box::new(std::iter::empty());
+        }
+        // Captures are compulsory, wherever they are on the board.
+        if board.generate_pseudolegal_captures(BB_ALL, BB_ALL).next().is_some() {
+            return Box::new(board.generate_pseudolegal_captures(from_mask, to_mask));
+        }
+        let not_them = to_mask & !board.baseboard.occupied_co[!board.turn as usize];
+        Box::new(board.generate_pseudo_legal_moves(from_mask, not_them))
+    }
+    fn is_legal(&self, board: &Board, m: Move) -> bool {
+        board.is_standard_legal(m)
+            && (board.is_capture(m) || board.generate_pseudolegal_captures(BB_ALL, BB_ALL).next().is_none())
+    }
+    fn is_check(&self, _board: &Board) -> bool {
+        false
+    }
+    fn is_into_check(&self, _board: &Board, _m: Move) -> bool {
+        false
+    }
+    fn was_into_check(&self, _board: &Board) -> bool {
+        false
+    }
+    fn is_variant_end(&self, board: &Board) -> bool {
+        let b = &board.baseboard;
+        b.occupied_co[WHITE as usize] == 0 || b.occupied_co[BLACK as usize] == 0
+    }
+    fn is_variant_win(&self, board: &Board) -> bool {
+        board.baseboard.occupied_co[board.turn as usize] == 0
+            || (!board.is_variant_end() && board.generate_legal_moves(BB_ALL, BB_ALL).next().is_none())
+    }
+    fn is_variant_loss(&self, board: &Board) -> bool {
+        let b = &board.baseboard;
+        b.occupied_co[board.turn as usize] != 0 && b.occupied_co[!board.turn as usize] == 0
+    }
+    fn has_insufficient_material(&self, board: &Board, color: Color) -> bool {
+        let b = &board.baseboard;
+        let ours = b.occupied_co[color as usize];
+        let theirs = b.occupied_co[!color as usize];
+        if ours == 0 {
+            return false;
+        }
+        if theirs == 0 {
+            return true;
+        }
+        if b.occupied == b.bishops {
+            // Our bishops can only be captured if some stand on a square
+            // colour the enemy bishops can reach.
+            let they_all_on_dark = theirs & BB_LIGHT_SQUARES == 0;
+            let they_all_on_light = theirs & BB_DARK_SQUARES == 0;
+            return (ours & BB_LIGHT_SQUARES != 0 && they_all_on_dark) || (ours & BB_DARK_SQUARES != 0 && they_all_on_light);
+        }
+        if b.occupied == b.knights && popcount(b.knights) == 2 {
+            // A knight changes square colour with every move, so only one
+            // of two lone knights can ever capture the other.
+            let white_on_light = b.occupied_co[WHITE as usize] & BB_LIGHT_SQUARES != 0;
+            let black_on_dark = b.occupied_co[BLACK as usize] & BB_DARK_SQUARES != 0;
+            return board.turn == (color ^ white_on_light ^ black_on_dark);
+        }
+        false
+    }
+    fn status(&self, board: &Board) -> u32 {
+        let mut status = board.standard_status();
+        status &= !(STATUS_NO_WHITE_KING | STATUS_NO_BLACK_KING | STATUS_TOO_MANY_KINGS | STATUS_OPPOSITE_CHECK);
+        status &= !(STATUS_TOO_MANY_CHECKERS | STATUS_IMPOSSIBLE_CHECK);
+        if board.castling_rights != 0 {
+            status |= STATUS_BAD_CASTLING_RIGHTS;
+        }
+        status
+    }
+}
+pub static ANTICHESS: Antichess = Antichess;
+
+/// Both kings race to the eighth rank from a start position without pawns,
+/// and no move may give check. Black still gets to reply after white
+/// arrives, and the game is drawn if black arrives on that move too.
+pub struct RacingKings;
+impl Variant for RacingKings {
+    fn name(&self) -> &'static str {
+        "Racing Kings"
+    }
+    fn aliases(&self) -> &'static [&'static str] {
+        &["Racing Kings", "Racing", "Race", "racingkings"]
+    }
+    fn uci_variant(&self) -> &'static str {
+        "racingkings"
+    }
+    fn starting_fen(&self) -> &'static str {
+        "8/8/8/8/8/8/krbnNBRK/qrbnNBRQ w - - 0 1"
+    }
+    fn has_symmetric_rules(&self) -> bool {
+        false
+    }
+    fn generate_legal_moves<'a>(&self, board: &'a Board, from_mask: Bitboard, to_mask: Bitboard) -> Box<dyn Iterator<Item = Move> + 'a> {
+        let mut after = board.copy(false);
+        Box::new(board.generate_standard_legal_moves(from_mask, to_mask).filter(move |&m| !after.gives_check(m)))
+    }
+    fn is_legal(&self, board: &Board, m: Move) -> bool {
+        board.is_standard_legal(m) && !board.copy(false).gives_check(m)
+    }
+    fn is_variant_end(&self, board: &Board) -> bool {
+        let b = &board.baseboard;
+        if b.kings & BB_RANK_8 == 0 {
+            return false;
+        }
+        let black_kings = b.kings & b.occupied_co[BLACK as usize];
+        if board.turn == WHITE || black_kings & BB_RANK_8 != 0 || black_kings == 0 {
+            return true;
+        }
+        // White arrived. Black may still draw if its king has a safe square
+        // on the eighth rank.
+        let targets = BB_KING_ATTACKS[msb(black_kings) as usize] & BB_RANK_8 & !b.occupied_co[BLACK as usize];
+        scan_forward(targets).all(|target| b.attackers_mask(WHITE, target) != 0)
+    }
+    fn is_variant_draw(&self, board: &Board) -> bool {
+        let b = &board.baseboard;
+        let in_goal = b.kings & BB_RANK_8;
+        in_goal & b.occupied_co[WHITE as usize] != 0 && in_goal & b.occupied_co[BLACK as usize] != 0
+    }
+    fn is_variant_loss(&self, board: &Board) -> bool {
+        let b = &board.baseboard;
+        board.is_variant_end() && b.kings & b.occupied_co[board.turn as usize] & BB_RANK_8 == 0
+    }
+    fn is_variant_win(&self, board: &Board) -> bool {
+        let b = &board.baseboard;
+        let in_goal = b.kings & BB_RANK_8;
+        board.is_variant_end()
+            && in_goal & b.occupied_co[board.turn as usize] != 0
+            && in_goal & b.occupied_co[!board.turn as usize] == 0
+    }
+    fn has_insufficient_material(&self, _board: &Board, _color: Color) -> bool {
+        false
+    }
+    fn status(&self, board: &Board) -> u32 {
+        let b = &board.baseboard;
+        let mut status = board.standard_status();
+        if board.is_check() {
+            status |= STATUS_RACE_CHECK;
+        }
+        if board.turn == BLACK && self.is_variant_draw(board) {
+            status |= STATUS_RACE_OVER;
+        }
+        // Only the pieces of the start position may be on the board.
+        if b.pawns != 0 {
+            status |= STATUS_RACE_MATERIAL;
+        }
+        for color in [WHITE, BLACK] {
+            let ours = b.occupied_co[color as usize];
+            if popcount(ours & b.knights) > 2
+                || popcount(ours & b.bishops) > 2
+                || popcount(ours & b.rooks) > 2
+                || popcount(ours & b.queens) > 1
+                || popcount(ours & b.kings) > 1
+            {
+                status |= STATUS_RACE_MATERIAL;
+            }
+        }
+        status
+    }
+}
+pub static RACING_KINGS: RacingKings = RacingKings;
+
+/// White has a horde of 36 pawns and no king, and loses once every piece
+/// is captured. Pawns on the first rank may advance two squares.
+pub struct Horde;
+impl Variant for Horde {
+    fn name(&self) -> &'static str {
+        "Horde"
+    }
+    fn aliases(&self) -> &'static [&'static str] {
+        &["Horde", "Horde chess"]
+    }
+    fn uci_variant(&self) -> &'static str {
+        "horde"
+    }
+    fn starting_fen(&self) -> &'static str {
+        "rnbqkbnr/pppppppp/8/1PP2PP1/PPPPPPPP/PPPPPPPP/PPPPPPPP/PPPPPPPP w kq - 0 1"
+    }
+    fn has_symmetric_rules(&self) -> bool {
+        false
+    }
+    fn is_variant_end(&self, board: &Board) -> bool {
+        let b = &board.baseboard;
+        b.occupied_co[WHITE as usize] == 0 || b.occupied_co[BLACK as usize] == 0
+    }
+    fn is_variant_draw(&self, board: &Board) -> bool {
+        board.baseboard.occupied == 0
+    }
+    fn is_variant_loss(&self, board: &Board) -> bool {
+        let b = &board.baseboard;
+        b.occupied != 0 && b.occupied_co[board.turn as usize] == 0
+    }
+    fn is_variant_win(&self, board: &Board) -> bool {
+        let b = &board.baseboard;
+        b.occupied != 0 && b.occupied_co[!board.turn as usize] == 0
+    }
+    fn has_insufficient_material(&self, board: &Board, color: Color) -> bool {
+        // Black can always win by capturing the horde. The horde is only
+        // certain to fail with a single knight or bishop against a bare king.
+        let b = &board.baseboard;
+        let white = b.occupied_co[WHITE as usize];
+        color == WHITE
+            && (white == 0
+                || (popcount(white) == 1
+                    && white & (b.knights | b.bishops) != 0
+                    && b.occupied_co[BLACK as usize] & !b.kings == 0))
+    }
+    fn status(&self, board: &Board) -> u32 {
+        let b = &board.baseboard;
+        let white = b.occupied_co[WHITE as usize];
+        let mut status = board.standard_status();
+        status &= !STATUS_NO_WHITE_KING;
+        if popcount(white) <= 36 {
+            status &= !(STATUS_TOO_MANY_WHITE_PIECES | STATUS_TOO_MANY_WHITE_PAWNS);
+        }
+        if b.pawns & BB_RANK_8 == 0 && b.pawns & b.occupied_co[BLACK as usize] & BB_RANK_1 == 0 {
+            status &= !STATUS_PAWNS_ON_BACKRANK;
+        }
+        if white & b.kings != 0 {
+            status |= STATUS_TOO_MANY_KINGS;
+        }
+        status
+    }
+}
+pub static HORDE: Horde = Horde;
+
+/// Giving check for the third time wins.
+pub struct ThreeCheck;
+impl Variant for ThreeCheck {
+    fn name(&self) -> &'static str {
+        "Three-check"
+    }
+    fn aliases(&self) -> &'static [&'static str] {
+        &["Three-check", "Three check", "Threecheck", "Three check chess", "3-check", "3 check", "3check"]
+    }
+    fn uci_variant(&self) -> &'static str {
+        "3check"
+    }
+    fn starting_fen(&self) -> &'static str {
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 3+3 0 1"
+    }
+    fn has_check_counts(&self) -> bool {
+        true
+    }
+    fn is_variant_end(&self, board: &Board) -> bool {
+        board.remaining_checks.contains(&0)
+    }
+    fn is_variant_draw(&self, board: &Board) -> bool {
+        board.remaining_checks == [0, 0]
+    }
+    fn is_variant_loss(&self, board: &Board) -> bool {
+        board.remaining_checks[!board.turn as usize] == 0 && board.remaining_checks[board.turn as usize] > 0
+    }
+    fn is_variant_win(&self, board: &Board) -> bool {
+        board.remaining_checks[board.turn as usize] == 0 && board.remaining_checks[!board.turn as usize] > 0
+    }
+    fn has_insufficient_material(&self, board: &Board, color: Color) -> bool {
+        // Any piece besides the king can still give check.
+        let b = &board.baseboard;
+        b.occupied_co[color as usize] & !b.kings == 0
+    }
+}
+pub static THREE_CHECK: ThreeCheck = ThreeCheck;
+
+/// Bringing the king to one of the four center squares wins.
+pub struct KingOfTheHill;
+impl Variant for KingOfTheHill {
+    fn name(&self) -> &'static str {
+        "King of the Hill"
+    }
+    fn aliases(&self) -> &'static [&'static str] {
+        &["King of the Hill", "KOTH", "kingOfTheHill"]
+    }
+    fn uci_variant(&self) -> &'static str {
+        "kingofthehill"
+    }
+    fn is_variant_end(&self, board: &Board) -> bool {
+        board.baseboard.kings & BB_CENTER != 0
+    }
+    fn is_variant_win(&self, board: &Board) -> bool {
+        let b = &board.baseboard;
+        b.kings & b.occupied_co[board.turn as usize] & BB_CENTER != 0
+    }
+    fn is_variant_loss(&self, board: &Board) -> bool {
+        let b = &board.baseboard;
+        b.kings & b.occupied_co[!board.turn as usize] & BB_CENTER != 0
+    }
+    fn has_insufficient_material(&self, _board: &Board, _color: Color) -> bool {
+        false
+    }
+}
+pub static KING_OF_THE_HILL: KingOfTheHill = KingOfTheHill;
+
+/// Crazyhouse on one of the two boards of a `BughouseGame`. Captured pieces
+/// go to the partner on the other board, so the pockets only change from
+/// outside and any material may still arrive.
+pub struct Bughouse;
+impl Variant for Bughouse {
+    fn name(&self) -> &'static str {
+        "Bughouse"
+    }
+    fn aliases(&self) -> &'static [&'static str] {
+        &["Bughouse", "Bughouse chess"]
+    }
+    fn uci_variant(&self) -> &'static str {
+        "bughouse"
+    }
+    fn starting_fen(&self) -> &'static str {
+        CRAZYHOUSE.starting_fen()
+    }
+    fn has_pockets(&self) -> bool {
+        true
+    }
+    fn generate_pseudo_legal_moves<'a>(&self, board: &'a Board, from_mask: Bitboard, to_mask: Bitboard) -> Box<dyn Iterator<Item = Move> + 'a> {
+        CRAZYHOUSE.generate_pseudo_legal_moves(board, from_mask, to_mask)
+    }
+    fn generate_legal_moves<'a>(&self, board: &'a Board, from_mask: Bitboard, to_mask: Bitboard) -> Box<dyn Iterator<Item = Move> + 'a> {
+        CRAZYHOUSE.generate_legal_moves(board, from_mask, to_mask)
+    }
+    fn is_pseudo_legal(&self, board: &Board, m: Move) -> bool {
+        CRAZYHOUSE.is_pseudo_legal(board, m)
+    }
+    fn is_legal(&self, board: &Board, m: Move) -> bool {
+        CRAZYHOUSE.is_legal(board, m)
+    }
+    fn has_insufficient_material(&self, _board: &Board, _color: Color) -> bool {
+        false
+    }
+    fn status(&self, board: &Board) -> u32 {
+        // Each side may hold its own pieces and those its partner captured.
+        let b = &board.baseboard;
+        let mut status = board.standard_status();
+        for color in [WHITE, BLACK] {
+            let ours = b.occupied_co[color as usize];
+            let pocket = &board.pockets[color as usize];
+            if popcount(ours & b.pawns) + pocket.count(PAWN) as u32 <= 16 {
+                status &= !(if color == WHITE { STATUS_TOO_MANY_WHITE_PAWNS } else { STATUS_TOO_MANY_BLACK_PAWNS });
+            }
+            if popcount(ours) + pocket.len() <= 32 {
+                status &= !(if color == WHITE { STATUS_TOO_MANY_WHITE_PIECES } else { STATUS_TOO_MANY_BLACK_PIECES });
+            }
+        }
+        status
+    }
+}
+pub static BUGHOUSE: Bughouse = Bughouse;
+
+pub static VARIANTS: [&'static dyn Variant; 9] = [
+    &STANDARD, &CRAZYHOUSE, &ATOMIC, &ANTICHESS, &RACING_KINGS, &HORDE, &THREE_CHECK, &KING_OF_THE_HILL, &BUGHOUSE,
+];
+
+/// Looks up a variant by its name or one of its aliases, ignoring case.
+pub fn find_variant(name: &str) -> Option<&'static dyn Variant> {
+    VARIANTS.iter().copied().find(|variant| {
+        variant.name().eq_ignore_ascii_case(name)
+            || variant.uci_variant().eq_ignore_ascii_case(name)
+            || variant.aliases().iter().any(|alias| alias.eq_ignore_ascii_case(name))
+    })
+}
+
+/// Pieces in hand of one side in Crazyhouse, counted by piece type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct CrazyhousePocket {
+    pieces: [u8; 7],
+}
+impl CrazyhousePocket {
+    pub fn new() -> CrazyhousePocket {
+        CrazyhousePocket::default()
+    }
+    pub fn add(&mut self, piece_type: PieceType) {
+        self.pieces[piece_type as usize] += 1;
+    }
+    /// Takes a piece out of the pocket. Moves are checked for legality
+    /// before they get here, so an empty slot is a bug and only asserted in
+    /// debug builds.
+    pub fn remove(&mut self, piece_type: PieceType) {
+        debug_assert!(self.pieces[piece_type as usize] > 0, "{} not in pocket", piece_symbol(piece_type).unwrap());
+        self.pieces[piece_type as usize] = self.pieces[piece_type as usize].saturating_sub(1);
+    }
+    pub fn count(&self, piece_type: PieceType) -> u8 {
+        self.pieces[piece_type as usize]
+    }
+    pub fn len(&self) -> u32 {
+        self.pieces.iter().map(|&count| count as u32).sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Lowercase piece symbols, most valuable first, e.g. `qnpp`.
+    pub fn fen(&self) -> String {
+        PIECE_TYPES
+            .iter()
+            .rev()
+            .flat_map(|&piece_type| std::iter::repeat_n(piece_symbol(piece_type).unwrap(), self.count(piece_type) as usize))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::init::{Outcome, Termination};
+
+    fn assert_perft(variant: &'static dyn Variant, fen: &str, counts: &[u64]) {
+        let mut board = Board::from_variant_fen(variant, fen).unwrap();
+        for (depth, nodes) in counts.iter().enumerate() {
+            assert_eq!(board.perft(depth as u32 + 1), *nodes, "{} {} depth {}", variant.name(), fen, depth + 1);
+        }
+    }
+
+    fn outcome(variant: &'static dyn Variant, fen: &str) -> Option<Outcome> {
+        Board::from_variant_fen(variant, fen).unwrap().outcome(false)
+    }
+
+    #[test]
+    fn crazyhouse_perft() {
+        assert_perft(&CRAZYHOUSE, CRAZYHOUSE.starting_fen(), &[20, 400, 8902, 197281, 4888832]);
+    }
+
+    #[test]
+    fn crazyhouse_perft_with_pockets_and_promoted_pieces() {
+        assert_perft(&CRAZYHOUSE, "2k5/8/8/8/8/8/8/4K3[QRBNPqrbnp] w - - 0 1", &[301, 75353]);
+        // Taking the promoted queen on b7 puts a pawn in Black's pocket, not a queen.
+        assert_perft(&CRAZYHOUSE, "4k3/1Q~6/8/8/4b3/8/Kpp5/8/ b - - 0 1", &[20, 360, 5445]);
+        assert_perft(&CRAZYHOUSE, "4k3/1Q6/8/8/4b3/8/Kpp5/8/ b - - 0 1", &[20, 360, 5490]);
+        assert_perft(&CRAZYHOUSE, "4k3/1Q~6/8/8/4b3/8/Kpp5/8[Rn] b - - 0 1", &[78, 5607, 159798]);
+    }
+
+    #[test]
+    fn crazyhouse_captures_fill_the_pocket() {
+        let mut board = Board::from_variant(&CRAZYHOUSE);
+        for san in ["e4", "d5", "exd5"] {
+            board.push_san(san).unwrap();
+        }
+        assert_eq!(board.pockets[WHITE as usize].count(PAWN), 1);
+        assert!(board.fen(false).contains("[P]"));
+        board.push_san("Qxd5").unwrap();
+        assert_eq!(board.pockets[BLACK as usize].count(PAWN), 1);
+        board.push_san("P@e6").unwrap();
+        assert_eq!(board.pockets[WHITE as usize].count(PAWN), 0);
+        board.pop();
+        assert_eq!(board.pockets[WHITE as usize].count(PAWN), 1);
+
+        // A promoted piece goes back to the pocket as a pawn.
+        let mut board = Board::from_variant_fen(&CRAZYHOUSE, "4k3/8/8/8/8/8/8/Rq~2K3[] w - - 0 1").unwrap();
+        board.push_san("Rxb1").unwrap();
+        assert_eq!(board.pockets[WHITE as usize].fen(), "p");
+    }
+
+    #[test]
+    fn crazyhouse_drops_can_block_mate() {
+        let mated = Outcome { termination: Termination::Checkmate, winner: Some(WHITE) };
+        assert_eq!(outcome(&CRAZYHOUSE, "k6R/8/1K6/8/8/8/8/8[] b - - 0 1"), Some(mated));
+        assert_eq!(outcome(&CRAZYHOUSE, "k6R/8/1K6/8/8/8/8/8[n] b - - 0 1"), None);
+        let board = Board::from_variant_fen(&CRAZYHOUSE, "k6R/8/1K6/8/8/8/8/8[n] b - - 0 1").unwrap();
+        let moves = board.generate_legal_moves(BB_ALL, BB_ALL).map(|m| m.uci()).collect::<Vec<_>>();
+        assert_eq!(moves.len(), 6);
+        assert!(moves.iter().all(|uci| uci.starts_with("N@")));
+    }
+
+    #[test]
+    fn atomic_perft() {
+        assert_perft(&ATOMIC, STARTING_FEN, &[20, 400, 8902, 197326]);
+    }
+
+    #[test]
+    fn atomic_explosions() {
+        let mut board = Board::from_variant_fen(&ATOMIC, "4k3/5p2/8/6N1/8/8/8/4K3 w - - 0 1").unwrap();
+        board.push_san("Nxf7").unwrap();
+        assert_eq!(board.baseboard.occupied, BB_SQUARES[4]);
+        assert_eq!(board.outcome(false), Some(Outcome { termination: Termination::VariantLoss, winner: Some(WHITE) }));
+        board.pop();
+        assert_eq!(board.fen(false), "4k3/5p2/8/6N1/8/8/8/4K3 w - - 0 1");
+
+        // Kings never capture, since they would explode themselves.
+        let board = Board::from_variant_fen(&ATOMIC, "4k3/8/8/8/8/8/4p3/4K3 w - - 0 1").unwrap();
+        assert!(board.generate_legal_moves(BB_ALL, BB_ALL).all(|m| m.uci() != "e1e2"));
+        assert_eq!(outcome(&ATOMIC, "4k3/8/8/8/8/8/4p3/4K3 w - - 0 1"), None);
+    }
+
+    #[test]
+    fn antichess_perft() {
+        assert_perft(&ANTICHESS, ANTICHESS.starting_fen(), &[20, 400, 8067, 153299]);
+    }
+
+    #[test]
+    fn antichess_captures_are_forced_and_losing_everything_wins() {
+        let board = Board::from_variant_fen(&ANTICHESS, "8/8/8/8/3p4/4P3/8/K7 w - - 0 1").unwrap();
+        let moves = board.generate_legal_moves(BB_ALL, BB_ALL).map(|m| m.uci()).collect::<Vec<_>>();
+        assert_eq!(moves, ["e3d4"]);
+
+        assert_eq!(
+            outcome(&ANTICHESS, "8/8/8/8/8/8/p7/8 w - - 0 1"),
+            Some(Outcome { termination: Termination::VariantWin, winner: Some(WHITE) })
+        );
+        // Being stalemated wins as well.
+        assert_eq!(
+            outcome(&ANTICHESS, "8/8/8/8/8/p7/P7/8 w - - 0 1"),
+            Some(Outcome { termination: Termination::VariantWin, winner: Some(WHITE) })
+        );
+    }
+
+    #[test]
+    fn racing_kings_perft() {
+        assert_perft(&RACING_KINGS, RACING_KINGS.starting_fen(), &[21, 421, 11264, 296242]);
+    }
+
+    #[test]
+    fn racing_kings_outcomes() {
+        assert_eq!(
+            outcome(&RACING_KINGS, "7K/8/8/8/8/8/k7/8 b - - 0 1"),
+            Some(Outcome { termination: Termination::VariantLoss, winner: Some(WHITE) })
+        );
+        // Black still gets a move to draw by reaching the backrank as well.
+        assert_eq!(outcome(&RACING_KINGS, "7K/k7/8/8/8/8/8/8 b - - 0 1"), None);
+        assert_eq!(
+            outcome(&RACING_KINGS, "k6K/8/8/8/8/8/8/8 w - - 0 1"),
+            Some(Outcome { termination: Termination::VariantDraw, winner: None })
+        );
+        assert_eq!(
+            outcome(&RACING_KINGS, "k7/8/8/8/8/8/8/7K w - - 0 1"),
+            Some(Outcome { termination: Termination::VariantLoss, winner: Some(BLACK) })
+        );
+    }
+
+    #[test]
+    fn horde_perft() {
+        assert_perft(&HORDE, HORDE.starting_fen(), &[8, 128, 1274, 23310, 265223]);
+    }
+
+    #[test]
+    fn horde_rules_and_outcomes() {
+        // Pawns on the first rank may advance two squares as well.
+        let board = Board::from_variant_fen(&HORDE, "4k3/8/8/8/8/8/8/P7 w - - 0 1").unwrap();
+        let mut moves = board.generate_legal_moves(BB_ALL, BB_ALL).map(|m| m.uci()).collect::<Vec<_>>();
+        moves.sort();
+        assert_eq!(moves, ["a1a2", "a1a3"]);
+
+        assert_eq!(
+            outcome(&HORDE, "4k3/8/8/8/8/8/8/8 w - - 0 1"),
+            Some(Outcome { termination: Termination::VariantLoss, winner: Some(BLACK) })
+        );
+        assert_eq!(
+            outcome(&HORDE, "3QkQ2/3PPP2/8/8/8/8/8/8 b - - 0 1"),
+            Some(Outcome { termination: Termination::Checkmate, winner: Some(WHITE) })
+        );
+        assert_eq!(outcome(&HORDE, HORDE.starting_fen()), None);
+    }
+
+    #[test]
+    fn three_check_and_king_of_the_hill_perft() {
+        assert_perft(&THREE_CHECK, THREE_CHECK.starting_fen(), &[20, 400, 8902, 197281]);
+        assert_perft(&KING_OF_THE_HILL, STARTING_FEN, &[20, 400, 8902, 197281]);
+    }
+
+    #[test]
+    fn three_check_fen_forms_round_trip() {
+        let remaining = "rnbqkbnr/ppp2ppp/8/3pp3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 2+3 0 3";
+        let given = "rnbqkbnr/ppp2ppp/8/3pp3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 3 +1+0";
+        let a = Board::from_variant_fen(&THREE_CHECK, remaining).unwrap();
+        let b = Board::from_variant_fen(&THREE_CHECK, given).unwrap();
+        assert_eq!(a.remaining_checks, [3, 2]);
+        assert_eq!(a.remaining_checks, b.remaining_checks);
+        assert_eq!(a.fen(false), remaining);
+        assert_eq!(b.fen(false), remaining);
+        assert_eq!(a.zobrist_hash(), b.zobrist_hash());
+    }
+
+    #[test]
+    fn three_check_and_king_of_the_hill_outcomes() {
+        let mut board = Board::from_variant_fen(&THREE_CHECK, "4k3/8/8/8/8/8/8/4KQ2 w - - 1+3 0 1").unwrap();
+        board.push_san("Qe2+").unwrap();
+        assert_eq!(board.remaining_checks[WHITE as usize], 0);
+        assert_eq!(board.outcome(false), Some(Outcome { termination: Termination::VariantLoss, winner: Some(WHITE) }));
+        board.pop();
+        assert_eq!(board.remaining_checks[WHITE as usize], 1);
+        assert_eq!(board.outcome(false), None);
+
+        let mut board = Board::from_variant_fen(&KING_OF_THE_HILL, "4k3/8/8/8/8/3K4/8/8 w - - 0 1").unwrap();
+        assert_eq!(board.outcome(false), None);
+        board.push_san("Kd4").unwrap();
+        assert_eq!(board.outcome(false), Some(Outcome { termination: Termination::VariantLoss, winner: Some(WHITE) }));
+    }
+}